@@ -1,2 +1,4 @@
+pub mod icon_atlas;
+pub mod ktx;
 pub mod material;
 pub mod texture;
@@ -0,0 +1,133 @@
+use crate::asset::material::{Material, MaterialId, MaterialManager, PbrMaterial, RawMaterial};
+use crate::asset::texture::{Texture, TextureId, TextureKind, TextureManager};
+use crate::resource::immediate_submit::SubmitContext;
+use crate::scene::billboard::Billboard;
+use ash::vk;
+use glam::{Vec2, Vec4};
+use hashbrown::HashMap;
+use serde::Deserialize;
+use std::path::Path;
+
+// The layout of the JSON file written by build.rs
+#[derive(Deserialize)]
+struct AtlasFile {
+    atlases: Vec<String>,
+    entries: std::collections::HashMap<String, AtlasEntry>,
+}
+
+#[derive(Deserialize)]
+struct AtlasEntry {
+    atlas_index: usize,
+    location: (u32, u32),
+    size: (u32, u32),
+}
+
+/// An icon's place in one of the atlas pages.
+#[derive(Debug, Copy, Clone)]
+pub struct Icon {
+    pub texture: TextureId,
+    pub material: MaterialId, // unlit-looking material showing the page, for billboards
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+}
+
+impl Icon {
+    /// UVs in the order billboard.vert expects them: bottom left, bottom right, top left, top right.
+    pub fn uvs(&self) -> [Vec2; 4] {
+        [
+            Vec2::new(self.uv_min.x, self.uv_max.y),
+            self.uv_max,
+            self.uv_min,
+            Vec2::new(self.uv_max.x, self.uv_min.y),
+        ]
+    }
+}
+
+/// The editor icons baked from `assets/icons` by build.rs, looked up by their file name without extension.
+#[derive(Default)]
+pub struct IconAtlas {
+    icons: HashMap<String, Icon>,
+}
+
+impl IconAtlas {
+    pub const PATH: &'static str = "assets/icons/atlas/atlas.json";
+    pub const LIGHT: &'static str = "light";
+
+    /// Uploads the atlas pages and creates a material for each of them.
+    pub fn load(
+        path: &Path,
+        ctx: &mut SubmitContext,
+        texture_manager: &mut TextureManager,
+        material_manager: &mut MaterialManager,
+    ) -> Result<Self, String> {
+        let file = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        let atlas: AtlasFile = serde_json::from_str(&file).map_err(|e| format!("Failed to parse {:?}: {}", path, e))?;
+
+        let mut pages = vec![];
+        for (i, page_path) in atlas.atlases.iter().enumerate() {
+            let page = image::open(page_path)
+                .map_err(|e| format!("Failed to load atlas page {}: {}", page_path, e))?
+                .to_rgba8();
+            let extent = vk::Extent3D {
+                width: page.width(),
+                height: page.height(),
+                depth: 1,
+            };
+            // no mips, they would bleed neighbouring icons into each other
+            let texture = ctx.nest(Box::new(|ctx| {
+                Texture::new_init_levels(
+                    TextureManager::DEFAULT_SAMPLER_LINEAR,
+                    vk::Format::R8G8B8A8_SRGB,
+                    ctx,
+                    Some(format!("icon_atlas_{}", i)),
+                    &[page.as_raw()],
+                    extent,
+                    TextureKind::Color,
+                    false,
+                )
+            }));
+            let texture = texture_manager.add_texture(texture, &ctx.device, true);
+            let material = ctx.nest(Box::new(|ctx| {
+                Material::new(
+                    Some(format!("Icon atlas {}", i)),
+                    RawMaterial::Pbr(PbrMaterial {
+                        albedo_tex: texture,
+                        metallic_roughness_tex: TextureManager::DEFAULT_TEXTURE_WHITE,
+                        ..Default::default()
+                    }),
+                    ctx,
+                )
+            }));
+            let material = material_manager.add_material(material);
+            pages.push((texture, material, Vec2::new(extent.width as f32, extent.height as f32)));
+        }
+
+        let icons = atlas
+            .entries
+            .into_iter()
+            .filter_map(|(name, entry)| {
+                let (texture, material, page_size) = *pages.get(entry.atlas_index)?;
+                let location = Vec2::new(entry.location.0 as f32, entry.location.1 as f32);
+                let size = Vec2::new(entry.size.0 as f32, entry.size.1 as f32);
+                let icon = Icon {
+                    texture,
+                    material,
+                    uv_min: location / page_size,
+                    uv_max: (location + size) / page_size,
+                };
+                Some((name, icon))
+            })
+            .collect();
+        Ok(Self { icons })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Icon> {
+        self.icons.get(name)
+    }
+
+    /// A billboard showing the icon, if the atlas has it.
+    pub fn billboard(&self, name: &str, size: Vec2) -> Option<Billboard> {
+        let icon = self.get(name)?;
+        Some(Billboard::new(Vec4::ZERO, size, icon.uvs(), icon.material))
+    }
+}
@@ -0,0 +1,224 @@
+use ash::{vk, Instance};
+use basis_universal::{DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat};
+use hashbrown::HashSet;
+use ktx2::{BasicDataFormatDescriptor, ColorModel, DataFormatDescriptorHeader, Reader, SupercompressionScheme, TransferFunction};
+use log::info;
+use std::fmt::{Display, Formatter};
+use std::io::{Cursor, Read};
+use std::sync::Once;
+
+// KHR_DF_CHANNEL_UASTC_RGBA, the only UASTC channel layout that carries alpha
+const UASTC_CHANNEL_RGBA: u8 = 3;
+const UASTC_BLOCK_BYTES: u32 = 16;
+
+/// The format Basis Universal (UASTC) textures are transcoded to. Picked once at startup from what the device can sample.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TranscodeTarget {
+    Bc7,
+    Bc3,
+    Rgba8, // uncompressed fallback
+}
+
+impl TranscodeTarget {
+    pub(crate) fn pick(instance: &Instance, device: vk::PhysicalDevice) -> Self {
+        let features = unsafe { instance.get_physical_device_features(device) };
+        let sampleable = |format| {
+            let props = unsafe { instance.get_physical_device_format_properties(device, format) };
+            props.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+        };
+        let target = if features.texture_compression_bc != vk::TRUE {
+            Self::Rgba8
+        } else if sampleable(vk::Format::BC7_SRGB_BLOCK) {
+            Self::Bc7
+        } else if sampleable(vk::Format::BC3_SRGB_BLOCK) {
+            Self::Bc3
+        } else {
+            Self::Rgba8
+        };
+        info!("Transcoding Basis Universal textures to {:?}", target);
+        target
+    }
+
+    pub fn format(self, srgb: bool) -> vk::Format {
+        match (self, srgb) {
+            (Self::Bc7, true) => vk::Format::BC7_SRGB_BLOCK,
+            (Self::Bc7, false) => vk::Format::BC7_UNORM_BLOCK,
+            (Self::Bc3, true) => vk::Format::BC3_SRGB_BLOCK,
+            (Self::Bc3, false) => vk::Format::BC3_UNORM_BLOCK,
+            (Self::Rgba8, true) => vk::Format::R8G8B8A8_SRGB,
+            (Self::Rgba8, false) => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+
+    fn block_format(self) -> TranscoderBlockFormat {
+        match self {
+            Self::Bc7 => TranscoderBlockFormat::BC7,
+            Self::Bc3 => TranscoderBlockFormat::BC3,
+            Self::Rgba8 => TranscoderBlockFormat::RGBA32,
+        }
+    }
+}
+
+/// What the device can do with KTX2 textures: the transcode target for UASTC data and the set of core formats it can sample,
+/// which textures that already carry a Vulkan format are checked against.
+#[derive(Debug, Clone)]
+pub struct FormatSupport {
+    pub transcode_target: TranscodeTarget,
+    sampleable: HashSet<vk::Format>,
+}
+
+impl FormatSupport {
+    pub(crate) fn query(instance: &Instance, device: vk::PhysicalDevice) -> Self {
+        let bc_enabled = unsafe { instance.get_physical_device_features(device) }.texture_compression_bc == vk::TRUE;
+        // the core formats end with the ASTC blocks, extension formats are never passed through
+        let sampleable = (1..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw())
+            .map(vk::Format::from_raw)
+            .filter(|&format| bc_enabled || !is_bc_format(format))
+            .filter(|&format| {
+                let props = unsafe { instance.get_physical_device_format_properties(device, format) };
+                props.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+            })
+            .collect();
+        Self {
+            transcode_target: TranscodeTarget::pick(instance, device),
+            sampleable,
+        }
+    }
+
+    pub fn can_sample(&self, format: vk::Format) -> bool {
+        self.sampleable.contains(&format)
+    }
+}
+
+// BC formats need the textureCompressionBC feature even if the format properties claim otherwise
+fn is_bc_format(format: vk::Format) -> bool {
+    (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::BC7_SRGB_BLOCK.as_raw()).contains(&format.as_raw())
+}
+
+/// A decoded KTX2 file, ready to be uploaded with [`crate::asset::texture::Texture::new_init_levels`].
+pub struct KtxImage {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl KtxImage {
+    pub fn levels(&self) -> Vec<&[u8]> {
+        self.levels.iter().map(|level| level.as_slice()).collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum KtxError {
+    Parse(ktx2::ParseError),
+    Zstd(String),
+    Transcode(String),
+    Unsupported(&'static str),
+    UnsupportedFormat(vk::Format),
+}
+
+impl Display for KtxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KtxError::Parse(e) => write!(f, "invalid KTX2 file: {}", e),
+            KtxError::Zstd(e) => write!(f, "failed to inflate zstd supercompressed level: {}", e),
+            KtxError::Transcode(e) => write!(f, "failed to transcode UASTC level: {}", e),
+            KtxError::Unsupported(what) => write!(f, "unsupported KTX2 file: {}", what),
+            KtxError::UnsupportedFormat(format) => write!(f, "the device can't sample {:?}", format),
+        }
+    }
+}
+
+impl std::error::Error for KtxError {}
+
+/// Loads a 2D KTX2 texture. UASTC data is transcoded to the device's transcode target, textures that already carry a Vulkan
+/// format are passed through as-is if the device can sample it and rejected otherwise, so callers fall back to their
+/// default texture. ETC1S (BasisLZ) needs the global codebook transcoder and isn't supported.
+pub fn load_ktx2(bytes: &[u8], support: &FormatSupport) -> Result<KtxImage, KtxError> {
+    let reader = Reader::new(bytes).map_err(KtxError::Parse)?;
+    let header = reader.header();
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err(KtxError::Unsupported("only single 2D images are supported"));
+    }
+
+    let mut levels = Vec::with_capacity(header.level_count.max(1) as usize);
+    for level in reader.levels() {
+        levels.push(match header.supercompression_scheme {
+            None => level.to_vec(),
+            Some(SupercompressionScheme::Zstandard) => {
+                let mut cursor = Cursor::new(level);
+                let mut decoder = ruzstd::StreamingDecoder::new(&mut cursor).map_err(|e| KtxError::Zstd(e.to_string()))?;
+                let mut inflated = Vec::new();
+                decoder.read_to_end(&mut inflated).map_err(|e| KtxError::Zstd(e.to_string()))?;
+                inflated
+            }
+            Some(SupercompressionScheme::BasisLZ) => return Err(KtxError::Unsupported("ETC1S/BasisLZ supercompression")),
+            Some(_) => return Err(KtxError::Unsupported("supercompression scheme")),
+        });
+    }
+
+    let extent = vk::Extent3D {
+        width: header.pixel_width,
+        height: header.pixel_height.max(1),
+        depth: 1,
+    };
+
+    // textures with a concrete format need no transcoding, there's nothing to transcode them to if the device lacks it though
+    if let Some(format) = header.format {
+        let format = vk::Format::from_raw(format.0.get() as i32);
+        if !support.can_sample(format) {
+            return Err(KtxError::UnsupportedFormat(format));
+        }
+        return Ok(KtxImage { format, extent, levels });
+    }
+
+    let dfd = reader
+        .data_format_descriptors()
+        .find(|dfd| dfd.header == DataFormatDescriptorHeader::BASIC)
+        .ok_or(KtxError::Unsupported("missing basic data format descriptor"))?;
+    let dfd = BasicDataFormatDescriptor::parse(dfd.data).map_err(KtxError::Parse)?;
+    if dfd.color_model != Some(ColorModel::UASTC) {
+        return Err(KtxError::Unsupported("only UASTC is supported for formatless textures"));
+    }
+    let srgb = dfd.transfer_function == Some(TransferFunction::SRGB);
+    let has_alpha = dfd.sample_information().any(|sample| sample.channel_type == UASTC_CHANNEL_RGBA);
+
+    static TRANSCODER_INIT: Once = Once::new();
+    TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+    let transcoder = LowLevelUastcTranscoder::new();
+    let target = support.transcode_target;
+    let levels = levels
+        .iter()
+        .enumerate()
+        .map(|(mip_level, data)| {
+            let width = (extent.width >> mip_level).max(1);
+            let height = (extent.height >> mip_level).max(1);
+            let num_blocks_x = width.div_ceil(4);
+            let num_blocks_y = height.div_ceil(4);
+            let size = (num_blocks_x * num_blocks_y * UASTC_BLOCK_BYTES) as usize;
+            if data.len() < size {
+                return Err(KtxError::Transcode(format!("level {} is truncated", mip_level)));
+            }
+            transcoder
+                .transcode_slice(
+                    &data[..size],
+                    SliceParametersUastc {
+                        num_blocks_x,
+                        num_blocks_y,
+                        has_alpha,
+                        original_width: width,
+                        original_height: height,
+                    },
+                    DecodeFlags::HIGH_QUALITY,
+                    target.block_format(),
+                )
+                .map_err(|e| KtxError::Transcode(format!("{:?}", e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(KtxImage {
+        format: target.format(srgb),
+        extent,
+        levels,
+    })
+}
@@ -1,4 +1,6 @@
 use crate::asset::texture::{TextureId, TextureManager};
+use crate::gpu_layout;
+use crate::pipeline::mesh::MeshVariant;
 use crate::resource::buffer::AllocatedBuffer;
 use crate::resource::immediate_submit::SubmitContext;
 use crate::resource::AllocUsage;
@@ -70,6 +72,45 @@ pub enum RawMaterial {
     Pbr(PbrMaterial),
 }
 
+impl RawMaterial {
+    /// The variant of the mesh shaders the material needs. Shadow filtering isn't up to the material.
+    pub fn variant(&self) -> MeshVariant {
+        match self {
+            RawMaterial::Unlit(_) => MeshVariant::default(),
+            RawMaterial::Pbr(pbr) => MeshVariant {
+                normal_map: pbr.normal_tex != TextureManager::DEFAULT_TEXTURE_NORMAL,
+                alpha_mask: pbr.alpha_cutoff > 0.0,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// The textures the material samples.
+    pub fn textures(&self) -> Vec<TextureId> {
+        match self {
+            RawMaterial::Unlit(unlit) => vec![unlit.texture],
+            RawMaterial::Pbr(pbr) => vec![pbr.albedo_tex, pbr.metallic_roughness_tex, pbr.normal_tex],
+        }
+    }
+
+    /// Replaces a texture that's about to be deleted with the checkerboard, or the flat normal map for normals.
+    pub fn drop_texture(&mut self, texture: TextureId) {
+        let fallback = |id: &mut TextureId, default| {
+            if *id == texture {
+                *id = default;
+            }
+        };
+        match self {
+            RawMaterial::Unlit(unlit) => fallback(&mut unlit.texture, TextureManager::DEFAULT_TEXTURE_CHECKERBOARD),
+            RawMaterial::Pbr(pbr) => {
+                fallback(&mut pbr.albedo_tex, TextureManager::DEFAULT_TEXTURE_CHECKERBOARD);
+                fallback(&mut pbr.metallic_roughness_tex, TextureManager::DEFAULT_TEXTURE_CHECKERBOARD);
+                fallback(&mut pbr.normal_tex, TextureManager::DEFAULT_TEXTURE_NORMAL);
+            }
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone, Debug)]
 pub struct UnlitMaterial {
@@ -94,7 +135,18 @@ pub struct PbrMaterial {
     pub albedo: [f32; 4],
     pub metallic: f32,
     pub roughness: f32,
+    pub normal_tex: TextureId, // DEFAULT_TEXTURE_NORMAL if no normal map
+    pub alpha_cutoff: f32,     // fragments with a lower alpha are discarded, 0 if the material isn't alpha masked
 }
+gpu_layout!(PbrMaterial {
+    albedo_tex,
+    metallic_roughness_tex,
+    albedo,
+    metallic,
+    roughness,
+    normal_tex,
+    alpha_cutoff
+});
 
 impl Default for PbrMaterial {
     fn default() -> Self {
@@ -104,6 +156,8 @@ impl Default for PbrMaterial {
             albedo: [1.0, 1.0, 1.0, 1.0],
             metallic: 0.0,
             roughness: 0.0,
+            normal_tex: TextureManager::DEFAULT_TEXTURE_NORMAL,
+            alpha_cutoff: 0.0,
         }
     }
 }
@@ -125,6 +179,8 @@ impl MaterialManager {
                     albedo: [1.0, 1.0, 1.0, 1.0],
                     metallic: 0.0,
                     roughness: 0.0,
+                    normal_tex: TextureManager::DEFAULT_TEXTURE_NORMAL,
+                    alpha_cutoff: 0.0,
                 }),
                 ctx,
             )
@@ -160,4 +216,8 @@ impl MaterialManager {
     pub fn get_material_mut(&mut self, id: MaterialId) -> Option<&mut Material> {
         self.materials.get_mut(&id)
     }
+
+    pub fn iter_materials_mut(&mut self) -> impl Iterator<Item = &mut Material> {
+        self.materials.values_mut()
+    }
 }
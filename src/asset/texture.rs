@@ -1,10 +1,16 @@
-use crate::resource::image::AllocatedImage;
+use crate::asset::ktx::FormatSupport;
+use crate::resource::image::{mip_level_count, AllocatedImage};
 use crate::resource::immediate_submit::SubmitContext;
 use crate::resource::{update_set, AllocUsage, Allocator, DescriptorImageWriteInfo};
-use crate::util::transition_image;
+use crate::util::{transition_image, DeletionQueue};
+use crate::FRAME_OVERLAP;
 use ash::{vk, Device};
+use image::imageops::FilterType;
+use image::RgbaImage;
 use log::debug;
+use std::collections::VecDeque;
 use std::mem;
+use std::sync::{Arc, Mutex};
 
 pub type SamplerId = usize;
 pub type TextureId = u32;
@@ -14,7 +20,7 @@ pub struct Texture {
     pub id: TextureId,
     pub image: AllocatedImage,
     pub sampler: SamplerId,
-    pub data: Vec<u8>,
+    pub data: Option<Vec<u8>>, // CPU copy of the first mip level, only kept if retention was asked for at upload
     pub(crate) kind: TextureKind,
 }
 pub const TEXTURE_IMAGE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
@@ -25,12 +31,15 @@ impl Texture {
         ctx: &mut SubmitContext,
         label: Option<String>,
         extent: vk::Extent3D,
+        mip_levels: u32,
         kind: TextureKind,
     ) -> Self {
         let img = AllocatedImage::new(
             &ctx.device,
             &mut ctx.allocator.borrow_mut(),
             extent,
+            mip_levels,
+            vk::SampleCountFlags::TYPE_1,
             format,
             vk::ImageUsageFlags::TRANSFER_DST
                 | vk::ImageUsageFlags::TRANSFER_SRC
@@ -53,7 +62,7 @@ impl Texture {
             image: img,
             id: 0,
             sampler,
-            data: vec![],
+            data: None,
             kind,
         }
     }
@@ -67,18 +76,73 @@ impl Texture {
         extent: vk::Extent3D,
         kind: TextureKind,
     ) -> Self {
-        let mut tex = Self::new(sampler, format, ctx, label, extent, kind);
+        let tex = Self::new(sampler, format, ctx, label, extent, 1, kind);
         tex.image.write(data, ctx);
-        tex.data = Vec::from(data);
         tex
     }
 
+    /// Creates a texture with a full mip chain. `data` holds the RGBA8 pixels of the largest level, the remaining levels are
+    /// generated according to `generation`. `retain_data` keeps a CPU copy of `data` around.
+    pub fn new_mipmapped(
+        sampler: SamplerId,
+        format: vk::Format,
+        ctx: &mut SubmitContext,
+        label: Option<String>,
+        data: &[u8],
+        extent: vk::Extent3D,
+        kind: TextureKind,
+        generation: MipGeneration,
+        retain_data: bool,
+    ) -> Self {
+        let mip_levels = mip_level_count(extent);
+        let mut tex = Self::new(sampler, format, ctx, label, extent, mip_levels, kind);
+        tex.data = retain_data.then(|| data.to_vec());
+        match generation {
+            MipGeneration::Blit => tex.image.write(data, ctx),
+            MipGeneration::Cpu => {
+                let levels = downsample_rgba8(data, extent, mip_levels);
+                tex.image
+                    .write_levels(&levels.iter().map(|level| level.as_slice()).collect::<Vec<_>>(), ctx);
+            }
+        }
+        tex
+    }
+
+    /// Creates a texture from precomputed mip levels (e.g. from a container format), starting with the largest one.
+    /// `retain_data` keeps a CPU copy of the largest level around.
+    pub fn new_init_levels(
+        sampler: SamplerId,
+        format: vk::Format,
+        ctx: &mut SubmitContext,
+        label: Option<String>,
+        levels: &[&[u8]],
+        extent: vk::Extent3D,
+        kind: TextureKind,
+        retain_data: bool,
+    ) -> Self {
+        let mut tex = Self::new(sampler, format, ctx, label, extent, levels.len() as u32, kind);
+        tex.data = retain_data.then(|| levels[0].to_vec());
+        tex.image.write_levels(levels, ctx);
+        tex
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.image.format
+    }
+
+    /// Whether the texture is stored in a block-compressed format.
+    pub fn is_compressed(&self) -> bool {
+        is_block_compressed(self.image.format)
+    }
+
     /// Replaces the image data with the given data. Creates a new AllocatedImage and destroys the old one.
     pub fn replace_image(&mut self, ctx: &mut SubmitContext, label: Option<String>, data: &[u8], extent: vk::Extent3D) {
         let img = AllocatedImage::new(
             &ctx.device,
             &mut ctx.allocator.borrow_mut(),
             extent,
+            1,
+            vk::SampleCountFlags::TYPE_1,
             TEXTURE_IMAGE_FORMAT,
             vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
             AllocUsage::GpuOnly,
@@ -92,6 +156,10 @@ impl Texture {
         );
 
         img.write(data, ctx);
+        if let Some(retained) = &mut self.data {
+            retained.clear();
+            retained.extend_from_slice(data);
+        }
 
         let old = mem::replace(&mut self.image, img);
 
@@ -188,10 +256,41 @@ impl Texture {
     }
 }
 
+/// Builds the full mip chain of an RGBA8 image on the CPU. Used for formats the device can't blit with linear filtering.
+fn downsample_rgba8(data: &[u8], extent: vk::Extent3D, mip_levels: u32) -> Vec<Vec<u8>> {
+    let mut levels = vec![data.to_vec()];
+    let mut image = RgbaImage::from_raw(extent.width, extent.height, data.to_vec()).expect("mip data doesn't match extent");
+    for mip_level in 1..mip_levels {
+        let width = (extent.width >> mip_level).max(1);
+        let height = (extent.height >> mip_level).max(1);
+        image = image::imageops::resize(&image, width, height, FilterType::Triangle);
+        levels.push(image.as_raw().clone());
+    }
+    levels
+}
+
+fn is_block_compressed(format: vk::Format) -> bool {
+    (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw()).contains(&format.as_raw())
+}
+
+/// How the lower mip levels of uploaded textures are filled.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MipGeneration {
+    Blit, // on the GPU, via vkCmdBlitImage
+    Cpu,  // fallback for formats without linear blit support
+}
+
 pub struct TextureManager {
     textures: Vec<Option<Texture>>,
+    ref_counts: Vec<u32>,
+    pending_free: Vec<TextureId>, // released textures that haven't been handed to a deletion queue yet
+    retired_ids: Arc<Mutex<Vec<TextureId>>>, // slots whose textures have been destroyed, filled by the deletion queue
+    cooling_ids: VecDeque<Vec<TextureId>>, // retired slots of the last frames, see collect_garbage
+    free_ids: Vec<TextureId>,     // slots no frame in flight refers to anymore
     samplers: Vec<vk::Sampler>,
     descriptor_set: vk::DescriptorSet,
+    mip_generation: MipGeneration,
+    format_support: FormatSupport,
 }
 #[allow(dead_code)]
 impl TextureManager {
@@ -201,12 +300,25 @@ impl TextureManager {
     pub const DEFAULT_TEXTURE_BLACK: TextureId = 1;
     pub const DEFAULT_TEXTURE_CHECKERBOARD: TextureId = 2;
     pub const DEFAULT_TEXTURE_NORMAL: TextureId = 3;
+    const BUILTIN_TEXTURE_COUNT: TextureId = 4; // the defaults above are never freed
 
-    pub fn new(descriptor_set: vk::DescriptorSet, ctx: &mut SubmitContext) -> Self {
+    pub fn new(
+        descriptor_set: vk::DescriptorSet,
+        ctx: &mut SubmitContext,
+        mip_generation: MipGeneration,
+        format_support: FormatSupport,
+    ) -> Self {
         let mut manager = Self {
             textures: vec![],
+            ref_counts: vec![],
+            pending_free: vec![],
+            retired_ids: Arc::new(Mutex::new(vec![])),
+            cooling_ids: VecDeque::new(),
+            free_ids: vec![],
             samplers: vec![],
             descriptor_set,
+            mip_generation,
+            format_support,
         };
 
         let sampler_info = vk::SamplerCreateInfo::default()
@@ -214,7 +326,9 @@ impl TextureManager {
             .address_mode_v(vk::SamplerAddressMode::REPEAT)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
             .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST);
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .max_lod(vk::LOD_CLAMP_NONE);
         let sampler_nearest = unsafe { ctx.device.create_sampler(&sampler_info, None).unwrap() };
         Self::add_sampler(&mut manager, sampler_nearest);
 
@@ -223,7 +337,9 @@ impl TextureManager {
             .address_mode_v(vk::SamplerAddressMode::REPEAT)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
             .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR);
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .max_lod(vk::LOD_CLAMP_NONE);
         let sampler_linear = unsafe { ctx.device.create_sampler(&sampler_info, None).unwrap() };
 
         Self::add_sampler(&mut manager, sampler_linear);
@@ -325,32 +441,116 @@ impl TextureManager {
         manager
     }
 
-    pub fn free(&mut self, to_free: TextureId, device: &Device, allocator: &mut Allocator) {
-        let texture = self.textures[to_free as usize].take().unwrap();
-        let label = texture.image.label.clone().unwrap_or_default();
-        texture.image.destroy(device, allocator);
-        debug!("Freed texture {} ({:?}) ", label, to_free);
+    /// Adds a reference to the texture, e.g. when a material starts using it.
+    pub fn acquire(&mut self, id: TextureId) {
+        if !Self::is_builtin(id) {
+            self.ref_counts[id as usize] += 1;
+        }
+    }
+
+    /// Drops a reference to the texture. The texture is freed once no references are left.
+    pub fn release(&mut self, id: TextureId) {
+        if Self::is_builtin(id) {
+            return;
+        }
+        let count = &mut self.ref_counts[id as usize];
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            self.free(id);
+        }
     }
 
-    pub fn next_free_id(&self) -> TextureId {
-        self.textures.iter().position(|t| t.is_none()).unwrap_or(self.textures.len()) as TextureId
+    /// Frees the texture regardless of its references. Destruction is deferred until [`Self::collect_garbage`], so the
+    /// texture stays valid for frames that are still being recorded. Whatever still refers to the texture has to be
+    /// pointed elsewhere first, see `Command::DeleteTexture`.
+    pub fn free(&mut self, to_free: TextureId) {
+        if Self::is_builtin(to_free) || self.textures[to_free as usize].is_none() {
+            return;
+        }
+        self.ref_counts[to_free as usize] = 0;
+        if !self.pending_free.contains(&to_free) {
+            self.pending_free.push(to_free);
+        }
+    }
+
+    /// Hands all freed textures to the given (per-frame) deletion queue. Once the queue is flushed, the images are destroyed
+    /// and their bindless slots point back to the checkerboard texture. The slots become available for new textures
+    /// `FRAME_OVERLAP` calls later, when every frame that was in flight at the time has finished.
+    pub fn collect_garbage(&mut self, deletion_queue: &mut DeletionQueue) {
+        let retired = mem::take(&mut *self.retired_ids.lock().unwrap());
+        self.cooling_ids.push_back(retired);
+        if self.cooling_ids.len() > FRAME_OVERLAP {
+            self.free_ids.extend(self.cooling_ids.pop_front().unwrap());
+        }
+
+        let checkerboard = self.textures[Self::DEFAULT_TEXTURE_CHECKERBOARD as usize].as_ref().unwrap();
+        let (fallback_view, fallback_sampler) = (checkerboard.image.view, self.samplers[checkerboard.sampler]);
+        for id in self.pending_free.drain(..) {
+            let Some(texture) = self.textures[id as usize].take() else {
+                continue;
+            };
+            let descriptor_set = self.descriptor_set;
+            let retired_ids = self.retired_ids.clone();
+            deletion_queue.push(move |device, allocator| {
+                let label = texture.image.label.clone().unwrap_or_default();
+                texture.image.destroy(device, allocator);
+                update_set(
+                    device,
+                    descriptor_set,
+                    &[DescriptorImageWriteInfo {
+                        binding: 2,
+                        array_index: id,
+                        image_view: fallback_view,
+                        sampler: fallback_sampler,
+                        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    }],
+                    &[],
+                );
+                retired_ids.lock().unwrap().push(id);
+                debug!("Freed texture {} ({:?}) ", label, id);
+            });
+        }
+    }
+
+    pub fn is_builtin(id: TextureId) -> bool {
+        id < Self::BUILTIN_TEXTURE_COUNT
+    }
+
+    pub fn ref_count(&self, id: TextureId) -> u32 {
+        self.ref_counts[id as usize]
+    }
+
+    fn next_free_id(&mut self) -> TextureId {
+        self.free_ids.pop().unwrap_or(self.textures.len() as TextureId)
     }
 
     pub fn descriptor_set(&self) -> vk::DescriptorSet {
         self.descriptor_set
     }
 
+    pub fn mip_generation(&self) -> MipGeneration {
+        self.mip_generation
+    }
+
+    pub fn format_support(&self) -> &FormatSupport {
+        &self.format_support
+    }
+
     pub fn iter_textures(&self) -> impl Iterator<Item = &Texture> {
         self.textures.iter().filter_map(|t| t.as_ref())
     }
 
+    /// Adds a texture and returns its id. The caller holds the first reference, see [`Self::release`].
     pub fn add_texture(&mut self, mut texture: Texture, device: &Device, update_set: bool) -> TextureId {
         let id = self.next_free_id();
         texture.id = id;
         if id as usize == self.textures.len() {
             self.textures.push(Some(texture));
+            self.ref_counts.push(1);
         } else {
             self.textures[id as usize] = Some(texture);
+            self.ref_counts[id as usize] = 1;
         }
 
         if update_set {
@@ -91,3 +91,83 @@ impl Camera {
         self.delta = (0.0, 0.0);
     }
 }
+
+/// A world space ray, e.g. from the camera through the cursor.
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3, // normalized, except for rays transformed into model space
+}
+
+impl Ray {
+    /// Unprojects a position in window pixels onto the near and far plane using the inverse view-projection matrix.
+    pub fn from_screen(position: (f32, f32), extent: (f32, f32), unproj: Mat4) -> Self {
+        let x = position.0 / extent.0 * 2.0 - 1.0;
+        let y = position.1 / extent.1 * 2.0 - 1.0;
+        let near = unproj * Vec4::new(x, y, 0.0, 1.0);
+        let far = unproj * Vec4::new(x, y, 1.0, 1.0);
+        let (near, far) = (near.xyz() / near.w, far.xyz() / far.w);
+        Self {
+            origin: near,
+            dir: (far - near).normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// Distance along the ray to the plane through `point`, if it's hit in front of the origin.
+    pub fn intersect_plane(&self, point: Vec3, normal: Vec3) -> Option<f32> {
+        let denom = normal.dot(self.dir);
+        if denom.abs() < 1e-6 {
+            return None;
+        }
+        let t = (point - self.origin).dot(normal) / denom;
+        (t >= 0.0).then_some(t)
+    }
+
+    /// Closest approach to the line through `origin` along `dir` (normalized). Returns the parameters on the ray and the
+    /// line, or None if they're parallel.
+    pub fn closest_to_line(&self, origin: Vec3, dir: Vec3) -> Option<(f32, f32)> {
+        let w = self.origin - origin;
+        let b = self.dir.dot(dir);
+        let d = self.dir.dot(w);
+        let e = dir.dot(w);
+        let denom = 1.0 - b * b;
+        if denom < 1e-6 {
+            return None;
+        }
+        Some(((b * e - d) / denom, (e - b * d) / denom))
+    }
+
+    /// Möller–Trumbore intersection with the triangle `abc`, from either side.
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+        let (ab, ac) = (b - a, c - a);
+        let p = self.dir.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < 1e-8 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(ab);
+        let v = self.dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = ac.dot(q) * inv_det;
+        (t >= 0.0).then_some(t)
+    }
+
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        Self {
+            origin: transform.transform_point3(self.origin),
+            dir: transform.transform_vector3(self.dir),
+        }
+    }
+}
@@ -1,20 +1,39 @@
+use crate::asset::ktx::load_ktx2;
 use crate::asset::texture::TextureKind;
-use crate::asset::texture::{Texture, TextureManager};
+use crate::asset::texture::{Texture, TextureId, TextureManager};
+use crate::history::Edit;
 use crate::resource::immediate_submit::SubmitContext;
-use crate::scene::model::ModelId;
+use crate::scene::light::{Light, LightMeta};
+use crate::scene::model::{Model, ModelId};
+use crate::scene::primitive::Primitive;
 use crate::App;
 use ash::vk;
+use glam::Mat4;
 use image::{EncodableLayout, GenericImageView, ImageReader};
-use log::info;
+use log::{error, info};
 use std::path::PathBuf;
 use std::sync::mpsc;
 
 pub enum Command {
     LoadScene(PathBuf),
     ImportModel(PathBuf),
+    AddPrimitive(Primitive), // added at the camera's target
+    AddLight(LightMeta),     // likewise, as a model with the light attached
     DeleteModel(ModelId),
+    DuplicateModel(ModelId), // places the copy next to the original
+    PasteModel {
+        model: ModelId,
+        parent: ModelId, // the copy becomes its last child, keeping its place in the world
+    },
     ImportTexture(PathBuf),
+    DeleteTexture(TextureId), // materials using it fall back to default textures
+    #[cfg(feature = "watch")]
     ReloadShaders,
+    SetMsaa(vk::SampleCountFlags), // recreates the pipelines for the new sample count
+    Edit(Edit),                    // applies an editor change and records it in the history
+    EndEdit,                       // ends the current history entry, e.g. when a drag is released
+    Undo,
+    Redo,
 }
 
 pub struct CommandHandler {
@@ -37,7 +56,17 @@ impl CommandHandler {
                                 .unwrap();
                         }
                     }
+                    for edit in app.history.clear() {
+                        edit.discard(app);
+                    }
                     app.world.borrow_mut().clear(&app.device, &mut app.allocator.borrow_mut());
+                    let ctx = SubmitContext::from_app(app);
+                    ctx.immediate_submit(Box::new(|ctx| {
+                        let mut light_manager = app.light_manager.borrow_mut();
+                        for light in light_manager.keys() {
+                            light_manager.remove_light(light, ctx, &mut app.texture_manager.borrow_mut());
+                        }
+                    }));
                     let mut reader = crate::gltf::GltfReader::new(
                         app.world.clone(),
                         app.texture_manager.clone(),
@@ -46,6 +75,7 @@ impl CommandHandler {
                     );
                     let ctx = SubmitContext::from_app(app);
                     reader.load(&path, ctx);
+                    app.gui.forget_removed_models(&app.world.borrow());
                 }
                 Command::ImportModel(path) => {
                     let mut reader = crate::gltf::GltfReader::new(
@@ -57,45 +87,182 @@ impl CommandHandler {
                     let ctx = SubmitContext::from_app(app);
                     reader.load(&path, ctx);
                 }
+                Command::AddPrimitive(primitive) => {
+                    let transform = Mat4::from_translation(app.camera.target);
+                    let ctx = SubmitContext::from_app(app);
+                    let model = ctx.immediate_submit(Box::new(|ctx| {
+                        let mut mesh = primitive.mesh();
+                        mesh.transform = transform;
+                        mesh.upload(ctx);
+                        let model = Model::new(vec![mesh], transform, None, None, Some(primitive.name().into()));
+                        app.world.borrow_mut().add_model(model)
+                    }));
+                    Self::record(app, Edit::add_model(model));
+                    app.selection.set(model);
+                    info!("Added {} {}", primitive.name(), model);
+                }
+                Command::AddLight(meta) => {
+                    let transform = Mat4::from_translation(app.camera.target);
+                    let ctx = SubmitContext::from_app(app);
+                    let model = ctx.immediate_submit(Box::new(|ctx| {
+                        let mut light_manager = app.light_manager.borrow_mut();
+                        let light = light_manager.add_light(Light::new(meta, [1.0, 1.0, 1.0], 10.0), ctx, app.texture_manager.clone());
+                        let mut world = app.world.borrow_mut();
+                        // gets a billboard child, which marks the light in the viewport
+                        let model = world.add_model(Model::new(vec![], transform, Some(light), None, Some(meta.name().into())));
+                        world.update_transforms(model, Mat4::IDENTITY, &mut light_manager, ctx);
+                        model
+                    }));
+                    Self::record(app, Edit::add_model(model));
+                    app.selection.set(model);
+                    info!("Added {} {}", meta.name().to_lowercase(), model);
+                }
                 Command::DeleteModel(id) => {
-                    println!("Delete model: {:?}", id);
-                    unsafe {
-                        app.device.device_wait_idle().unwrap();
-                        for frame in app.frames.as_mut_slice() {
-                            app.device
-                                .reset_command_buffer(frame.command_buffer, vk::CommandBufferResetFlags::empty())
-                                .unwrap();
+                    let mut edit = Edit::delete_model(id);
+                    edit.apply(app, false);
+                    Self::record(app, edit);
+                    app.gui.forget_removed_models(&app.world.borrow());
+                    info!("Deleted model {}", id);
+                }
+                Command::DuplicateModel(id) => {
+                    if let Some(copy) = Self::duplicate(app, id, None) {
+                        // the copy takes the original's place in the selection, so it can be moved right away
+                        if app.selection.contains(id) {
+                            app.selection.toggle(id);
+                            app.selection.toggle(copy);
                         }
+                        info!("Duplicated model {} as {}", id, copy);
+                    }
+                }
+                Command::PasteModel { model, parent } => {
+                    if let Some(copy) = Self::duplicate(app, model, Some(parent)) {
+                        app.selection.set(copy);
+                        info!("Pasted model {} as {}", model, copy);
                     }
-                    app.world.borrow_mut().models.remove(&id);
                 }
                 Command::ImportTexture(path) => {
-                    let img = ImageReader::open(path.clone()).unwrap().decode().unwrap();
-                    let dimensions = img.dimensions();
-                    let ctx = SubmitContext::from_app(app);
-                    ctx.immediate_submit(Box::new(|ctx| {
-                        let texture = Texture::new_init(
-                            TextureManager::DEFAULT_SAMPLER_NEAREST,
-                            vk::Format::R8G8B8A8_SRGB,
-                            ctx,
-                            Some(path.file_name().unwrap().to_string_lossy().into()),
-                            img.to_rgba8().as_bytes(),
-                            vk::Extent3D {
-                                width: dimensions.0,
-                                height: dimensions.1,
-                                depth: 1,
-                            },
-                            TextureKind::Color,
-                        );
-                        app.texture_manager.borrow_mut().add_texture(texture, &ctx.device, true);
-                    }));
+                    let label = Some(path.file_name().unwrap().to_string_lossy().into());
+                    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ktx2")) {
+                        let format_support = app.texture_manager.borrow().format_support().clone();
+                        let image = match std::fs::read(&path)
+                            .map_err(|e| e.to_string())
+                            .and_then(|bytes| load_ktx2(&bytes, &format_support).map_err(|e| e.to_string()))
+                        {
+                            Ok(image) => image,
+                            Err(e) => {
+                                error!("Failed to import texture {:?}: {}", path, e);
+                                continue;
+                            }
+                        };
+                        let ctx = SubmitContext::from_app(app);
+                        ctx.immediate_submit(Box::new(|ctx| {
+                            let texture = Texture::new_init_levels(
+                                TextureManager::DEFAULT_SAMPLER_LINEAR,
+                                image.format,
+                                ctx,
+                                label,
+                                &image.levels(),
+                                image.extent,
+                                TextureKind::Color,
+                                false,
+                            );
+                            app.texture_manager.borrow_mut().add_texture(texture, &ctx.device, true);
+                        }));
+                    } else {
+                        let img = ImageReader::open(path.clone()).unwrap().decode().unwrap();
+                        let dimensions = img.dimensions();
+                        let mip_generation = app.texture_manager.borrow().mip_generation();
+                        let ctx = SubmitContext::from_app(app);
+                        ctx.immediate_submit(Box::new(|ctx| {
+                            let texture = Texture::new_mipmapped(
+                                TextureManager::DEFAULT_SAMPLER_LINEAR,
+                                vk::Format::R8G8B8A8_SRGB,
+                                ctx,
+                                label,
+                                img.to_rgba8().as_bytes(),
+                                vk::Extent3D {
+                                    width: dimensions.0,
+                                    height: dimensions.1,
+                                    depth: 1,
+                                },
+                                TextureKind::Color,
+                                mip_generation,
+                                false,
+                            );
+                            app.texture_manager.borrow_mut().add_texture(texture, &ctx.device, true);
+                        }));
+                    }
                     info!("Imported texture: {:?}", path);
                 }
+                Command::DeleteTexture(texture) => {
+                    // nothing may refer to the texture once it's freed, as its slot is reused for the next one
+                    SubmitContext::from_app(app).immediate_submit(Box::new(|ctx| {
+                        for material in app.material_manager.borrow_mut().iter_materials_mut() {
+                            if material.data.textures().contains(&texture) {
+                                material.update(|data| data.drop_texture(texture), ctx);
+                            }
+                        }
+                    }));
+                    app.history.drop_texture(texture);
+                    app.texture_manager.borrow_mut().free(texture);
+                    info!("Deleted texture {}", texture);
+                }
+                #[cfg(feature = "watch")]
                 Command::ReloadShaders => {
-                    app.recreate_pipelines();
-                    info!("Recreated pipelines and reloaded shaders.");
+                    if app.recreate_pipelines() {
+                        info!("Recompiled shaders and recreated pipelines.");
+                    }
+                }
+                Command::SetMsaa(samples) => app.set_msaa(samples),
+                Command::Edit(mut edit) => {
+                    edit.apply(app, false);
+                    Self::record(app, edit);
+                }
+                Command::EndEdit => app.history.seal(),
+                Command::Undo => {
+                    if let Some(mut edit) = app.history.take_undo() {
+                        edit.apply(app, true);
+                        info!("Undo: {}", edit.describe());
+                        app.history.push_redo(edit);
+                        app.gui.forget_removed_models(&app.world.borrow());
+                    }
+                }
+                Command::Redo => {
+                    if let Some(mut edit) = app.history.take_redo() {
+                        edit.apply(app, false);
+                        info!("Redo: {}", edit.describe());
+                        app.history.push_undo(edit);
+                        app.gui.forget_removed_models(&app.world.borrow());
+                    }
+                }
+            }
+        }
+    }
+
+    // Copies the model, optionally moving the copy under `parent`, and records it in the history.
+    fn duplicate(app: &mut App, model: ModelId, parent: Option<ModelId>) -> Option<ModelId> {
+        let ctx = SubmitContext::from_app(app);
+        let copy = ctx.immediate_submit(Box::new(|ctx| {
+            let mut world = app.world.borrow_mut();
+            let mut light_manager = app.light_manager.borrow_mut();
+            let copy = world.duplicate_model(model, &mut light_manager, ctx, app.texture_manager.clone())?;
+            if let Some(parent) = parent {
+                if world.reparent(copy, Some(parent), None) {
+                    let transform = world.world_transform(parent);
+                    world.update_transforms(copy, transform, &mut light_manager, ctx);
                 }
             }
+            Some(copy)
+        }))?;
+        Self::record(app, Edit::add_model(copy));
+        Some(copy)
+    }
+
+    // Records an applied edit and drops whatever fell out of the history.
+    fn record(app: &mut App, edit: Edit) {
+        let dropped = app.history.record(edit, &mut app.texture_manager.borrow_mut());
+        for edit in dropped {
+            edit.discard(app);
         }
     }
 }
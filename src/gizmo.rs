@@ -0,0 +1,368 @@
+use crate::camera::Ray;
+use crate::history::Edit;
+use crate::scene::model::ModelId;
+use crate::scene::world::World;
+use crate::ui::TransformSpace;
+use egui::{Color32, Pos2, Shape, Stroke};
+use glam::{Mat4, Quat, Vec3, Vec4Swizzles};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Handle {
+    Axis(usize),  // translate/scale along an axis, or rotate around it
+    Plane(usize), // translate in the plane with this axis as its normal
+    Uniform,      // scale along all axes
+}
+
+// Where the gizmo is drawn, in world space.
+#[derive(Debug, Copy, Clone)]
+struct Frame {
+    origin: Vec3,
+    axes: [Vec3; 3],
+    size: f32, // world space length of the axis handles, scaled with the distance to keep a constant screen size
+}
+
+struct Drag {
+    model: ModelId,
+    handle: Handle,
+    frame: Frame,
+    start: Vec3, // where the handle was grabbed, constrained to the handle's axis or plane
+    plane_normal: Vec3,
+    start_world: Mat4,
+    parent: Mat4,
+    before: Mat4, // local transform before the drag
+}
+
+/// Translate/rotate/scale handles drawn over the selected model. Dragging a handle sends transform edits, so gizmo changes
+/// end up in the same update path and history as the inspector.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub space: TransformSpace, // scaling always happens along the local axes
+    pub snapping: bool,
+    pub translate_snap: f32,
+    pub rotate_snap: f32, // degrees
+    pub scale_snap: f32,
+    hovered: Option<Handle>,
+    drag: Option<Drag>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            space: TransformSpace::World,
+            snapping: false,
+            translate_snap: 0.25,
+            rotate_snap: 15.0,
+            scale_snap: 0.1,
+            hovered: None,
+            drag: None,
+        }
+    }
+}
+
+impl Gizmo {
+    const SCREEN_SIZE: f32 = 0.15; // axis length relative to the distance to the camera
+    const AXIS_COLORS: [Color32; 3] = [
+        Color32::from_rgb(220, 60, 60),
+        Color32::from_rgb(60, 200, 60),
+        Color32::from_rgb(60, 100, 230),
+    ];
+    const ACTIVE_COLOR: Color32 = Color32::from_rgb(250, 210, 40);
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    fn frame(&self, world_transform: Mat4, camera_position: Vec3) -> Frame {
+        let (_, rotation, origin) = world_transform.to_scale_rotation_translation();
+        let rotation = if self.mode == GizmoMode::Scale || self.space == TransformSpace::Local {
+            rotation
+        } else {
+            Quat::IDENTITY
+        };
+        Frame {
+            origin,
+            axes: [rotation * Vec3::X, rotation * Vec3::Y, rotation * Vec3::Z],
+            size: origin.distance(camera_position) * Self::SCREEN_SIZE,
+        }
+    }
+
+    fn handles(&self) -> Vec<Handle> {
+        let axes = (0..3).map(Handle::Axis);
+        match self.mode {
+            GizmoMode::Translate => axes.chain((0..3).map(Handle::Plane)).collect(),
+            GizmoMode::Rotate => axes.collect(),
+            GizmoMode::Scale => axes.chain([Handle::Uniform]).collect(),
+        }
+    }
+
+    // The handle under the ray that's closest to the camera.
+    fn hit(&self, frame: &Frame, ray: &Ray) -> Option<Handle> {
+        let Frame { origin, axes, size } = *frame;
+        self.handles()
+            .into_iter()
+            .filter_map(|handle| {
+                let t = match (self.mode, handle) {
+                    (GizmoMode::Rotate, Handle::Axis(i)) => {
+                        let t = ray.intersect_plane(origin, axes[i])?;
+                        ((ray.at(t).distance(origin) - size).abs() < size * 0.08).then_some(t)?
+                    }
+                    (_, Handle::Axis(i)) => {
+                        let (t, s) = ray.closest_to_line(origin, axes[i])?;
+                        let on_axis = (0.0..=size).contains(&s) && t >= 0.0;
+                        (on_axis && ray.at(t).distance(origin + axes[i] * s) < size * 0.06).then_some(t)?
+                    }
+                    (_, Handle::Plane(i)) => {
+                        let t = ray.intersect_plane(origin, axes[i])?;
+                        let offset = ray.at(t) - origin;
+                        let inside = |axis: Vec3| (size * 0.2..=size * 0.4).contains(&offset.dot(axis));
+                        (inside(axes[(i + 1) % 3]) && inside(axes[(i + 2) % 3])).then_some(t)?
+                    }
+                    (_, Handle::Uniform) => {
+                        let t = ray.dir.dot(origin - ray.origin);
+                        (t >= 0.0 && ray.at(t).distance(origin) < size * 0.12).then_some(t)?
+                    }
+                };
+                Some((handle, t))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(handle, _)| handle)
+    }
+
+    // The plane a handle is dragged in and the point the ray hits it at, constrained to the handle's axis if it has one.
+    fn constrain(&self, handle: Handle, frame: &Frame, plane_normal: Vec3, ray: &Ray) -> Option<Vec3> {
+        match (self.mode, handle) {
+            (GizmoMode::Translate | GizmoMode::Scale, Handle::Axis(i)) => {
+                let (_, s) = ray.closest_to_line(frame.origin, frame.axes[i])?;
+                Some(frame.origin + frame.axes[i] * s)
+            }
+            _ => ray.intersect_plane(frame.origin, plane_normal).map(|t| ray.at(t)),
+        }
+    }
+
+    /// Updates the hovered handle. Call when the cursor moves and nothing is being dragged.
+    pub fn hover(&mut self, ray: &Ray, world: &World, selected: Option<ModelId>, camera_position: Vec3) {
+        self.hovered = selected
+            .filter(|model| world.models.contains_key(model))
+            .and_then(|model| self.hit(&self.frame(world.world_transform(model), camera_position), ray));
+    }
+
+    /// Starts dragging the handle under the ray. Returns whether a handle was hit, in which case the click shouldn't go
+    /// anywhere else.
+    pub fn press(&mut self, ray: &Ray, world: &World, selected: Option<ModelId>, camera_position: Vec3) -> bool {
+        let Some(model) = selected.filter(|model| world.models.contains_key(model)) else {
+            return false;
+        };
+        let start_world = world.world_transform(model);
+        let frame = self.frame(start_world, camera_position);
+        let Some(handle) = self.hit(&frame, ray) else {
+            return false;
+        };
+        let plane_normal = match handle {
+            Handle::Axis(i) | Handle::Plane(i) => frame.axes[i],
+            Handle::Uniform => -ray.dir, // facing the camera
+        };
+        let Some(start) = self.constrain(handle, &frame, plane_normal, ray) else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            model,
+            handle,
+            frame,
+            start,
+            plane_normal,
+            start_world,
+            parent: world
+                .parent_of(model)
+                .map(|parent| world.world_transform(parent))
+                .unwrap_or(Mat4::IDENTITY),
+            before: world.models[&model].transform,
+        });
+        true
+    }
+
+    /// Continues a drag. Returns the edit to apply, relative to the transform at the start of the drag.
+    pub fn drag(&mut self, ray: &Ray) -> Option<Edit> {
+        let drag = self.drag.as_ref()?;
+        let Frame { origin, axes, size } = drag.frame;
+        let current = self.constrain(drag.handle, &drag.frame, drag.plane_normal, ray)?;
+        let delta = current - drag.start;
+        let (mut scale, mut rotation, mut translation) = drag.start_world.to_scale_rotation_translation();
+        match (self.mode, drag.handle) {
+            (GizmoMode::Translate, Handle::Axis(i)) => {
+                translation += axes[i] * self.snap(delta.dot(axes[i]), self.translate_snap);
+            }
+            (GizmoMode::Translate, Handle::Plane(i)) => {
+                for axis in [axes[(i + 1) % 3], axes[(i + 2) % 3]] {
+                    translation += axis * self.snap(delta.dot(axis), self.translate_snap);
+                }
+            }
+            (GizmoMode::Rotate, Handle::Axis(i)) => {
+                let from = (drag.start - origin).normalize_or_zero();
+                let to = (current - origin).normalize_or_zero();
+                let angle = from.cross(to).dot(axes[i]).atan2(from.dot(to));
+                let angle = self.snap(angle.to_degrees(), self.rotate_snap).to_radians();
+                rotation = Quat::from_axis_angle(axes[i], angle) * rotation;
+            }
+            (GizmoMode::Scale, Handle::Axis(i)) => {
+                let grabbed = (drag.start - origin).dot(axes[i]);
+                if grabbed.abs() < 1e-4 {
+                    return None;
+                }
+                let ratio = (current - origin).dot(axes[i]) / grabbed;
+                scale[i] = self.snap_scale(scale[i] * ratio);
+            }
+            (GizmoMode::Scale, Handle::Uniform) => {
+                // moving the cursor to the right grows the model
+                let right = Vec3::Y.cross(drag.plane_normal).try_normalize().unwrap_or(Vec3::X);
+                let ratio = (1.0 + delta.dot(right) / size).max(0.01);
+                scale = (scale * ratio).to_array().map(|s| self.snap_scale(s)).into();
+            }
+            _ => return None,
+        }
+        let world = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+        Some(Edit::Transform {
+            model: drag.model,
+            before: drag.before,
+            after: drag.parent.inverse() * world,
+        })
+    }
+
+    /// Ends the drag. Returns whether there was one.
+    pub fn release(&mut self) -> bool {
+        self.drag.take().is_some()
+    }
+
+    fn snap(&self, value: f32, step: f32) -> f32 {
+        if self.snapping && step > 0.0 {
+            (value / step).round() * step
+        } else {
+            value
+        }
+    }
+
+    fn snap_scale(&self, scale: f32) -> f32 {
+        self.snap(scale, self.scale_snap)
+            .max(if self.snapping { self.scale_snap } else { 0.001 })
+    }
+
+    /// Paints the gizmo for the selected model. `viewproj` is the camera's view-projection matrix.
+    pub fn paint(&self, ctx: &egui::Context, world: &World, selected: Option<ModelId>, viewproj: Mat4, camera_position: Vec3) {
+        let Some(model) = selected.filter(|model| world.models.contains_key(model)) else {
+            return;
+        };
+        // keep drawing the frame the drag started with, so the handles don't move away from the cursor
+        let frame = match &self.drag {
+            Some(drag) => drag.frame,
+            None => self.frame(world.world_transform(model), camera_position),
+        };
+        let screen = ctx.screen_rect();
+        let project = |point: Vec3| -> Option<Pos2> {
+            let clip = viewproj * point.extend(1.0);
+            if clip.w <= 0.0 {
+                return None;
+            }
+            let ndc = clip.xy() / clip.w;
+            Some(Pos2::new(
+                (ndc.x + 1.0) * 0.5 * screen.width(),
+                (ndc.y + 1.0) * 0.5 * screen.height(),
+            ))
+        };
+        let active = self.drag.as_ref().map(|drag| drag.handle).or(self.hovered);
+        let color = |handle: Handle, axis: usize| {
+            if active == Some(handle) {
+                Self::ACTIVE_COLOR
+            } else {
+                Self::AXIS_COLORS[axis]
+            }
+        };
+
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("gizmo")));
+        let Frame { origin, axes, size } = frame;
+        let Some(center) = project(origin) else {
+            return;
+        };
+        for handle in self.handles() {
+            match (self.mode, handle) {
+                (GizmoMode::Rotate, Handle::Axis(i)) => {
+                    let (u, v) = (axes[(i + 1) % 3], axes[(i + 2) % 3]);
+                    let points = (0..=48)
+                        .map(|step| step as f32 / 48.0 * std::f32::consts::TAU)
+                        .filter_map(|angle| project(origin + (u * angle.cos() + v * angle.sin()) * size))
+                        .collect();
+                    painter.add(Shape::line(points, Stroke::new(2.0, color(handle, i))));
+                }
+                (_, Handle::Axis(i)) => {
+                    let Some(tip) = project(origin + axes[i] * size) else {
+                        continue;
+                    };
+                    painter.line_segment([center, tip], Stroke::new(3.0, color(handle, i)));
+                    if self.mode == GizmoMode::Scale {
+                        painter.rect_filled(egui::Rect::from_center_size(tip, egui::vec2(9.0, 9.0)), 0.0, color(handle, i));
+                    } else {
+                        painter.circle_filled(tip, 5.0, color(handle, i));
+                    }
+                }
+                (_, Handle::Plane(i)) => {
+                    let (u, v) = (axes[(i + 1) % 3] * size, axes[(i + 2) % 3] * size);
+                    let corners = [u * 0.2 + v * 0.2, u * 0.4 + v * 0.2, u * 0.4 + v * 0.4, u * 0.2 + v * 0.4];
+                    let Some(points) = corners.iter().map(|corner| project(origin + *corner)).collect::<Option<Vec<_>>>() else {
+                        continue;
+                    };
+                    let fill = color(handle, i).gamma_multiply(0.5);
+                    painter.add(Shape::convex_polygon(points, fill, Stroke::new(1.0, color(handle, i))));
+                }
+                (_, Handle::Uniform) => {
+                    let fill = if active == Some(handle) {
+                        Self::ACTIVE_COLOR
+                    } else {
+                        Color32::LIGHT_GRAY
+                    };
+                    painter.circle_filled(center, 6.0, fill);
+                }
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Gizmo");
+            ui.selectable_value(&mut self.mode, GizmoMode::Translate, "Move (W)");
+            ui.selectable_value(&mut self.mode, GizmoMode::Rotate, "Rotate (E)");
+            ui.selectable_value(&mut self.mode, GizmoMode::Scale, "Scale (R)");
+        });
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.space, TransformSpace::Local, "Local");
+            ui.selectable_value(&mut self.space, TransformSpace::World, "World");
+            ui.checkbox(&mut self.snapping, "Snap");
+            ui.add_enabled_ui(self.snapping, |ui| {
+                ui.add(
+                    egui::DragValue::new(&mut self.translate_snap)
+                        .speed(0.01)
+                        .range(0.01..=10.0)
+                        .prefix("Move "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut self.rotate_snap)
+                        .speed(0.5)
+                        .range(1.0..=90.0)
+                        .prefix("Rotate ")
+                        .suffix("°"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut self.scale_snap)
+                        .speed(0.01)
+                        .range(0.01..=1.0)
+                        .prefix("Scale "),
+                );
+            });
+        });
+    }
+}
@@ -1,18 +1,23 @@
+use crate::asset::ktx::{load_ktx2, FormatSupport, KtxImage};
 use crate::asset::material::MaterialManager;
 use crate::asset::material::{Material, MaterialId, PbrMaterial, RawMaterial};
 use crate::asset::texture::TextureKind;
-use crate::asset::texture::{Texture, TextureManager};
+use crate::asset::texture::{Texture, TextureId, TextureManager};
 use crate::resource::immediate_submit::SubmitContext;
-use crate::scene::light::{Light, LightManager};
+use crate::scene::light::{Light, LightManager, LightMeta};
 use crate::scene::mesh::Mesh;
 use crate::scene::model::{Model, ModelId};
 use crate::scene::world::World;
 use ash::vk;
 use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use gltf::image::Source;
 use gltf::khr_lights_punctual::Kind;
+use gltf::material::AlphaMode;
+use gltf::texture::MagFilter;
 use hashbrown::HashMap;
-use log::info;
+use log::{error, info};
 use std::cell::RefCell;
+use std::fs;
 use std::path::Path;
 use std::rc::Rc;
 
@@ -24,10 +29,9 @@ pub struct GltfReader {
     material_mappings: HashMap<usize, MaterialId>,
 }
 
-struct ImageData {
-    width: u32,
-    height: u32,
-    data: Vec<u8>,
+enum ImageData {
+    Rgba8 { width: u32, height: u32, data: Vec<u8> },
+    Ktx(KtxImage), // from KHR_texture_basisu or plain KTX2 sources
 }
 
 impl GltfReader {
@@ -47,15 +51,14 @@ impl GltfReader {
     }
 
     pub fn load(&mut self, path: &Path, ctx: SubmitContext) {
-        let (gltf, buffers, images) = gltf::import(path).unwrap();
-        let images = images.iter().map(|image| image.to_rgba8()).collect::<Vec<_>>();
-        let images = images
-            .iter()
-            .map(|image| ImageData {
-                width: image.width(),
-                height: image.height(),
-                data: image.to_vec(),
-            })
+        // skip validation, it rejects files that require KHR_texture_basisu which the gltf crate doesn't know about
+        let gltf = gltf::Gltf::from_slice_without_validation(&fs::read(path).unwrap()).unwrap();
+        let base = path.parent();
+        let buffers = gltf::import_buffers(&gltf.document, base, gltf.blob.clone()).unwrap();
+        let format_support = self.texture_manager.borrow().format_support().clone();
+        let images = gltf
+            .images()
+            .map(|image| Self::load_image(image, base, &buffers, &format_support))
             .collect::<Vec<_>>();
         let device = ctx.device.clone();
         ctx.immediate_submit(Box::new(|ctx| {
@@ -69,11 +72,7 @@ impl GltfReader {
                 for child in node.children() {
                     self.world
                         .borrow_mut()
-                        .models
-                        .get_mut(model)
-                        .unwrap()
-                        .children
-                        .push(*mapping.get(&child.index()).unwrap());
+                        .set_parent(*mapping.get(&child.index()).unwrap(), Some(*model), None);
                 }
             }
             let models = self.world.borrow_mut().get_toplevel_model_ids().clone();
@@ -86,11 +85,52 @@ impl GltfReader {
         self.texture_manager.borrow_mut().update_set(&device);
     }
 
+    fn load_image(
+        image: gltf::Image,
+        base: Option<&Path>,
+        buffers: &[gltf::buffer::Data],
+        format_support: &FormatSupport,
+    ) -> Option<ImageData> {
+        let ktx_bytes = match image.source() {
+            Source::View { view, mime_type } if mime_type == "image/ktx2" => {
+                let buffer = &buffers[view.buffer().index()];
+                Some(Ok(buffer[view.offset()..view.offset() + view.length()].to_vec()))
+            }
+            Source::Uri { uri, mime_type } if mime_type == Some("image/ktx2") || uri.ends_with(".ktx2") => {
+                Some(fs::read(base.unwrap_or(Path::new(".")).join(uri)).map_err(|e| e.to_string()))
+            }
+            _ => None,
+        };
+        if let Some(bytes) = ktx_bytes {
+            return match bytes.and_then(|bytes| load_ktx2(&bytes, format_support).map_err(|e| e.to_string())) {
+                Ok(ktx) => Some(ImageData::Ktx(ktx)),
+                Err(e) => {
+                    error!("Failed to load KTX2 image {}: {}", image.index(), e);
+                    None
+                }
+            };
+        }
+        match gltf::image::Data::from_source(image.source(), base, buffers) {
+            Ok(image) => {
+                let image = image.to_rgba8();
+                Some(ImageData::Rgba8 {
+                    width: image.width(),
+                    height: image.height(),
+                    data: image.to_vec(),
+                })
+            }
+            Err(e) => {
+                error!("Failed to load image {}: {}", image.index(), e);
+                None
+            }
+        }
+    }
+
     fn load_model(
         &mut self,
         node: &gltf::Node,
         buffers: &[gltf::buffer::Data],
-        images: &[ImageData],
+        images: &[Option<ImageData>],
         ctx: &mut SubmitContext,
         parent_transform: Mat4,
     ) -> ModelId {
@@ -136,7 +176,15 @@ impl GltfReader {
                     let light = self.light_manager.borrow_mut().add_light(light, ctx, self.texture_manager.clone());
                     Model::new(Vec::new(), node_transform, Some(light), None, node.name().map(|x| x.to_string()))
                 }
-                _ => todo!("directional lights"),
+                Kind::Directional => {
+                    // glTF lights point along -Z
+                    let dir = node_transform.transform_vector3(-Vec3::Z).normalize();
+                    let mut light = Light::new(LightMeta::Directional, light.color(), light.intensity());
+                    light.data.direction = dir.extend(0.0).to_array();
+                    light.data.update_viewproj();
+                    let light = self.light_manager.borrow_mut().add_light(light, ctx, self.texture_manager.clone());
+                    Model::new(Vec::new(), node_transform, Some(light), None, node.name().map(|x| x.to_string()))
+                }
             }
         } else {
             Model::new(Vec::new(), node_transform, None, None, node.name().map(|x| x.to_string()))
@@ -199,37 +247,24 @@ impl GltfReader {
         self.world.borrow_mut().add_model(model)
     }
 
-    fn load_material(&mut self, material: gltf::Material, images: &[ImageData], ctx: &mut SubmitContext) -> MaterialId {
+    fn load_material(&mut self, material: gltf::Material, images: &[Option<ImageData>], ctx: &mut SubmitContext) -> MaterialId {
         if material.index().is_none() {
             return 0;
         }
 
         let pbr = material.pbr_metallic_roughness();
         let albedo = pbr.base_color_factor();
-        let texture = pbr.base_color_texture().map(|info| {
-            let image = images.get(info.texture().source().index()).unwrap();
-
-            let texture = ctx.nest(Box::new(|ctx| {
-                Texture::new_init(
-                    TextureManager::DEFAULT_SAMPLER_NEAREST,
-                    vk::Format::R8G8B8A8_SRGB,
-                    ctx,
-                    Some(info.texture().name().map(|x| x.to_string()).unwrap_or(format!(
-                        "Albedo, Material: {} ({})",
-                        material.name().unwrap_or_default(),
-                        self.material_manager.borrow().next_free_id()
-                    ))),
-                    &image.data,
-                    vk::Extent3D {
-                        width: image.width,
-                        height: image.height,
-                        depth: 1,
-                    },
-                    TextureKind::Color,
-                )
-            }));
-            self.texture_manager.borrow_mut().add_texture(texture, &ctx.device, false)
-        });
+        let texture = pbr
+            .base_color_texture()
+            .and_then(|info| self.load_texture(info.texture(), &material, "Albedo", vk::Format::R8G8B8A8_SRGB, images, ctx));
+        // normals aren't colors, so they're sampled linearly
+        let normal_texture = material
+            .normal_texture()
+            .and_then(|info| self.load_texture(info.texture(), &material, "Normal", vk::Format::R8G8B8A8_UNORM, images, ctx));
+        let alpha_cutoff = match material.alpha_mode() {
+            AlphaMode::Mask => material.alpha_cutoff().unwrap_or(0.5),
+            _ => 0.0,
+        };
         let engine_material = ctx.nest(Box::new(|ctx| {
             Material::new(
                 Some(material.name().unwrap_or_default().to_string()),
@@ -239,6 +274,8 @@ impl GltfReader {
                     albedo,
                     metallic: pbr.metallic_factor(),
                     roughness: pbr.roughness_factor(),
+                    normal_tex: normal_texture.unwrap_or(TextureManager::DEFAULT_TEXTURE_NORMAL),
+                    alpha_cutoff,
                 }),
                 ctx,
             )
@@ -248,4 +285,65 @@ impl GltfReader {
         self.material_mappings.insert(material.index().unwrap(), engine_material);
         engine_material
     }
+
+    /// Uploads the image of a texture the material references. `format` is the one RGBA8 images are uploaded in, KTX2
+    /// images carry their own.
+    fn load_texture(
+        &mut self,
+        texture: gltf::Texture,
+        material: &gltf::Material,
+        usage: &str,
+        format: vk::Format,
+        images: &[Option<ImageData>],
+        ctx: &mut SubmitContext,
+    ) -> Option<TextureId> {
+        // prefer the KHR_texture_basisu source, fall back to the regular one if it couldn't be loaded
+        let basisu_source = texture
+            .extension_value("KHR_texture_basisu")
+            .and_then(|ext| ext.get("source"))
+            .and_then(|source| source.as_u64());
+        let image = basisu_source
+            .and_then(|source| images.get(source as usize)?.as_ref())
+            .or_else(|| images.get(texture.source().index())?.as_ref())?;
+        let sampler = match texture.sampler().mag_filter() {
+            Some(MagFilter::Nearest) => TextureManager::DEFAULT_SAMPLER_NEAREST,
+            _ => TextureManager::DEFAULT_SAMPLER_LINEAR,
+        };
+        let mip_generation = self.texture_manager.borrow().mip_generation();
+        let label = Some(texture.name().map(|x| x.to_string()).unwrap_or(format!(
+            "{}, Material: {} ({})",
+            usage,
+            material.name().unwrap_or_default(),
+            self.material_manager.borrow().next_free_id()
+        )));
+
+        let texture = ctx.nest(Box::new(|ctx| match image {
+            ImageData::Rgba8 { width, height, data } => Texture::new_mipmapped(
+                sampler,
+                format,
+                ctx,
+                label,
+                data,
+                vk::Extent3D {
+                    width: *width,
+                    height: *height,
+                    depth: 1,
+                },
+                TextureKind::Color,
+                mip_generation,
+                false,
+            ),
+            ImageData::Ktx(ktx) => Texture::new_init_levels(
+                sampler,
+                ktx.format,
+                ctx,
+                label,
+                &ktx.levels(),
+                ktx.extent,
+                TextureKind::Color,
+                false,
+            ),
+        }));
+        Some(self.texture_manager.borrow_mut().add_texture(texture, &ctx.device, false))
+    }
 }
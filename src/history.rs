@@ -0,0 +1,529 @@
+use crate::asset::material::{MaterialId, RawMaterial};
+use crate::asset::texture::{TextureId, TextureManager};
+use crate::resource::immediate_submit::SubmitContext;
+use crate::scene::billboard::Billboard;
+use crate::scene::light::{Light, LightId, LightMeta, RawLight};
+use crate::scene::mesh::Mesh;
+use crate::scene::model::{Model, ModelId};
+use crate::scene::particles::EmitterSettings;
+use crate::App;
+use ash::vk;
+use glam::Mat4;
+
+/// A reversible editor operation. Holds both the state before and after the edit, so it can be applied in either direction.
+pub enum Edit {
+    Transform {
+        model: ModelId,
+        before: Mat4,
+        after: Mat4,
+    },
+    Light {
+        light: LightId,
+        before: RawLight,
+        after: RawLight,
+    },
+    LightType {
+        light: LightId,
+        before: LightMeta,
+        after: LightMeta,
+        data: RawLight, // the light's data before the switch, which undoing restores
+    },
+    Material {
+        material: MaterialId,
+        before: RawMaterial,
+        after: RawMaterial,
+    },
+    Billboard {
+        model: ModelId,
+        before: Billboard,
+        after: Billboard,
+    },
+    Emitter {
+        model: ModelId,
+        before: Option<EmitterSettings>, // None if the model had no emitter
+        after: Option<EmitterSettings>,
+    },
+    MeshMaterial {
+        model: ModelId,
+        mesh: usize,
+        before: MaterialId,
+        after: MaterialId,
+    },
+    Reparent {
+        model: ModelId,
+        before: Option<(ModelId, usize)>, // parent and position in its children
+        after: Option<(ModelId, usize)>,
+    },
+    Rename {
+        model: ModelId,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    Visibility {
+        model: ModelId,
+        before: bool, // whether the model was hidden
+        after: bool,
+    },
+    AddModel {
+        model: ModelId,
+        parent: Option<(ModelId, usize)>,
+        detached: Option<(Vec<Model>, Vec<Light>)>, // only set while the addition is undone
+    },
+    DeleteModel {
+        model: ModelId,
+        parent: Option<(ModelId, usize)>,           // parent and position in its children
+        detached: Option<(Vec<Model>, Vec<Light>)>, // only set while the deletion is applied
+    },
+    RemoveLight {
+        light: LightId,          // of a light without a model, others are deleted along with theirs
+        detached: Option<Light>, // only set while the removal is applied
+    },
+}
+
+impl Edit {
+    /// Records a model that has already been added, e.g. a duplicate.
+    pub fn add_model(model: ModelId) -> Self {
+        Edit::AddModel {
+            model,
+            parent: None,
+            detached: None,
+        }
+    }
+
+    pub fn delete_model(model: ModelId) -> Self {
+        Edit::DeleteModel {
+            model,
+            parent: None,
+            detached: None,
+        }
+    }
+
+    pub fn remove_light(light: LightId) -> Self {
+        Edit::RemoveLight { light, detached: None }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Edit::Transform { model, .. } => format!("Transform model {}", model),
+            Edit::Light { light, .. } => format!("Edit light {}", light),
+            Edit::LightType { light, after, .. } => format!("Make light {} a {}", light, after.name().to_lowercase()),
+            Edit::Material { material, .. } => format!("Edit material {}", material),
+            Edit::Billboard { model, .. } => format!("Edit billboard {}", model),
+            Edit::Emitter { model, before, after } => match (before, after) {
+                (None, Some(_)) => format!("Add particle emitter to model {}", model),
+                (Some(_), None) => format!("Remove particle emitter of model {}", model),
+                _ => format!("Edit particle emitter of model {}", model),
+            },
+            Edit::MeshMaterial { model, mesh, .. } => format!("Change material of mesh {} of model {}", mesh, model),
+            Edit::Reparent { model, after, .. } => match after {
+                Some((parent, _)) => format!("Move model {} under model {}", model, parent),
+                None => format!("Move model {} to the top level", model),
+            },
+            Edit::Rename { model, .. } => format!("Rename model {}", model),
+            Edit::Visibility { model, after, .. } => format!("{} model {}", if *after { "Hide" } else { "Show" }, model),
+            Edit::AddModel { model, .. } => format!("Add model {}", model),
+            Edit::DeleteModel { model, .. } => format!("Delete model {}", model),
+            Edit::RemoveLight { light, .. } => format!("Remove light {}", light),
+        }
+    }
+
+    /// Applies the edit, or reverts it if `undo` is set.
+    pub fn apply(&mut self, app: &App, undo: bool) {
+        let ctx = SubmitContext::from_app(app);
+        match self {
+            Edit::Transform { model, before, after } => {
+                let transform = if undo { *before } else { *after };
+                let mut world = app.world.borrow_mut();
+                let Some(target) = world.models.get_mut(model) else {
+                    return;
+                };
+                target.transform = transform;
+                let parent = world.parent_of(*model).map(|parent| world.world_transform(parent));
+                ctx.immediate_submit(Box::new(|ctx| {
+                    world.update_transforms(*model, parent.unwrap_or(Mat4::IDENTITY), &mut app.light_manager.borrow_mut(), ctx)
+                }));
+            }
+            Edit::Light { light, before, after } => {
+                let data = if undo { *before } else { *after };
+                if app.light_manager.borrow().get_light(*light).is_none() {
+                    return;
+                }
+                ctx.immediate_submit(Box::new(|ctx| {
+                    app.light_manager.borrow_mut().update_light(
+                        *light,
+                        |light| {
+                            // the position follows the model and the shadow map belongs to the light, keep both
+                            let (position, shadow_map) = (light.position, light.shadow_map);
+                            *light = data;
+                            light.position = position;
+                            light.shadow_map = shadow_map;
+                            light.update_viewproj();
+                        },
+                        ctx,
+                    );
+                }));
+            }
+            Edit::LightType {
+                light,
+                before,
+                after,
+                data,
+            } => {
+                let mut light_manager = app.light_manager.borrow_mut();
+                if light_manager.get_light(*light).is_none() {
+                    return;
+                }
+                let meta = if undo { *before } else { *after };
+                light_manager.set_meta(*light, meta);
+                ctx.immediate_submit(Box::new(|ctx| {
+                    light_manager.update_light(
+                        *light,
+                        |light| {
+                            if undo {
+                                let (position, shadow_map) = (light.position, light.shadow_map);
+                                *light = *data;
+                                light.position[..3].copy_from_slice(&position[..3]);
+                                light.shadow_map = shadow_map;
+                            }
+                            meta.apply(light);
+                        },
+                        ctx,
+                    )
+                }));
+            }
+            Edit::Material { material, before, after } => {
+                let data = if undo { before.clone() } else { after.clone() };
+                let mut material_manager = app.material_manager.borrow_mut();
+                let Some(material) = material_manager.get_material_mut(*material) else {
+                    return;
+                };
+                {
+                    let mut texture_manager = app.texture_manager.borrow_mut();
+                    for texture in data.textures() {
+                        texture_manager.acquire(texture);
+                    }
+                    for texture in material.data.textures() {
+                        texture_manager.release(texture);
+                    }
+                }
+                ctx.immediate_submit(Box::new(|ctx| material.update(|m| *m = data, ctx)));
+            }
+            Edit::Billboard { model, before, after } => {
+                let billboard = if undo { before.clone() } else { after.clone() };
+                app.world.borrow_mut().update_billboard(*model, billboard);
+            }
+            Edit::Emitter { model, before, after } => {
+                let emitter = if undo { before.clone() } else { after.clone() };
+                if let Some(model) = app.world.borrow_mut().models.get_mut(model) {
+                    model.emitter = emitter;
+                }
+            }
+            Edit::MeshMaterial {
+                model,
+                mesh,
+                before,
+                after,
+            } => {
+                let material = if undo { *before } else { *after };
+                if let Some(mesh) = app
+                    .world
+                    .borrow_mut()
+                    .models
+                    .get_mut(model)
+                    .and_then(|model| model.meshes.get_mut(*mesh))
+                {
+                    mesh.material = material;
+                }
+            }
+            Edit::Reparent { model, before, after } => {
+                let placement = if undo { *before } else { *after };
+                let mut world = app.world.borrow_mut();
+                // keeps the world transform, so undoing restores the local one
+                if !world.reparent(*model, placement.map(|(parent, _)| parent), placement.map(|(_, index)| index)) {
+                    return;
+                }
+                let parent = placement.map(|(parent, _)| world.world_transform(parent));
+                ctx.immediate_submit(Box::new(|ctx| {
+                    world.update_transforms(*model, parent.unwrap_or(Mat4::IDENTITY), &mut app.light_manager.borrow_mut(), ctx)
+                }));
+            }
+            Edit::Rename { model, before, after } => {
+                let label = if undo { before.clone() } else { after.clone() };
+                if let Some(model) = app.world.borrow_mut().models.get_mut(model) {
+                    model.label = label;
+                }
+            }
+            Edit::Visibility { model, before, after } => {
+                let hidden = if undo { *before } else { *after };
+                let mut world = app.world.borrow_mut();
+                if let Some(model) = world.models.get_mut(model) {
+                    model.hidden = hidden;
+                }
+                world.billboards_changed();
+            }
+            Edit::AddModel { model, parent, detached } => {
+                if undo {
+                    Self::detach(app, ctx, *model, parent, detached);
+                } else {
+                    Self::attach(app, ctx, *parent, detached);
+                }
+            }
+            Edit::DeleteModel { model, parent, detached } => {
+                if undo {
+                    Self::attach(app, ctx, *parent, detached);
+                } else {
+                    Self::detach(app, ctx, *model, parent, detached);
+                }
+            }
+            Edit::RemoveLight { light, detached } => {
+                ctx.immediate_submit(Box::new(|ctx| {
+                    let mut light_manager = app.light_manager.borrow_mut();
+                    if undo {
+                        if let Some(light) = detached.take() {
+                            light_manager.restore_light(light, ctx, app.texture_manager.clone());
+                        }
+                    } else {
+                        *detached = light_manager.take_light(*light, ctx, &mut app.texture_manager.borrow_mut());
+                    }
+                }));
+            }
+        }
+    }
+
+    // Takes the model's subtree and its lights out of the world, remembering where it was.
+    fn detach(
+        app: &App,
+        ctx: SubmitContext,
+        model: ModelId,
+        parent: &mut Option<(ModelId, usize)>,
+        detached: &mut Option<(Vec<Model>, Vec<Light>)>,
+    ) {
+        let mut world = app.world.borrow_mut();
+        *parent = world.placement(model);
+        let models = world.detach_subtree(model);
+        let lights = ctx.immediate_submit(Box::new(|ctx| {
+            models
+                .iter()
+                .filter_map(|model| model.light)
+                .filter_map(|light| {
+                    app.light_manager
+                        .borrow_mut()
+                        .take_light(light, ctx, &mut app.texture_manager.borrow_mut())
+                })
+                .collect::<Vec<_>>()
+        }));
+        *detached = Some((models, lights));
+    }
+
+    // Puts a subtree taken out by detach back.
+    fn attach(app: &App, ctx: SubmitContext, parent: Option<(ModelId, usize)>, detached: &mut Option<(Vec<Model>, Vec<Light>)>) {
+        let Some((models, lights)) = detached.take() else {
+            return;
+        };
+        ctx.immediate_submit(Box::new(|ctx| {
+            let mut light_manager = app.light_manager.borrow_mut();
+            for light in lights {
+                light_manager.restore_light(light, ctx, app.texture_manager.clone());
+            }
+        }));
+        app.world.borrow_mut().attach_subtree(models, parent);
+    }
+
+    // Merges a subsequent edit of the same target into this one, e.g. the steps of a continuous drag.
+    fn merge(&mut self, next: &Edit, texture_manager: &mut TextureManager) -> bool {
+        match (self, next) {
+            (
+                Edit::Transform { model, after, .. },
+                Edit::Transform {
+                    model: next,
+                    after: next_after,
+                    ..
+                },
+            ) if model == next => {
+                *after = *next_after;
+            }
+            (
+                Edit::Light { light, after, .. },
+                Edit::Light {
+                    light: next,
+                    after: next_after,
+                    ..
+                },
+            ) if light == next => {
+                *after = *next_after;
+            }
+            (
+                Edit::Material { material, after, .. },
+                Edit::Material {
+                    material: next,
+                    after: next_after,
+                    ..
+                },
+            ) if material == next => {
+                for texture in next_after.textures() {
+                    texture_manager.acquire(texture);
+                }
+                for texture in after.textures() {
+                    texture_manager.release(texture);
+                }
+                *after = next_after.clone();
+            }
+            (
+                Edit::Billboard { model, after, .. },
+                Edit::Billboard {
+                    model: next,
+                    after: next_after,
+                    ..
+                },
+            ) if model == next => {
+                *after = next_after.clone();
+            }
+            (
+                Edit::Emitter { model, after, .. },
+                Edit::Emitter {
+                    model: next,
+                    after: next_after,
+                    ..
+                },
+            ) if model == next && after.is_some() && next_after.is_some() => {
+                *after = next_after.clone();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Keeps the textures referenced by the edit alive while it's in the history.
+    fn retain(&self, texture_manager: &mut TextureManager) {
+        if let Edit::Material { before, after, .. } = self {
+            for texture in before.textures().into_iter().chain(after.textures()) {
+                texture_manager.acquire(texture);
+            }
+        }
+    }
+
+    // GPU memory kept alive by the edit, i.e. the mesh buffers of models it took out of the world.
+    fn retained_bytes(&self) -> vk::DeviceSize {
+        match self {
+            Edit::AddModel {
+                detached: Some((models, _)),
+                ..
+            }
+            | Edit::DeleteModel {
+                detached: Some((models, _)),
+                ..
+            } => models.iter().flat_map(|model| &model.meshes).map(Mesh::gpu_size).sum(),
+            _ => 0,
+        }
+    }
+
+    /// Drops everything the edit holds on to. Called once the edit falls out of the history.
+    pub fn discard(self, app: &App) {
+        match self {
+            Edit::Material { before, after, .. } => {
+                let mut texture_manager = app.texture_manager.borrow_mut();
+                for texture in before.textures().into_iter().chain(after.textures()) {
+                    texture_manager.release(texture);
+                }
+            }
+            Edit::AddModel {
+                detached: Some((models, _lights)),
+                ..
+            }
+            | Edit::DeleteModel {
+                detached: Some((models, _lights)),
+                ..
+            } => {
+                app.world.borrow_mut().discard_models(models);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Undo/redo stacks of applied edits.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    sealed: bool, // whether the next edit starts a new entry even if it targets the same thing as the last one
+}
+
+impl History {
+    const MAX_ENTRIES: usize = 100;
+    const MAX_RETAINED_BYTES: vk::DeviceSize = 256 * 1024 * 1024; // of deleted meshes, see Edit::retained_bytes
+
+    /// Records an edit that has already been applied. Returns the edits that fell out of the history, which have to be
+    /// [discarded](Edit::discard).
+    pub fn record(&mut self, edit: Edit, texture_manager: &mut TextureManager) -> Vec<Edit> {
+        let mut dropped = self.redo.drain(..).collect::<Vec<_>>();
+        if !self.sealed {
+            if let Some(last) = self.undo.last_mut() {
+                if last.merge(&edit, texture_manager) {
+                    return dropped;
+                }
+            }
+        }
+        edit.retain(texture_manager);
+        self.undo.push(edit);
+        self.sealed = false;
+        if self.undo.len() > Self::MAX_ENTRIES {
+            dropped.push(self.undo.remove(0));
+        }
+        // the latest edit stays undoable however much it holds on to
+        let mut retained = self.undo.iter().map(Edit::retained_bytes).sum::<vk::DeviceSize>();
+        while retained > Self::MAX_RETAINED_BYTES && self.undo.len() > 1 {
+            let edit = self.undo.remove(0);
+            retained -= edit.retained_bytes();
+            dropped.push(edit);
+        }
+        dropped
+    }
+
+    /// Ends the current entry, so that the next edit isn't merged into it (e.g. when a drag ends).
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    pub fn take_undo(&mut self) -> Option<Edit> {
+        self.sealed = true;
+        self.undo.pop()
+    }
+
+    pub fn take_redo(&mut self) -> Option<Edit> {
+        self.sealed = true;
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, edit: Edit) {
+        self.undo.push(edit);
+    }
+
+    pub fn push_redo(&mut self, edit: Edit) {
+        self.redo.push(edit);
+    }
+
+    /// Points the material edits away from a texture that's about to be deleted, like `RawMaterial::drop_texture`.
+    pub fn drop_texture(&mut self, texture: TextureId) {
+        for edit in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            if let Edit::Material { before, after, .. } = edit {
+                before.drop_texture(texture);
+                after.drop_texture(texture);
+            }
+        }
+    }
+
+    /// Empties both stacks, e.g. when loading a new scene. Returns the edits that have to be discarded.
+    pub fn clear(&mut self) -> Vec<Edit> {
+        self.sealed = true;
+        self.undo.drain(..).chain(self.redo.drain(..)).collect()
+    }
+
+    pub fn undo_entries(&self) -> impl Iterator<Item = &Edit> {
+        self.undo.iter()
+    }
+
+    pub fn redo_entries(&self) -> impl Iterator<Item = &Edit> {
+        self.redo.iter().rev()
+    }
+}
@@ -3,10 +3,14 @@ extern crate core;
 mod asset;
 mod camera;
 mod commands;
+mod gizmo;
 mod gltf;
+mod history;
 mod pipeline;
+mod render_graph;
 mod resource;
 mod scene;
+mod selection;
 mod ui;
 mod util;
 
@@ -17,14 +21,26 @@ use ash::vk::{DescriptorSet, DescriptorSetLayout};
 use ash::{khr, vk, Device, Instance};
 use resource::immediate_submit::SubmitContext;
 
+use crate::asset::icon_atlas::IconAtlas;
 use crate::asset::material::MaterialManager;
+use crate::camera::Ray;
 use crate::commands::{Command, CommandHandler};
+use crate::gizmo::{Gizmo, GizmoMode};
 use crate::gltf::GltfReader;
+use crate::history::History;
 use crate::pipeline::billboard::BillboardPipeline;
-use asset::texture::TextureManager;
+use crate::pipeline::cache::PipelineCache;
+use crate::pipeline::compiler::ShaderCompiler;
+use crate::pipeline::particles::ParticlePipeline;
+use crate::render_graph::{BufferUsage, ImageDesc, ImageUsage, RenderGraph, RenderGraphInfo, TransientImages};
+use crate::scene::billboard::SizeMode;
+use asset::ktx::FormatSupport;
+use asset::texture::{MipGeneration, TextureManager, TEXTURE_IMAGE_FORMAT};
+use glam::{Mat4, Vec2};
 use gpu_alloc::GpuAllocator;
 use gpu_alloc_ash::device_properties;
-use log::{debug, info};
+use log::{debug, info, warn};
+#[cfg(feature = "watch")]
 use notify::Watcher;
 use pipeline::GpuSceneData;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
@@ -35,6 +51,7 @@ use std::error::Error;
 use std::path::Path;
 use std::rc::Rc;
 use std::sync::mpsc;
+use std::time::Instant;
 use util::FrameData;
 use winit::event::{ElementState, KeyEvent};
 use winit::keyboard::{KeyCode, PhysicalKey};
@@ -48,10 +65,13 @@ use winit::{
 use crate::pipeline::egui::EguiPipeline;
 use crate::pipeline::grid::GridPipeline;
 use crate::pipeline::mesh::MeshPipeline;
+use crate::pipeline::outline::OutlinePipeline;
 use crate::pipeline::shadow_mapping::ShadowMappingPipeline;
 
 use crate::scene::light::{LightId, LightManager};
+use crate::scene::particles::ParticleSystem;
 use crate::scene::world::World;
+use crate::selection::Selection;
 use crate::ui::Gui;
 
 const FRAME_OVERLAP: usize = 2;
@@ -76,41 +96,62 @@ struct App {
     main_deletion_queue: DeletionQueue,
     draw_image: Option<AllocatedImage>,
     unorm_draw_image_view: vk::ImageView,
+    depth_format: vk::Format, // of the depth buffer, whose stencil is used for selection outlines
     bindless_descriptor_pool: vk::DescriptorPool,
     mesh_pipeline: MeshPipeline,
     egui_pipeline: EguiPipeline,
     grid_pipeline: GridPipeline,
     billboard_pipeline: BillboardPipeline,
+    particle_pipeline: ParticlePipeline,
+    outline_pipeline: OutlinePipeline,
     immediate_fence: vk::Fence,
     immediate_command_pool: vk::CommandPool,
     immediate_command_buffer: vk::CommandBuffer,
-    depth_image: Option<AllocatedImage>,
+    transient_images: TransientImages,  // e.g. the depth buffer, see RenderGraph
+    render_graph_info: RenderGraphInfo, // of the last frame, for the debug UI
     scene_data: WrappedBuffer<GpuSceneData>,
     texture_manager: Rc<RefCell<TextureManager>>,
     material_manager: Rc<RefCell<MaterialManager>>,
     light_manager: Rc<RefCell<LightManager>>,
+    icons: IconAtlas,
     camera: camera::Camera,
     world: Rc<RefCell<World>>,
+    particles: ParticleSystem,
+    last_update: Instant, // when particles were last advanced
     settings: AppSettings,
     gui: Gui,
+    history: History,
+    gizmo: Gizmo,
+    selection: Selection,
+    cursor_position: (f32, f32),        // in window pixels
+    press_position: Option<(f32, f32)>, // where the left button went down in the viewport, to tell clicks from drags
+    modifiers: winit::keyboard::ModifiersState,
     cmd_sender: mpsc::Sender<Command>,
     bindless_set_layout: DescriptorSetLayout,
     pipeline_deletion_queue: DeletionQueue,
+    pipeline_cache: PipelineCache,
     shadow_mapping_pipeline: ShadowMappingPipeline,
+    shader_compiler: ShaderCompiler,
 }
 
 struct AppSettings {
     show_gui: bool,
     show_grid: bool,
     view_as_light: bool,
+    shadow_pcf: bool,                     // filter shadow edges, see MeshVariant
+    msaa: vk::SampleCountFlags,           // samples per pixel of the scene passes, TYPE_1 if off, see App::set_msaa
+    supported_msaa: vk::SampleCountFlags, // what the device supports for the draw and depth images
+    outline_color: [f32; 4],
+    outline_width: f32, // in pixels
 }
 
 pub const SWAPCHAIN_IMAGE_FORMAT: vk::Format = vk::Format::B8G8R8A8_SRGB;
 pub const DRAW_IMAGE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
-pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
 pub const API_VERSION: u32 = vk::make_api_version(0, 1, 3, 0);
 
 impl App {
+    const CLICK_TOLERANCE: f32 = 4.0; // how far the cursor may move between press and release for a click, in pixels
+
     fn new(event_loop: &EventLoop<()>, cmd_sender: mpsc::Sender<Command>) -> Result<Self, Box<dyn Error>> {
         let window_size = (1500, 850);
         let (instance, surface_khr, surface, entry, window) = unsafe {
@@ -148,12 +189,28 @@ impl App {
         let mut allocator = GpuAllocator::new(config, device_properties);
 
         let capabilities = unsafe { surface.get_physical_device_surface_capabilities(physical_device, surface_khr) }?;
-        let ((swapchain, swapchain_khr), swapchain_images, swapchain_views, draw_image, unorm_draw_image_view, depth_image) =
+        let ((swapchain, swapchain_khr), swapchain_images, swapchain_views, draw_image, unorm_draw_image_view) =
             Self::create_swapchain(&instance, &device, surface_khr, capabilities, &mut allocator, window_size);
         let (immediate_command_pool, immediate_command_buffer, immediate_fence, frames) =
             Self::init_commands(graphics_queue.1, &device, &mut deletion_queue);
         let (bindless_descriptor_pool, bindless_descriptor_set, bindless_set_layout) = Self::init_bindless(&device);
-        let grid_pipeline = GridPipeline::new(&device, window_size, &mut deletion_queue);
+        let mip_generation = if device_discovery::supports_linear_blit(&instance, physical_device, TEXTURE_IMAGE_FORMAT) {
+            MipGeneration::Blit
+        } else {
+            MipGeneration::Cpu
+        };
+        let format_support = FormatSupport::query(&instance, physical_device);
+        // falls back to the last compiled shaders if any of them doesn't compile
+        let mut shader_compiler = ShaderCompiler::new();
+        shader_compiler.compile_all();
+        let pipeline_cache = PipelineCache::load(&instance, physical_device, &device);
+        let supported_msaa = device_discovery::supported_sample_counts(&instance, physical_device);
+        let depth_format = device_discovery::pick_depth_format(&instance, physical_device);
+        let msaa = if supported_msaa.contains(vk::SampleCountFlags::TYPE_4) {
+            vk::SampleCountFlags::TYPE_4
+        } else {
+            vk::SampleCountFlags::TYPE_1
+        };
         let mut scene_data_buffer = WrappedBuffer {
             dirty: false,
             buffer: AllocatedBuffer::new(
@@ -187,15 +244,17 @@ impl App {
         )
         .immediate_submit(Box::new(|ctx| scene_data_buffer.write(ctx)));
 
-        let texture_manager = SubmitContext::new(
+        let mut texture_manager = SubmitContext::new(
             device.clone(),
             allocator.clone(),
             immediate_fence,
             immediate_command_buffer,
             graphics_queue.0,
         )
-        .immediate_submit(Box::new(|ctx| TextureManager::new(bindless_descriptor_set, ctx)));
-        let material_manager = SubmitContext::new(
+        .immediate_submit(Box::new(|ctx| {
+            TextureManager::new(bindless_descriptor_set, ctx, mip_generation, format_support)
+        }));
+        let mut material_manager = SubmitContext::new(
             device.clone(),
             allocator.clone(),
             immediate_fence,
@@ -204,17 +263,58 @@ impl App {
         )
         .immediate_submit(Box::new(|ctx| MaterialManager::new(ctx)));
 
+        let icons = SubmitContext::new(
+            device.clone(),
+            allocator.clone(),
+            immediate_fence,
+            immediate_command_buffer,
+            graphics_queue.0,
+        )
+        .immediate_submit(Box::new(|ctx| {
+            IconAtlas::load(Path::new(IconAtlas::PATH), ctx, &mut texture_manager, &mut material_manager)
+        }))
+        .unwrap_or_else(|e| {
+            warn!("Editor icons unavailable: {}", e);
+            IconAtlas::default()
+        });
+        let mut world = World::default();
+        if let Some(mut billboard) = icons.billboard(IconAtlas::LIGHT, Vec2::splat(12.0)) {
+            // editor icons stay the same size on screen and visible through geometry
+            billboard.size_mode = SizeMode::Screen;
+            billboard.ignore_depth = true;
+            world.set_light_icon(billboard);
+        }
+
         let light_manager = LightManager::new(&device, &mut allocator.borrow_mut());
 
         let camera = camera::Camera::new(window_size.0 as f32, window_size.1 as f32);
 
         let mut pipeline_deletion_queue = DeletionQueue::default();
 
-        let mesh_pipeline = MeshPipeline::new(&device, window_size, &mut pipeline_deletion_queue, bindless_set_layout);
+        let grid_pipeline = GridPipeline::new(
+            &device,
+            window_size,
+            &mut pipeline_deletion_queue,
+            pipeline_cache.cache,
+            msaa,
+            depth_format,
+        );
+        let mesh_pipeline = MeshPipeline::new(
+            &device,
+            window_size,
+            &mut pipeline_deletion_queue,
+            pipeline_cache.cache,
+            msaa,
+            depth_format,
+            bindless_set_layout,
+            &mut shader_compiler,
+            [],
+        );
         let egui_pipeline = EguiPipeline::new(
             &device,
             window_size,
             &mut pipeline_deletion_queue,
+            pipeline_cache.cache,
             bindless_set_layout,
             &window,
             SubmitContext::new(
@@ -225,8 +325,34 @@ impl App {
                 graphics_queue.0,
             ),
         );
-        let billboard_pipeline = BillboardPipeline::new(&device, window_size, &mut pipeline_deletion_queue, bindless_set_layout);
-        let shadow_mapping_pipeline = ShadowMappingPipeline::new(&device, &mut pipeline_deletion_queue, bindless_set_layout);
+        let billboard_pipeline = BillboardPipeline::new(
+            &device,
+            window_size,
+            &mut pipeline_deletion_queue,
+            pipeline_cache.cache,
+            msaa,
+            depth_format,
+            bindless_set_layout,
+        );
+        let particle_pipeline = ParticlePipeline::new(
+            &device,
+            window_size,
+            &mut pipeline_deletion_queue,
+            pipeline_cache.cache,
+            msaa,
+            depth_format,
+            bindless_set_layout,
+        );
+        let outline_pipeline = OutlinePipeline::new(
+            &device,
+            window_size,
+            &mut pipeline_deletion_queue,
+            pipeline_cache.cache,
+            msaa,
+            depth_format,
+        );
+        let shadow_mapping_pipeline =
+            ShadowMappingPipeline::new(&device, &mut pipeline_deletion_queue, pipeline_cache.cache, bindless_set_layout);
 
         info!("Init done.");
 
@@ -248,17 +374,23 @@ impl App {
             window_size,
             allocator,
             pipeline_deletion_queue,
+            pipeline_cache,
             main_deletion_queue: deletion_queue,
             draw_image: Some(draw_image), // must be present at all times, Option<_> because we need ownership when destroying
             unorm_draw_image_view,
-            depth_image: Some(depth_image),
+            depth_format,
+            transient_images: TransientImages::default(),
+            render_graph_info: RenderGraphInfo::default(),
             bindless_set_layout,
             bindless_descriptor_pool,
             mesh_pipeline,
             egui_pipeline,
             grid_pipeline,
             billboard_pipeline,
+            particle_pipeline,
+            outline_pipeline,
             shadow_mapping_pipeline,
+            shader_compiler,
             immediate_command_pool,
             immediate_command_buffer,
             immediate_fence,
@@ -266,34 +398,81 @@ impl App {
             texture_manager: Rc::new(RefCell::new(texture_manager)),
             material_manager: Rc::new(RefCell::new(material_manager)),
             light_manager: Rc::new(RefCell::new(light_manager)),
+            icons,
             camera,
             settings: AppSettings {
                 show_gui: true,
                 show_grid: false,
                 view_as_light: false,
+                shadow_pcf: false,
+                msaa,
+                supported_msaa,
+                outline_color: [1.0, 0.6, 0.1, 1.0],
+                outline_width: 2.0,
             },
             gui: Gui::new(cmd_sender.clone()),
-            world: Rc::new(RefCell::new(World::default())),
+            history: History::default(),
+            gizmo: Gizmo::default(),
+            selection: Selection::default(),
+            cursor_position: (0.0, 0.0),
+            press_position: None,
+            modifiers: Default::default(),
+            world: Rc::new(RefCell::new(world)),
+            particles: ParticleSystem::default(),
+            last_update: Instant::now(),
             cmd_sender,
         })
     }
 
-    fn recreate_pipelines(&mut self) {
+    /// Recompiles the shaders and recreates the pipelines from them. If a shader doesn't compile, the current pipelines
+    /// are kept and the errors are shown in the GUI. Returns whether the pipelines were recreated.
+    #[cfg(feature = "watch")]
+    fn recreate_pipelines(&mut self) -> bool {
+        if !self.shader_compiler.compile_all() {
+            return false;
+        }
+        self.rebuild_pipelines();
+        true
+    }
+
+    /// Switches the scene passes to `samples` per pixel. Counts that aren't in `AppSettings::supported_msaa` are ignored.
+    fn set_msaa(&mut self, samples: vk::SampleCountFlags) {
+        if samples == self.settings.msaa {
+            return;
+        }
+        if samples.as_raw().count_ones() != 1 || !self.settings.supported_msaa.contains(samples) {
+            warn!("Ignoring unsupported MSAA sample count {:?}", samples);
+            return;
+        }
+        self.settings.msaa = samples;
+        self.rebuild_pipelines();
+        info!("Switched to {} samples per pixel.", samples.as_raw());
+    }
+
+    /// Recreates all pipelines from the compiled shaders, once the GPU is idle.
+    fn rebuild_pipelines(&mut self) {
         unsafe {
             self.device.device_wait_idle().unwrap();
         }
         self.pipeline_deletion_queue.flush(&self.device, &mut self.allocator.borrow_mut());
         self.egui_pipeline.destroy(&self.device, &mut self.allocator.borrow_mut());
+        let mesh_variants = self.mesh_pipeline.variants();
         self.mesh_pipeline = MeshPipeline::new(
             &self.device,
             self.window_size,
             &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
+            self.settings.msaa,
+            self.depth_format,
             self.bindless_set_layout,
+            &mut self.shader_compiler,
+            mesh_variants,
         );
         self.egui_pipeline = EguiPipeline::new(
             &self.device,
             self.window_size,
             &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
             self.bindless_set_layout,
             &self.window,
             SubmitContext::new(
@@ -308,10 +487,43 @@ impl App {
             &self.device,
             self.window_size,
             &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
+            self.settings.msaa,
+            self.depth_format,
             self.bindless_set_layout,
         );
-        self.shadow_mapping_pipeline =
-            ShadowMappingPipeline::new(&self.device, &mut self.pipeline_deletion_queue, self.bindless_set_layout);
+        self.particle_pipeline = ParticlePipeline::new(
+            &self.device,
+            self.window_size,
+            &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
+            self.settings.msaa,
+            self.depth_format,
+            self.bindless_set_layout,
+        );
+        self.shadow_mapping_pipeline = ShadowMappingPipeline::new(
+            &self.device,
+            &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
+            self.bindless_set_layout,
+        );
+        self.outline_pipeline = OutlinePipeline::new(
+            &self.device,
+            self.window_size,
+            &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
+            self.settings.msaa,
+            self.depth_format,
+        );
+        self.grid_pipeline = GridPipeline::new(
+            &self.device,
+            self.window_size,
+            &mut self.pipeline_deletion_queue,
+            self.pipeline_cache.cache,
+            self.settings.msaa,
+            self.depth_format,
+        );
+        self.pipeline_cache.save(&self.device);
         self.resize(self.window_size);
     }
 
@@ -344,7 +556,10 @@ impl App {
             })
             .collect::<Vec<_>>();
 
-        let device_features = vk::PhysicalDeviceFeatures::default().geometry_shader(true);
+        let supported_features = unsafe { instance.get_physical_device_features(device) };
+        let device_features = vk::PhysicalDeviceFeatures::default()
+            .geometry_shader(true)
+            .texture_compression_bc(supported_features.texture_compression_bc == vk::TRUE);
         let mut vk12_features = vk::PhysicalDeviceVulkan12Features::default()
             .buffer_device_address(true)
             .scalar_block_layout(true)
@@ -398,7 +613,6 @@ impl App {
         Vec<vk::ImageView>,
         AllocatedImage,
         vk::ImageView,
-        AllocatedImage,
     ) {
         let create_info = vk::SwapchainCreateInfoKHR {
             surface: surface_khr,
@@ -453,6 +667,8 @@ impl App {
                 height: window_size.1,
                 depth: 1,
             },
+            1,
+            vk::SampleCountFlags::TYPE_1, // multisampled rendering is resolved into it
             DRAW_IMAGE_FORMAT,
             vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::COLOR_ATTACHMENT,
             AllocUsage::GpuOnly,
@@ -486,30 +702,7 @@ impl App {
                 .unwrap()
         };
 
-        let depth_image = AllocatedImage::new(
-            device,
-            allocator,
-            vk::Extent3D {
-                width: window_size.0,
-                height: window_size.1,
-                depth: 1,
-            },
-            DEPTH_FORMAT,
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-            AllocUsage::GpuOnly,
-            vk::ImageAspectFlags::DEPTH,
-            vk::ImageCreateFlags::empty(),
-            Some("Depth Image".into()),
-        );
-
-        (
-            (swapchain, swapchain_khr),
-            images,
-            image_views,
-            draw_image,
-            unorm_draw_image_view,
-            depth_image,
-        )
+        ((swapchain, swapchain_khr), images, image_views, draw_image, unorm_draw_image_view)
     }
 
     fn init_commands(
@@ -678,6 +871,8 @@ impl App {
         self.resize_swapchain(size);
         self.mesh_pipeline.resize(size);
         self.billboard_pipeline.resize(size);
+        self.particle_pipeline.resize(size);
+        self.outline_pipeline.resize(size);
         self.egui_pipeline.resize(size);
         self.grid_pipeline.resize(size);
         self.camera.resize(size.0 as f32, size.1 as f32);
@@ -690,10 +885,6 @@ impl App {
                 .take()
                 .unwrap()
                 .destroy(&self.device, &mut self.allocator.borrow_mut());
-            self.depth_image
-                .take()
-                .unwrap()
-                .destroy(&self.device, &mut self.allocator.borrow_mut());
             self.device.destroy_image_view(self.unorm_draw_image_view, None);
         }
         self.window_size = size;
@@ -702,7 +893,7 @@ impl App {
                 .get_physical_device_surface_capabilities(self.physical_device, self.surface)
                 .unwrap()
         };
-        let (swapchain, swapchain_images, swapchain_views, draw_image, unorm_draw_image_view, depth_image) = Self::create_swapchain(
+        let (swapchain, swapchain_images, swapchain_views, draw_image, unorm_draw_image_view) = Self::create_swapchain(
             &self.instance,
             &self.device,
             self.surface,
@@ -714,7 +905,6 @@ impl App {
         self.swapchain_images = swapchain_images;
         self.swapchain_views = swapchain_views;
         self.draw_image = Some(draw_image);
-        self.depth_image = Some(depth_image);
         self.unorm_draw_image_view = unorm_draw_image_view;
     }
 
@@ -730,6 +920,10 @@ impl App {
                 .unwrap();
             let device = self.device.clone();
             frame!(self).deletion_queue.flush(&device, &mut self.allocator.borrow_mut());
+            self.texture_manager.borrow_mut().collect_garbage(&mut frame!(self).deletion_queue);
+            self.world.borrow_mut().collect_garbage(&mut frame!(self).deletion_queue);
+            self.particles.collect_garbage(&mut frame!(self).deletion_queue);
+            self.transient_images.collect_garbage(&mut frame!(self).deletion_queue);
             frame!(self).descriptor_allocator.clear_pools(&device);
             for buffer in frame!(self).stale_buffers.drain(..) {
                 buffer.destroy(&device, &mut self.allocator.borrow_mut());
@@ -756,162 +950,7 @@ impl App {
             let begin_info = vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
             self.device.begin_command_buffer(cmd_buffer, &begin_info).unwrap();
 
-            // transition draw image into writable mode before rendering. undefined = we don't care, we're fine with the GPU destroying the image. general = general purpose layout which allows reading and writing from the image.
-            util::transition_image(
-                &self.device,
-                cmd_buffer,
-                self.draw_image.as_ref().unwrap().image,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::GENERAL,
-            );
-
-            self.draw_background(cmd_buffer);
-
-            util::transition_image(
-                &self.device,
-                cmd_buffer,
-                self.draw_image.as_ref().unwrap().image,
-                vk::ImageLayout::GENERAL,
-                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            );
-            util::transition_image(
-                &self.device,
-                cmd_buffer,
-                self.depth_image.as_ref().unwrap().image,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
-            );
-            {
-                // todo don't do this on every frame, only when scene changes
-                let texture_manager = self.texture_manager.borrow();
-                for light in self.light_manager.borrow().iter().filter(|light| light.data.shadow_map != 0) {
-                    let shadow_map = texture_manager.get_texture(light.data.shadow_map).unwrap();
-                    self.shadow_mapping_pipeline.draw(
-                        &self.device,
-                        cmd_buffer,
-                        &self.world.borrow().get_meshes(),
-                        shadow_map.image.view,
-                        self.texture_manager.borrow().descriptor_set(),
-                        self.scene_data.buffer.device_address(&self.device),
-                        &self.light_manager.borrow(),
-                        light.id,
-                    )
-                }
-            }
-
-            self.mesh_pipeline.draw(
-                &self.device,
-                cmd_buffer,
-                &self.world.borrow().get_meshes(),
-                self.draw_image.as_ref().unwrap().view,
-                self.depth_image.as_ref().unwrap().view,
-                self.texture_manager.borrow().descriptor_set(),
-                self.scene_data.buffer.device_address(&self.device),
-                &self.material_manager.borrow(),
-                &self.light_manager.borrow(),
-            );
-            {
-                let world = self.world.borrow();
-                let billboards = world.get_billboards();
-                self.billboard_pipeline.draw(
-                    &self.device,
-                    cmd_buffer,
-                    &billboards,
-                    self.draw_image.as_ref().unwrap().view,
-                    self.depth_image.as_ref().unwrap().view,
-                    self.texture_manager.borrow().descriptor_set(),
-                    self.scene_data.buffer.device_address(&self.device),
-                    &self.material_manager.borrow(),
-                    &self.light_manager.borrow(),
-                );
-            }
-
-            if self.settings.show_grid {
-                self.grid_pipeline.draw(
-                    &self.device,
-                    cmd_buffer,
-                    self.draw_image.as_ref().unwrap().view,
-                    self.scene_data.buffer.device_address(&self.device),
-                );
-            }
-
-            if self.settings.show_gui {
-                let ctx = SubmitContext::from_app(self);
-                self.egui_pipeline.begin_frame(&self.window);
-
-                self.gui.draw(
-                    self.egui_pipeline.context().clone(),
-                    &mut self.settings,
-                    &mut self.camera,
-                    self.world.clone(),
-                    self.texture_manager.clone(),
-                    self.material_manager.clone(),
-                    self.light_manager.clone(),
-                    ctx.clone(),
-                );
-
-                let output = self.egui_pipeline.end_frame(
-                    &self.window,
-                    &mut self.texture_manager.borrow_mut(),
-                    &self.device,
-                    &mut self.allocator.borrow_mut(),
-                );
-                let meshes = self
-                    .egui_pipeline
-                    .context()
-                    .tessellate(output.shapes, self.window.scale_factor() as f32);
-                let descriptor_set = self.texture_manager.borrow().descriptor_set();
-                self.egui_pipeline.draw(
-                    &self.device,
-                    cmd_buffer,
-                    self.unorm_draw_image_view,
-                    descriptor_set,
-                    output.textures_delta,
-                    meshes,
-                    &mut self.texture_manager.borrow_mut(),
-                    ctx,
-                    (self.current_frame % FRAME_OVERLAP as u32) as usize,
-                );
-            }
-
-            // prepare copying of the draw image to the swapchain image
-            util::transition_image(
-                &self.device,
-                cmd_buffer,
-                self.draw_image.as_ref().unwrap().image,
-                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-            );
-            util::transition_image(
-                &self.device,
-                cmd_buffer,
-                self.swapchain_images[image_index as usize],
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
-            // copy the draw image to the swapchain image
-            util::copy_image_to_image(
-                &self.device,
-                cmd_buffer,
-                self.draw_image.as_ref().unwrap().image,
-                self.swapchain_images[image_index as usize],
-                vk::Extent2D {
-                    width: self.draw_image.as_ref().unwrap().extent.width,
-                    height: self.draw_image.as_ref().unwrap().extent.height,
-                },
-                vk::Extent2D {
-                    width: self.window_size.0,
-                    height: self.window_size.1,
-                },
-            );
-            // transition the swapchain image to present mode
-            util::transition_image(
-                &self.device,
-                cmd_buffer,
-                self.swapchain_images[image_index as usize],
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                vk::ImageLayout::PRESENT_SRC_KHR,
-            );
+            self.record_frame(cmd_buffer, image_index);
 
             self.device.end_command_buffer(cmd_buffer).unwrap();
 
@@ -949,17 +988,313 @@ impl App {
         self.current_frame = self.current_frame.wrapping_add(1);
     }
 
-    fn draw_background(&self, cmd: vk::CommandBuffer) {
+    /// Records the frame's passes into `cmd` through a render graph, which takes care of the barriers between them.
+    fn record_frame(&mut self, cmd: vk::CommandBuffer, image_index: u32) {
+        // the GUI is built first, so the egui pass only has to draw it
+        let gui = if self.settings.show_gui {
+            let ctx = SubmitContext::from_app(self);
+            self.egui_pipeline.begin_frame(&self.window);
+            self.gui.draw(
+                self.egui_pipeline.context().clone(),
+                &mut self.settings,
+                &mut self.camera,
+                self.world.clone(),
+                self.texture_manager.clone(),
+                self.material_manager.clone(),
+                self.light_manager.clone(),
+                &self.icons,
+                &self.history,
+                &mut self.gizmo,
+                &mut self.selection,
+                &self.shader_compiler.errors,
+                &self.render_graph_info,
+                ctx.clone(),
+            );
+            self.gizmo.paint(
+                self.egui_pipeline.context(),
+                &self.world.borrow(),
+                self.selection.primary(),
+                self.camera.proj() * self.camera.view(),
+                self.camera.position,
+            );
+            let output = self.egui_pipeline.end_frame(&self.window, &mut self.texture_manager.borrow_mut());
+            let meshes = self
+                .egui_pipeline
+                .context()
+                .tessellate(output.shapes, self.window.scale_factor() as f32);
+            Some((output.textures_delta, meshes, ctx))
+        } else {
+            None
+        };
+        self.mesh_pipeline.prepare(
+            &self.device,
+            &mut self.shader_compiler,
+            &mut self.pipeline_deletion_queue,
+            &self.world.borrow().get_meshes(),
+            &self.material_manager.borrow(),
+            self.settings.shadow_pcf,
+        );
+
+        let device = self.device.clone();
+        let world = self.world.borrow();
+        let meshes = world.get_meshes();
+        let descriptor_set = self.texture_manager.borrow().descriptor_set();
+        let light_manager = self.light_manager.borrow();
+        let material_manager = self.material_manager.borrow();
+        let scene_data = self.scene_data.buffer.device_address(&self.device);
+        let extent = vk::Extent2D {
+            width: self.window_size.0,
+            height: self.window_size.1,
+        };
+
+        let mut graph = RenderGraph::default();
+        let draw_image = self.draw_image.as_ref().unwrap();
+        let draw = graph.import_image(
+            "Draw image",
+            draw_image.image,
+            draw_image.view,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED, // cleared or resolved into every frame
+        );
+        // with MSAA, the scene is rendered into a multisampled image and resolved into the draw image before the GUI
+        let scene = if self.settings.msaa == vk::SampleCountFlags::TYPE_1 {
+            draw
+        } else {
+            graph.create_image(
+                "Scene (multisampled)",
+                ImageDesc {
+                    format: DRAW_IMAGE_FORMAT,
+                    extent,
+                    usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    samples: self.settings.msaa,
+                },
+            )
+        };
+        let depth = graph.create_image(
+            "Depth",
+            ImageDesc {
+                format: self.depth_format,
+                extent,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                aspect: vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+                samples: self.settings.msaa,
+            },
+        );
+        let swapchain = graph.import_image(
+            "Swapchain",
+            self.swapchain_images[image_index as usize],
+            self.swapchain_views[image_index as usize],
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+        );
+        graph.export_image(swapchain, ImageUsage::Present);
+        let particles = graph.import_buffer("Particles");
+
+        graph
+            .pass("Simulate particles")
+            .write_buffer(particles, BufferUsage::TransferDst)
+            .write_buffer(particles, BufferUsage::Storage(vk::PipelineStageFlags2::COMPUTE_SHADER))
+            .record(|ctx| self.particle_pipeline.simulate(ctx.device, ctx.cmd, &self.particles));
+        graph
+            .pass("Clear")
+            .write_image(scene, ImageUsage::TransferDst)
+            .record(|ctx| Self::draw_background(ctx.device, ctx.cmd, ctx.image(scene)));
+
+        // todo don't do this on every frame, only when scene changes
+        let mut shadow_maps = vec![];
+        for light in light_manager.iter().filter(|light| light.data.shadow_map != 0) {
+            let (image, view) = {
+                let texture_manager = self.texture_manager.borrow();
+                let shadow_map = &texture_manager.get_texture(light.data.shadow_map).unwrap().image;
+                (shadow_map.image, shadow_map.view)
+            };
+            let handle = graph.import_image(
+                format!("Shadow map {}", light.id),
+                image,
+                view,
+                vk::ImageAspectFlags::DEPTH,
+                vk::ImageLayout::UNDEFINED, // cleared every frame
+            );
+            let (pipeline, meshes, light_manager, light_id) = (&self.shadow_mapping_pipeline, &meshes, &light_manager, light.id);
+            graph
+                .pass(format!("Shadow map {}", light.id))
+                .write_image(handle, ImageUsage::DepthAttachment)
+                .record(move |ctx| {
+                    pipeline.draw(
+                        ctx.device,
+                        ctx.cmd,
+                        meshes,
+                        ctx.view(handle),
+                        descriptor_set,
+                        scene_data,
+                        light_manager,
+                        light_id,
+                    )
+                });
+            shadow_maps.push(handle);
+        }
+
+        let mut pass = graph
+            .pass("Meshes")
+            .write_image(scene, ImageUsage::ColorAttachment)
+            .write_image(depth, ImageUsage::DepthStencilAttachment);
+        for shadow_map in shadow_maps {
+            pass = pass.read_image(shadow_map, ImageUsage::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER));
+        }
+        pass.record(|ctx| {
+            self.mesh_pipeline.draw(
+                ctx.device,
+                ctx.cmd,
+                &meshes,
+                ctx.view(scene),
+                ctx.view(depth),
+                descriptor_set,
+                scene_data,
+                &material_manager,
+                &light_manager,
+                self.settings.shadow_pcf,
+            )
+        });
+        // before the billboards, so editor icons drawn on top of everything stay on top of the particles too
+        graph
+            .pass("Particles")
+            .read_buffer(particles, BufferUsage::Storage(vk::PipelineStageFlags2::VERTEX_SHADER))
+            .write_image(scene, ImageUsage::ColorAttachment)
+            .write_image(depth, ImageUsage::DepthStencilAttachment)
+            .record(|ctx| {
+                self.particle_pipeline.draw(
+                    ctx.device,
+                    ctx.cmd,
+                    &self.particles,
+                    ctx.view(scene),
+                    ctx.view(depth),
+                    descriptor_set,
+                    scene_data,
+                    &material_manager,
+                )
+            });
+        if let Some(billboards) = world.billboard_buffer() {
+            let (pipeline, light_manager) = (&self.billboard_pipeline, &light_manager);
+            let billboard_buffer = graph.import_buffer("Billboards");
+            if billboards.resorted() {
+                graph
+                    .pass("Sort billboards")
+                    .write_buffer(billboard_buffer, BufferUsage::TransferDst)
+                    .record(move |ctx| billboards.record_sorted(ctx.device, ctx.cmd));
+            }
+            graph
+                .pass("Billboards")
+                .read_buffer(
+                    billboard_buffer,
+                    // unlit.frag looks up the material of its billboard
+                    BufferUsage::Storage(vk::PipelineStageFlags2::VERTEX_SHADER | vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                )
+                .write_image(scene, ImageUsage::ColorAttachment)
+                .write_image(depth, ImageUsage::DepthStencilAttachment)
+                .record(move |ctx| {
+                    pipeline.draw(
+                        ctx.device,
+                        ctx.cmd,
+                        billboards,
+                        ctx.view(scene),
+                        ctx.view(depth),
+                        descriptor_set,
+                        scene_data,
+                        light_manager,
+                    )
+                });
+        }
+        if self.settings.show_grid {
+            graph
+                .pass("Grid")
+                .write_image(scene, ImageUsage::ColorAttachment)
+                .record(|ctx| self.grid_pipeline.draw(ctx.device, ctx.cmd, ctx.view(scene), scene_data));
+        }
+        if self.settings.show_gui {
+            let (pipeline, selected, settings) = (
+                &self.outline_pipeline,
+                world.get_subtree_meshes(self.selection.iter()),
+                &self.settings,
+            );
+            graph
+                .pass("Outline")
+                .write_image(scene, ImageUsage::ColorAttachment)
+                .write_image(depth, ImageUsage::DepthStencilAttachment)
+                .record(move |ctx| {
+                    pipeline.draw(
+                        ctx.device,
+                        ctx.cmd,
+                        &selected,
+                        ctx.view(scene),
+                        ctx.view(depth),
+                        scene_data,
+                        settings.outline_color,
+                        settings.outline_width,
+                    )
+                });
+        }
+        if scene != draw {
+            graph
+                .pass("Resolve")
+                .write_image(scene, ImageUsage::ColorAttachment) // not stored afterwards, which counts as a write
+                .write_image(draw, ImageUsage::ColorAttachment)
+                .record(|ctx| util::resolve_image(ctx.device, ctx.cmd, ctx.view(scene), ctx.view(draw), extent));
+        }
+        if let Some((textures_delta, meshes, ctx)) = gui {
+            let (pipeline, target_view, texture_manager) = (&mut self.egui_pipeline, self.unorm_draw_image_view, &self.texture_manager);
+            let frame_index = (self.current_frame % FRAME_OVERLAP as u32) as usize;
+            graph
+                .pass("Egui")
+                .write_image(draw, ImageUsage::ColorAttachment)
+                .record(move |pass| {
+                    pipeline.draw(
+                        pass.device,
+                        pass.cmd,
+                        target_view,
+                        descriptor_set,
+                        textures_delta,
+                        meshes,
+                        &mut texture_manager.borrow_mut(),
+                        ctx,
+                        frame_index,
+                    )
+                });
+        }
+
+        // copy the draw image to the swapchain image
+        graph
+            .pass("Present")
+            .read_image(draw, ImageUsage::TransferSrc)
+            .write_image(swapchain, ImageUsage::TransferDst)
+            .record(|ctx| {
+                util::copy_image_to_image(
+                    ctx.device,
+                    ctx.cmd,
+                    ctx.image(draw),
+                    ctx.image(swapchain),
+                    vk::Extent2D {
+                        width: draw_image.extent.width,
+                        height: draw_image.extent.height,
+                    },
+                    extent,
+                )
+            });
+
+        self.render_graph_info = graph.execute(&device, cmd, &mut self.transient_images, &self.allocator);
+    }
+
+    fn draw_background(device: &Device, cmd: vk::CommandBuffer, image: vk::Image) {
         let clear_range = vk::ImageSubresourceRange::default()
             .level_count(vk::REMAINING_MIP_LEVELS)
             .layer_count(vk::REMAINING_ARRAY_LAYERS)
             .aspect_mask(vk::ImageAspectFlags::COLOR);
 
         unsafe {
-            self.device.cmd_clear_color_image(
+            device.cmd_clear_color_image(
                 cmd,
-                self.draw_image.as_ref().unwrap().image,
-                vk::ImageLayout::GENERAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &vk::ClearColorValue {
                     float32: [0.0, 0.0, 0.0, 1.0],
                 },
@@ -969,13 +1304,54 @@ impl App {
     }
 
     fn input_window(&mut self, event: &winit::event::WindowEvent) {
+        // the button may be released over a window, which egui consumes
+        if let WindowEvent::MouseInput {
+            button: winit::event::MouseButton::Left,
+            state: ElementState::Released,
+            ..
+        } = event
+        {
+            if self.gizmo.release() {
+                self.cmd_sender.send(Command::EndEdit).unwrap();
+            }
+        }
         if !self.egui_pipeline.input(&self.window, event) {
             match event {
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor_position = (position.x as f32, position.y as f32);
+                    if !self.settings.show_gui {
+                        return;
+                    }
+                    let ray = self.cursor_ray();
+                    if self.gizmo.is_dragging() {
+                        if let Some(edit) = self.gizmo.drag(&ray) {
+                            self.cmd_sender.send(Command::Edit(edit)).unwrap();
+                        }
+                    } else {
+                        self.gizmo
+                            .hover(&ray, &self.world.borrow(), self.selection.primary(), self.camera.position);
+                    }
+                }
                 winit::event::WindowEvent::MouseInput { button, state, .. } => {
                     if *button == winit::event::MouseButton::Left && *state == winit::event::ElementState::Pressed {
-                        self.camera.on_mouse_drag(true);
+                        // grabbing a gizmo handle takes precedence over orbiting the camera
+                        let ray = self.cursor_ray();
+                        if !self.settings.show_gui
+                            || !self
+                                .gizmo
+                                .press(&ray, &self.world.borrow(), self.selection.primary(), self.camera.position)
+                        {
+                            self.camera.on_mouse_drag(true);
+                            self.press_position = Some(self.cursor_position);
+                        }
                     } else if *button == winit::event::MouseButton::Left && *state == winit::event::ElementState::Released {
                         self.camera.on_mouse_drag(false);
+                        if let Some(press) = self.press_position.take() {
+                            let moved = (self.cursor_position.0 - press.0).hypot(self.cursor_position.1 - press.1);
+                            if self.settings.show_gui && moved < Self::CLICK_TOLERANCE {
+                                self.pick();
+                            }
+                        }
                     }
                 }
                 WindowEvent::KeyboardInput {
@@ -987,10 +1363,15 @@ impl App {
                             ..
                         },
                     ..
-                } => {
-                    if *key_code == KeyCode::F10 {
-                        self.settings.show_gui = !self.settings.show_gui;
-                    }
+                } => match key_code {
+                    KeyCode::F10 => self.settings.show_gui = !self.settings.show_gui,
+                    KeyCode::KeyW => self.gizmo.mode = GizmoMode::Translate,
+                    KeyCode::KeyE => self.gizmo.mode = GizmoMode::Rotate,
+                    KeyCode::KeyR => self.gizmo.mode = GizmoMode::Scale,
+                    _ => {}
+                },
+                WindowEvent::ModifiersChanged(modifiers) => {
+                    self.modifiers = modifiers.state();
                 }
                 WindowEvent::MouseWheel { delta, .. } => {
                     self.camera.on_mouse_scroll(*delta);
@@ -1000,6 +1381,23 @@ impl App {
         }
     }
 
+    // Selects the model under the cursor. Ctrl adds to or removes from the selection, clicking empty space clears it.
+    fn pick(&mut self) {
+        let picked = self.world.borrow().pick(&self.cursor_ray(), &self.camera);
+        match picked {
+            Some(model) if self.modifiers.control_key() => self.selection.toggle(model),
+            Some(model) => self.selection.set(model),
+            None if self.modifiers.control_key() => {}
+            None => self.selection.clear(),
+        }
+    }
+
+    // The ray from the camera through the cursor.
+    fn cursor_ray(&self) -> Ray {
+        let extent = (self.window_size.0 as f32, self.window_size.1 as f32);
+        Ray::from_screen(self.cursor_position, extent, Mat4::from_cols_array_2d(&self.scene_data.data.unproj))
+    }
+
     fn input_device(&mut self, event: &winit::event::DeviceEvent) {
         match event {
             winit::event::DeviceEvent::MouseMotion { delta } => {
@@ -1010,6 +1408,7 @@ impl App {
     }
 
     fn update(&mut self) {
+        let camera_moved = self.camera.dirty;
         if self.camera.dirty {
             self.camera.dirty = false;
             let view = self.camera.view();
@@ -1031,11 +1430,31 @@ impl App {
             self.light_manager.borrow_mut().count_dirty = false;
             self.scene_data.data.light_count = self.light_manager.borrow().count() as u32;
             self.scene_data.dirty = true;
+            // billboards refer to lights by their index, which changes when lights are removed
+            self.world.borrow_mut().billboards_changed();
         }
         if self.scene_data.dirty {
             SubmitContext::from_app(self).immediate_submit(Box::new(|ctx| self.scene_data.write(ctx)));
             self.scene_data.dirty = false;
         }
+        if self.world.borrow().billboards_outdated() {
+            SubmitContext::from_app(self).immediate_submit(Box::new(|ctx| {
+                self.world.borrow_mut().write_billboards(
+                    self.camera.position,
+                    ctx,
+                    &self.light_manager.borrow(),
+                    &self.material_manager.borrow(),
+                )
+            }));
+        } else {
+            // only the order of the blended billboards changes, the frame uploads it without waiting on the GPU
+            self.world.borrow_mut().sort_billboards(self.camera.position, camera_moved);
+        }
+        // capped, so particles don't jump after a stall, e.g. while a file dialog is open
+        let dt = self.last_update.elapsed().as_secs_f32().min(0.1);
+        self.last_update = Instant::now();
+        self.particles
+            .update(&self.world.borrow(), dt, &self.device, &mut self.allocator.borrow_mut());
     }
 }
 
@@ -1043,10 +1462,20 @@ impl Drop for App {
     fn drop(&mut self) {
         unsafe {
             self.device.device_wait_idle().unwrap();
+            for edit in self.history.clear() {
+                edit.discard(self);
+            }
+            // pending frees still write to the bindless set, so they have to run before it's destroyed
+            for frame in self.frames.iter_mut() {
+                frame.deletion_queue.flush(&self.device, &mut self.allocator.borrow_mut());
+            }
             self.main_deletion_queue.flush(&self.device, &mut self.allocator.borrow_mut());
             self.pipeline_deletion_queue.flush(&self.device, &mut self.allocator.borrow_mut());
+            self.pipeline_cache.save(&self.device);
+            self.pipeline_cache.destroy(&self.device);
             self.device.destroy_descriptor_pool(self.bindless_descriptor_pool, None);
             self.world.borrow_mut().destroy(&self.device, &mut self.allocator.borrow_mut());
+            self.particles.destroy(&self.device, &mut self.allocator.borrow_mut());
             self.device.destroy_descriptor_set_layout(self.bindless_set_layout, None);
 
             self.device.destroy_image_view(self.unorm_draw_image_view, None);
@@ -1054,10 +1483,7 @@ impl Drop for App {
                 .take()
                 .unwrap()
                 .destroy(&self.device, &mut self.allocator.borrow_mut());
-            self.depth_image
-                .take()
-                .unwrap()
-                .destroy(&self.device, &mut self.allocator.borrow_mut());
+            self.transient_images.destroy(&self.device, &mut self.allocator.borrow_mut());
             self.texture_manager
                 .borrow_mut()
                 .destroy(&self.device, &mut self.allocator.borrow_mut());
@@ -1097,24 +1523,24 @@ fn main() -> Result<(), Box<dyn Error>> {
     );
     gltf_loader.load(Path::new("assets/shadow_test.glb"), ctx);
 
-    // todo restrict to "watch" feature
-    let (watch_tx, watch_rx) = std::sync::mpsc::channel();
-    let mut watcher = notify::RecommendedWatcher::new(watch_tx, notify::Config::default()).unwrap();
-    watcher
-        .watch(
-            std::env::current_dir().unwrap().join("src").join("shaders").join("spirv").as_path(),
-            notify::RecursiveMode::Recursive,
-        )
-        .unwrap();
+    // the watcher stops watching when dropped
+    #[cfg(feature = "watch")]
+    let (_watcher, watch_rx) = watch_shaders();
 
     Ok(event_loop.unwrap().run(move |event, target| match event {
         Event::WindowEvent {
             event: WindowEvent::RedrawRequested,
             ..
         } => {
-            if watch_rx.try_recv().is_ok() {
-                app.recreate_pipelines();
-                info!("Shader files changed - recreated pipelines and reloaded shaders.");
+            #[cfg(feature = "watch")]
+            {
+                // an editor saving a file usually causes several events, which are handled at once
+                let shaders_changed = watch_rx.try_iter().filter_map(Result::ok).fold(false, |changed, event| {
+                    changed | event.paths.iter().any(|path| ShaderCompiler::is_source(path))
+                });
+                if shaders_changed && app.recreate_pipelines() {
+                    info!("Shader files changed - recompiled shaders and recreated pipelines.");
+                }
             }
             app.update();
             app.draw();
@@ -1152,3 +1578,17 @@ fn main() -> Result<(), Box<dyn Error>> {
         _ => {}
     })?)
 }
+
+/// Watches the shader sources, so they can be recompiled when edited.
+#[cfg(feature = "watch")]
+fn watch_shaders() -> (notify::RecommendedWatcher, mpsc::Receiver<notify::Result<notify::Event>>) {
+    let (watch_tx, watch_rx) = mpsc::channel();
+    let mut watcher = notify::RecommendedWatcher::new(watch_tx, notify::Config::default()).unwrap();
+    watcher
+        .watch(
+            std::env::current_dir().unwrap().join(ShaderCompiler::SOURCE_DIR).as_path(),
+            notify::RecursiveMode::NonRecursive,
+        )
+        .unwrap();
+    (watcher, watch_rx)
+}
@@ -1,13 +1,24 @@
 pub mod billboard;
+pub mod cache;
+pub mod compiler;
 pub mod egui;
 pub mod grid;
 pub mod mesh;
+pub mod outline;
+pub mod particles;
+pub mod reflection;
 pub mod shadow_mapping;
 
 use crate::asset::texture::TEXTURE_IMAGE_FORMAT;
-use crate::DEPTH_FORMAT;
+use crate::gpu_layout;
+use crate::pipeline::compiler::ShaderCompiler;
+use crate::pipeline::reflection::GpuLayout;
+use crate::util::load_shader_module;
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
+use std::ffi::CStr;
+use std::fs;
+use std::path::Path;
 
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone, Debug)]
@@ -18,6 +29,14 @@ pub(crate) struct Vertex {
     pub(crate) uv_y: f32,
     pub(crate) color: [f32; 4],
 }
+gpu_layout!(Vertex {
+    position,
+    uv_x,
+    normal,
+    uv_y,
+    color
+});
+
 pub struct PipelineBuilder<'a> {
     pub shader_stages: Vec<vk::PipelineShaderStageCreateInfo<'a>>,
     pub input_assembly: vk::PipelineInputAssemblyStateCreateInfo<'a>,
@@ -29,7 +48,30 @@ pub struct PipelineBuilder<'a> {
     pub render_info: vk::PipelineRenderingCreateInfo<'a>,
 }
 impl PipelineBuilder<'_> {
-    pub(crate) fn build(mut self, device: &Device) -> vk::Pipeline {
+    pub(crate) fn depth_test(mut self, test: bool, write: bool) -> Self {
+        self.depth_stencil = self.depth_stencil.depth_test_enable(test).depth_write_enable(write);
+        self
+    }
+
+    /// Enables the stencil test with the same ops for front and back faces.
+    pub(crate) fn stencil(mut self, op: vk::StencilOpState) -> Self {
+        self.depth_stencil = self.depth_stencil.stencil_test_enable(true).front(op).back(op);
+        self
+    }
+
+    /// Rasterizes with `samples` per pixel, which has to match the attachments the pipeline renders to.
+    pub(crate) fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.multisample = self.multisample.rasterization_samples(samples);
+        self
+    }
+
+    /// Renders to a depth/stencil attachment of `format`, see `App::depth_format`.
+    pub(crate) fn depth_format(mut self, format: vk::Format) -> Self {
+        self.render_info = self.render_info.depth_attachment_format(format).stencil_attachment_format(format);
+        self
+    }
+
+    pub(crate) fn build(mut self, device: &Device, cache: vk::PipelineCache) -> vk::Pipeline {
         let viewport_state = vk::PipelineViewportStateCreateInfo::default()
             .viewport_count(1) // dynamic state allows us to only specify count
             .scissor_count(1);
@@ -52,11 +94,7 @@ impl PipelineBuilder<'_> {
             .push_next(&mut self.render_info)
             .dynamic_state(&dynamic_state_info);
 
-        unsafe {
-            device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-                .unwrap()[0]
-        }
+        unsafe { device.create_graphics_pipelines(cache, &[pipeline_info], None).unwrap()[0] }
     }
 }
 
@@ -90,11 +128,140 @@ impl Default for PipelineBuilder<'_> {
                 .back(Default::default())
                 .min_depth_bounds(0.0)
                 .max_depth_bounds(1.0),
-            render_info: vk::PipelineRenderingCreateInfo::default()
-                .color_attachment_formats(&[TEXTURE_IMAGE_FORMAT])
-                .depth_attachment_format(DEPTH_FORMAT),
+            render_info: vk::PipelineRenderingCreateInfo::default().color_attachment_formats(&[TEXTURE_IMAGE_FORMAT]),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ComputePipelineBuilder {
+    pub shader: Option<vk::ShaderModule>,
+    pub layout: Option<vk::PipelineLayout>,
+}
+
+impl ComputePipelineBuilder {
+    pub(crate) fn build(self, device: &Device, cache: vk::PipelineCache) -> vk::Pipeline {
+        let pipeline_info = vk::ComputePipelineCreateInfo::default()
+            .stage(shader_stage(
+                vk::ShaderStageFlags::COMPUTE,
+                self.shader.expect("Compute shader not set!"),
+            ))
+            .layout(self.layout.expect("Pipeline layout not set!"));
+
+        unsafe { device.create_compute_pipelines(cache, &[pipeline_info], None).unwrap()[0] }
+    }
+}
+
+/// A shader a pipeline is built from, with the push constants its layout is validated against.
+#[derive(Copy, Clone)]
+pub(crate) struct Shader {
+    pub name: &'static str, // file name in src/shaders, e.g. mesh.vert
+    validate: fn(&[u32]) -> Result<(), String>,
+}
+
+impl Shader {
+    pub(crate) const fn new<P: GpuLayout>(name: &'static str) -> Self {
+        Self {
+            name,
+            validate: reflection::validate::<P>,
         }
     }
+
+    /// Checks the push constants and the structs shared with Rust, see `reflection::validate`.
+    pub(crate) fn validate(&self, spirv: &[u32]) -> Result<(), String> {
+        (self.validate)(spirv)
+    }
+
+    /// Loads the compiled shader from src/shaders/spirv. Panics if its layouts don't match the Rust types, which can only
+    /// happen at startup, as `ShaderCompiler::compile_all` doesn't write shaders that fail validation.
+    pub(crate) fn load(&self, device: &Device) -> vk::ShaderModule {
+        let code = fs::read(format!("src/shaders/spirv/{}.spv", self.name))
+            .unwrap_or_else(|e| panic!("Failed to read shader {}: {}", self.name, e));
+        let words = code
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        self.create_module(device, &words).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Compiles a variant of the shader with `defines` set, for pipelines with permutations. Unlike `load`, failing to
+    /// compile or validate is an error rather than a panic, as variants are built while the app is running.
+    pub(crate) fn compile(&self, device: &Device, compiler: &ShaderCompiler, defines: &[&str]) -> Result<vk::ShaderModule, String> {
+        let words = compiler.compile(&Path::new(ShaderCompiler::SOURCE_DIR).join(self.name), defines)?;
+        self.create_module(device, &words)
+    }
+
+    fn create_module(&self, device: &Device, words: &[u32]) -> Result<vk::ShaderModule, String> {
+        self.validate(words)
+            .map_err(|e| format!("Layouts in shader {} don't match the Rust types:\n{}", self.name, e))?;
+        load_shader_module(device, bytemuck::cast_slice(words)).map_err(|e| format!("Failed to load shader module {}: {}", self.name, e))
+    }
+}
+
+/// The shaders of all pipelines, which compiled stages are validated against.
+pub(crate) fn shaders() -> Vec<Shader> {
+    [
+        billboard::SHADERS.as_slice(),
+        &egui::SHADERS,
+        &grid::SHADERS,
+        &mesh::SHADERS,
+        &outline::SHADERS,
+        &particles::SHADERS,
+        &shadow_mapping::SHADERS,
+    ]
+    .concat()
+}
+
+/// A shader stage using the module's `main` entry point.
+pub(crate) fn shader_stage(stage: vk::ShaderStageFlags, module: vk::ShaderModule) -> vk::PipelineShaderStageCreateInfo<'static> {
+    vk::PipelineShaderStageCreateInfo::default()
+        .stage(stage)
+        .module(module)
+        .name(CStr::from_bytes_with_nul(b"main\0").unwrap())
+}
+
+/// Creates a layout with a single push constant range the size of `P`, visible to `stages`. Pipelines that read
+/// textures pass the bindless set layout.
+pub(crate) fn create_layout<P: Pod>(
+    device: &Device,
+    bindless_set_layout: Option<vk::DescriptorSetLayout>,
+    stages: vk::ShaderStageFlags,
+) -> vk::PipelineLayout {
+    let push_constant_range = [vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<P>() as u32)
+        .stage_flags(stages)];
+    let set_layouts = bindless_set_layout.as_slice();
+    let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(&push_constant_range);
+    unsafe { device.create_pipeline_layout(&layout_create_info, None).unwrap() }
+}
+
+/// Records pushing `push_constants` for a layout created with `create_layout`.
+pub(crate) fn push_constants<P: Pod>(
+    device: &Device,
+    cmd: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    stages: vk::ShaderStageFlags,
+    push_constants: &P,
+) {
+    unsafe {
+        device.cmd_push_constants(cmd, layout, stages, 0, bytemuck::bytes_of(push_constants));
+    }
+}
+
+/// Records a dispatch of enough workgroups of `workgroup_size` (the shader's local size) to cover `invocations`.
+pub(crate) fn dispatch(device: &Device, cmd: vk::CommandBuffer, invocations: [u32; 3], workgroup_size: [u32; 3]) {
+    let [x, y, z] = [0, 1, 2].map(|i| invocations[i].div_ceil(workgroup_size[i]));
+    unsafe {
+        device.cmd_dispatch(cmd, x, y, z);
+    }
+}
+
+/// Records a dispatch over `count` invocations of a shader with a local size of `workgroup_size` along X.
+pub(crate) fn dispatch_1d(device: &Device, cmd: vk::CommandBuffer, count: u32, workgroup_size: u32) {
+    dispatch(device, cmd, [count, 1, 1], [workgroup_size, 1, 1]);
 }
 
 #[repr(C)]
@@ -110,3 +277,13 @@ pub struct GpuSceneData {
     pub light_count: u32,
     pub padding: [u32; 3],
 }
+gpu_layout!(GpuSceneData {
+    view,
+    proj,
+    viewproj,
+    unproj,
+    ambient_color,
+    camera_position,
+    light_count,
+    padding
+});
@@ -1,72 +1,104 @@
-use crate::pipeline::PipelineBuilder;
-use crate::util::{load_shader_module, DeletionQueue};
+use crate::gpu_layout;
+use crate::pipeline::{create_layout, shader_stage, PipelineBuilder, Shader};
+use crate::util::DeletionQueue;
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
 
-use crate::asset::material::MaterialManager;
-use crate::scene::billboard::Billboard;
+use crate::scene::billboard::BillboardBuffer;
 use crate::scene::light::LightManager;
-use glam::{Mat4, Vec2, Vec4, Vec4Swizzles};
-use image::EncodableLayout;
-use std::ffi::CStr;
-use std::fs;
 
 pub struct BillboardPipeline {
     viewport: vk::Viewport,
     scissor: vk::Rect2D,
-    pipeline: vk::Pipeline,
+    pipelines: [vk::Pipeline; 3], // for opaque and blended billboards, and for those ignoring depth
     pub layout: vk::PipelineLayout,
     window_size: (u32, u32),
 }
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone, Debug)]
 struct PushConstants {
-    transform: [[f32; 4]; 4],
-    size: [f32; 2],
-    uvs: [[f32; 2]; 4],
     scene_data: vk::DeviceAddress,
-    material_buffer: vk::DeviceAddress,
+    billboard_buffer: vk::DeviceAddress,
     light_buffer: vk::DeviceAddress,
+    viewport_height: f32,
+    padding: f32,
 }
+gpu_layout!(PushConstants {
+    scene_data,
+    billboard_buffer,
+    light_buffer,
+    viewport_height,
+    padding
+});
+
+// the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 2] = [
+    Shader::new::<PushConstants>("billboard.vert"),
+    Shader::new::<PushConstants>("unlit.frag"),
+];
 
 impl BillboardPipeline {
     pub fn new(
         device: &Device,
         window_size: (u32, u32),
         deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
+        samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
         bindless_set_layout: vk::DescriptorSetLayout,
     ) -> Self {
-        let vertex_shader = load_shader_module(device, fs::read("src/shaders/spirv/billboard.vert.spv").unwrap().as_bytes())
-            .expect("Failed to load vertex shader module");
-        let fragment_shader = load_shader_module(device, fs::read("src/shaders/spirv/unlit.frag.spv").unwrap().as_bytes())
-            .expect("Failed to load fragment shader module");
+        let [vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
 
-        let push_constant_range = [vk::PushConstantRange::default()
-            .offset(0)
-            .size(size_of::<PushConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)];
-        let binding = [bindless_set_layout];
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&binding)
-            .push_constant_ranges(&push_constant_range);
-        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None).unwrap() };
+        let layout = create_layout::<PushConstants>(
+            device,
+            Some(bindless_set_layout),
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        );
         let pipeline_builder = PipelineBuilder {
             layout: Some(layout),
             shader_stages: vec![
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::VERTEX)
-                    .module(vertex_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::FRAGMENT)
-                    .module(fragment_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
+                shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+                shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
             ],
             input_assembly: vk::PipelineInputAssemblyStateCreateInfo::default().topology(vk::PrimitiveTopology::TRIANGLE_LIST),
             ..Default::default()
         };
 
-        let pipeline = pipeline_builder.build(device);
+        let alpha_blend = vk::PipelineColorBlendAttachmentState::default()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_blend_op(vk::BlendOp::ADD);
+        // blended billboards are sorted instead of writing depth
+        let blended_pipeline = PipelineBuilder {
+            layout: pipeline_builder.layout,
+            shader_stages: pipeline_builder.shader_stages.clone(),
+            color_blend_attachment: alpha_blend,
+            ..Default::default()
+        }
+        .depth_test(true, false)
+        .samples(samples)
+        .depth_format(depth_format)
+        .build(device, cache);
+        let overlay_pipeline = PipelineBuilder {
+            layout: pipeline_builder.layout,
+            shader_stages: pipeline_builder.shader_stages.clone(),
+            color_blend_attachment: alpha_blend,
+            ..Default::default()
+        }
+        .depth_test(false, false)
+        .samples(samples)
+        .depth_format(depth_format)
+        .build(device, cache);
+        let pipelines = [
+            pipeline_builder.samples(samples).depth_format(depth_format).build(device, cache),
+            blended_pipeline,
+            overlay_pipeline,
+        ];
 
         unsafe {
             device.destroy_shader_module(vertex_shader, None);
@@ -75,7 +107,9 @@ impl BillboardPipeline {
 
         deletion_queue.push(move |device, _allocator| unsafe {
             device.destroy_pipeline_layout(layout, None);
-            device.destroy_pipeline(pipeline, None);
+            for pipeline in pipelines {
+                device.destroy_pipeline(pipeline, None);
+            }
         });
 
         let viewport = vk::Viewport::default()
@@ -91,7 +125,7 @@ impl BillboardPipeline {
         Self {
             viewport,
             scissor,
-            pipeline,
+            pipelines,
             layout,
             window_size,
         }
@@ -113,21 +147,20 @@ impl BillboardPipeline {
         &self,
         device: &Device,
         cmd: vk::CommandBuffer,
-        billboards: &Vec<&Billboard>,
+        billboards: &BillboardBuffer,
         target_view: vk::ImageView,
         depth_view: vk::ImageView,
         bindless_descriptor_set: vk::DescriptorSet,
         scene_data: vk::DeviceAddress,
-        material_manager: &MaterialManager,
         light_manager: &LightManager,
     ) {
         let color_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(target_view)
-            .image_layout(vk::ImageLayout::GENERAL);
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
         let color_attachments = [color_attachment];
         let depth_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(depth_view)
-            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .load_op(vk::AttachmentLoadOp::LOAD)
             .store_op(vk::AttachmentStoreOp::STORE)
             .clear_value(vk::ClearValue {
@@ -136,6 +169,7 @@ impl BillboardPipeline {
         let render_info = vk::RenderingInfo::default()
             .color_attachments(&color_attachments)
             .depth_attachment(&depth_attachment)
+            .stencil_attachment(&depth_attachment)
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
                 extent: vk::Extent2D {
@@ -155,28 +189,31 @@ impl BillboardPipeline {
                 &[],
             );
             device.cmd_begin_rendering(cmd, &render_info);
-            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
             device.cmd_set_viewport(cmd, 0, &[self.viewport]);
             device.cmd_set_scissor(cmd, 0, &[self.scissor]);
 
-            for billboard in billboards {
-                let push_constants = PushConstants {
-                    transform: Mat4::from_translation(billboard.center.xyz()).to_cols_array_2d(),
-                    size: billboard.size.to_array(),
-                    uvs: billboard.uvs.map(|v| Vec2::from((v.x, v.y)).to_array()),
-                    scene_data,
-                    material_buffer: material_manager.get_material(billboard.material).unwrap().device_address(device),
-                    light_buffer: light_manager.device_address(device),
-                };
-
-                device.cmd_push_constants(
-                    cmd,
-                    self.layout,
-                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                    0,
-                    bytemuck::cast_slice(&[push_constants]),
-                );
-                device.cmd_draw(cmd, 6, 1, 0, 0);
+            let push_constants = PushConstants {
+                scene_data,
+                billboard_buffer: billboards.device_address(device),
+                light_buffer: light_manager.device_address(device),
+                viewport_height: self.window_size.1 as f32,
+                padding: 0.0,
+            };
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::cast_slice(&[push_constants]),
+            );
+            // the buffer holds each pipeline's billboards in turn
+            let mut first_instance = 0;
+            for (pipeline, count) in self.pipelines.into_iter().zip(billboards.counts) {
+                if count > 0 {
+                    device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    device.cmd_draw(cmd, 6, count, 0, first_instance);
+                }
+                first_instance += count;
             }
 
             device.cmd_end_rendering(cmd);
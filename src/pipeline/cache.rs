@@ -0,0 +1,99 @@
+use ash::{vk, Device, Instance};
+use log::{info, warn};
+use std::fs;
+use std::path::PathBuf;
+
+/// A pipeline cache shared by all pipelines and kept on disk, so pipelines don't have to be compiled from scratch on
+/// every launch. Cache data is only valid for the device and driver that produced it, so each gets its own file.
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    path: PathBuf,
+}
+
+impl PipelineCache {
+    const DIR: &'static str = "pipeline_cache";
+    const HEADER_SIZE: usize = 32; // VkPipelineCacheHeaderVersionOne
+
+    /// Creates the cache from the file of the device, or an empty one if there is none or it's unusable.
+    pub fn load(instance: &Instance, physical_device: vk::PhysicalDevice, device: &Device) -> Self {
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut id_properties);
+        unsafe {
+            instance.get_physical_device_properties2(physical_device, &mut properties2);
+        }
+        let properties = properties2.properties;
+        let device_uuid = id_properties
+            .device_uuid
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let path = PathBuf::from(Self::DIR).join(format!("{}-{}.bin", device_uuid, properties.driver_version));
+
+        // drivers are supposed to reject data they can't use, but not all of them do, so it's checked here first
+        let data = match fs::read(&path) {
+            Ok(data) => match Self::check_header(&data, &properties) {
+                Ok(()) => data,
+                Err(e) => {
+                    warn!("Ignoring pipeline cache {}: {}", path.display(), e);
+                    vec![]
+                }
+            },
+            Err(_) => vec![],
+        };
+        let create =
+            |data: &[u8]| unsafe { device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default().initial_data(data), None) };
+        let cache = create(&data)
+            .or_else(|e| {
+                warn!("Failed to create pipeline cache from {}: {}", path.display(), e);
+                create(&[])
+            })
+            .unwrap();
+        if !data.is_empty() {
+            info!("Loaded pipeline cache {} ({} bytes).", path.display(), data.len());
+        }
+        Self { cache, path }
+    }
+
+    /// Checks that the data starts with a valid header written by this device's driver.
+    fn check_header(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> Result<(), String> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err("the header is truncated".into());
+        }
+        // the header is in the host's byte order
+        let word = |i: usize| u32::from_ne_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+        let (length, version, vendor_id, device_id) = (word(0), word(1), word(2), word(3));
+        if (length as usize) < Self::HEADER_SIZE || length as usize > data.len() {
+            return Err(format!("the header has an invalid length of {}", length));
+        }
+        if version != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+            return Err(format!("the header version {} isn't supported", version));
+        }
+        if vendor_id != properties.vendor_id || device_id != properties.device_id {
+            return Err("it was created for a different device".into());
+        }
+        if data[16..32] != properties.pipeline_cache_uuid {
+            return Err("it was created by a different driver".into());
+        }
+        Ok(())
+    }
+
+    /// Writes the cache to its file, including pipelines created since it was loaded.
+    pub fn save(&self, device: &Device) {
+        let result = unsafe { device.get_pipeline_cache_data(self.cache) }
+            .map_err(|e| e.to_string())
+            .and_then(|data| {
+                fs::create_dir_all(Self::DIR)
+                    .and_then(|_| fs::write(&self.path, &data))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save pipeline cache {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline_cache(self.cache, None);
+        }
+    }
+}
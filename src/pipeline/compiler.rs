@@ -0,0 +1,135 @@
+use crate::pipeline;
+use log::{info, warn};
+use shaderc::{CompileOptions, Compiler, IncludeType, ResolvedInclude, ShaderKind};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A shader that failed to compile, with the compiler's message.
+pub struct ShaderError {
+    pub shader: String, // file name, e.g. mesh.vert
+    pub message: String,
+}
+
+/// Compiles the GLSL sources in src/shaders into src/shaders/spirv, where the pipelines load them from. If any shader
+/// fails to compile, or its layouts don't match the Rust types, all of them keep their last compiled version, so the
+/// pipelines never mix shaders from different edits.
+pub struct ShaderCompiler {
+    compiler: Compiler,
+    pub errors: Vec<ShaderError>, // of the last compilation
+}
+
+impl ShaderCompiler {
+    pub const SOURCE_DIR: &'static str = "src/shaders";
+    const OUTPUT_DIR: &'static str = "src/shaders/spirv";
+
+    pub fn new() -> Self {
+        Self {
+            compiler: Compiler::new().expect("Failed to create shader compiler"),
+            errors: vec![],
+        }
+    }
+
+    /// Whether the file is a shader stage or an include, i.e. whether editing it requires recompiling.
+    #[cfg(feature = "watch")]
+    pub fn is_source(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension == "glsl" || Self::kind(extension).is_some())
+    }
+
+    fn kind(extension: &str) -> Option<ShaderKind> {
+        match extension {
+            "vert" => Some(ShaderKind::Vertex),
+            "geom" => Some(ShaderKind::Geometry),
+            "frag" => Some(ShaderKind::Fragment),
+            "comp" => Some(ShaderKind::Compute),
+            _ => None,
+        }
+    }
+
+    /// The shader stages in the source directory, i.e. all sources except includes.
+    pub fn stages() -> Vec<PathBuf> {
+        let mut stages = fs::read_dir(Self::SOURCE_DIR)
+            .unwrap()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && path.extension().and_then(|extension| Self::kind(extension.to_str()?)).is_some())
+            .collect::<Vec<_>>();
+        stages.sort();
+        stages
+    }
+
+    /// Compiles all shader stages and validates them against the pipelines using them. Returns whether all of them
+    /// compiled, in which case they're written out, otherwise the errors are kept in `errors`.
+    pub fn compile_all(&mut self) -> bool {
+        self.errors.clear();
+        let mut compiled = vec![];
+        let shaders = pipeline::shaders();
+        for path in Self::stages() {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let result = self.compile(&path, &[]).and_then(|spirv| {
+                // stages no pipeline uses have nothing to be checked against
+                match shaders.iter().find(|shader| shader.name == name) {
+                    Some(shader) => shader
+                        .validate(&spirv)
+                        .map(|_| spirv)
+                        .map_err(|e| format!("Layouts don't match the Rust types:\n{}", e)),
+                    None => Ok(spirv),
+                }
+            });
+            match result {
+                Ok(spirv) => compiled.push((name, spirv)),
+                Err(message) => {
+                    warn!("Failed to compile {}:\n{}", name, message);
+                    self.errors.push(ShaderError { shader: name, message });
+                }
+            }
+        }
+        if !self.errors.is_empty() {
+            return false;
+        }
+        fs::create_dir_all(Self::OUTPUT_DIR).unwrap();
+        for (name, spirv) in compiled {
+            fs::write(
+                Path::new(Self::OUTPUT_DIR).join(format!("{}.spv", name)),
+                bytemuck::cast_slice(&spirv),
+            )
+            .unwrap();
+        }
+        info!("Compiled shaders.");
+        true
+    }
+
+    /// Compiles a shader stage to SPIR-V words, with each of `defines` defined for the preprocessor.
+    pub fn compile(&self, path: &Path, defines: &[&str]) -> Result<Vec<u32>, String> {
+        let kind = path
+            .extension()
+            .and_then(|extension| Self::kind(extension.to_str()?))
+            .ok_or(format!("{} isn't a shader stage", path.display()))?;
+        let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut options = CompileOptions::new().ok_or("Failed to create compile options")?;
+        options.set_generate_debug_info();
+        for define in defines {
+            options.add_macro_definition(define, None);
+        }
+        // includes are resolved relative to the including file, e.g. #include "globals.glsl"
+        options.set_include_callback(|requested, include_type, requesting, _depth| {
+            let path = match include_type {
+                IncludeType::Relative => Path::new(requesting).parent().unwrap_or(Path::new("")).join(requested),
+                IncludeType::Standard => PathBuf::from(Self::SOURCE_DIR).join(requested),
+            };
+            let content = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(ResolvedInclude {
+                resolved_name: path.to_string_lossy().into_owned(),
+                content,
+            })
+        });
+        let artifact = self
+            .compiler
+            .compile_into_spirv(&source, kind, &path.to_string_lossy(), "main", Some(&options))
+            .map_err(|e| e.to_string())?;
+        if artifact.get_num_warnings() > 0 {
+            warn!("Warnings compiling {}:\n{}", path.display(), artifact.get_warning_messages());
+        }
+        Ok(artifact.as_binary().to_vec())
+    }
+}
@@ -1,7 +1,8 @@
 use crate::asset::texture::TEXTURE_IMAGE_FORMAT;
-use crate::pipeline::PipelineBuilder;
+use crate::gpu_layout;
+use crate::pipeline::{create_layout, shader_stage, PipelineBuilder, Shader};
 use crate::resource::{AllocUsage, Allocator};
-use crate::util::{load_shader_module, DeletionQueue};
+use crate::util::DeletionQueue;
 use crate::FRAME_OVERLAP;
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
@@ -13,10 +14,7 @@ use crate::asset::texture::{Texture, TextureId, TextureManager};
 use crate::resource::buffer::AllocatedBuffer;
 use crate::resource::immediate_submit::SubmitContext;
 use hashbrown::HashMap;
-use image::EncodableLayout;
 use log::debug;
-use std::ffi::CStr;
-use std::fs;
 use winit::window::Window;
 
 type EguiTextureId = egui::TextureId;
@@ -41,15 +39,25 @@ struct PushConstants {
     font_texture_id: u32,
     padding: u32,
 }
+gpu_layout!(PushConstants {
+    screen_size,
+    vertex_buffer,
+    font_texture_id,
+    padding
+});
 
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone, Debug)]
-struct Vertex {
+pub(crate) struct Vertex {
     pos: [f32; 2],
     uv: [f32; 2],
     color: u32,
     padding: u32,
 }
+gpu_layout!(Vertex { pos, uv, color, padding });
+
+// the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 2] = [Shader::new::<PushConstants>("egui.vert"), Shader::new::<PushConstants>("egui.frag")];
 
 impl EguiPipeline {
     const INDEX_BUFFER_SIZE: usize = 1024 * 1024;
@@ -59,24 +67,14 @@ impl EguiPipeline {
         device: &ash::Device,
         window_size: (u32, u32),
         deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
         bindless_set_layout: vk::DescriptorSetLayout,
         window: &Window,
         submit_context: SubmitContext,
     ) -> Self {
-        let vertex_shader = load_shader_module(device, fs::read("src/shaders/spirv/egui.vert.spv").unwrap().as_bytes())
-            .expect("Failed to load vertex shader module");
-        let fragment_shader = load_shader_module(device, fs::read("src/shaders/spirv/egui.frag.spv").unwrap().as_bytes())
-            .expect("Failed to load fragment shader module");
+        let [vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
 
-        let push_constant_range = [vk::PushConstantRange::default()
-            .offset(0)
-            .size(std::mem::size_of::<PushConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)];
-        let binding = [bindless_set_layout];
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&binding)
-            .push_constant_ranges(&push_constant_range);
-        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None).unwrap() };
+        let layout = create_layout::<PushConstants>(device, Some(bindless_set_layout), vk::ShaderStageFlags::VERTEX);
         let pipeline_builder = PipelineBuilder {
             layout: Some(layout),
             color_blend_attachment: vk::PipelineColorBlendAttachmentState::default()
@@ -89,20 +87,14 @@ impl EguiPipeline {
                 .dst_alpha_blend_factor(vk::BlendFactor::ONE)
                 .alpha_blend_op(vk::BlendOp::ADD),
             shader_stages: vec![
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::VERTEX)
-                    .module(vertex_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::FRAGMENT)
-                    .module(fragment_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
+                shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+                shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
             ],
             render_info: vk::PipelineRenderingCreateInfo::default().color_attachment_formats(&[vk::Format::R8G8B8A8_UNORM]),
             ..Default::default()
         };
 
-        let pipeline = pipeline_builder.build(device);
+        let pipeline = pipeline_builder.build(device, cache);
 
         unsafe {
             device.destroy_shader_module(vertex_shader, None);
@@ -203,7 +195,7 @@ impl EguiPipeline {
         }
         let color_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(target_view)
-            .image_layout(vk::ImageLayout::GENERAL);
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
         let color_attachments = [color_attachment];
         let depth_attachment = vk::RenderingAttachmentInfo::default();
         let render_info = vk::RenderingInfo::default()
@@ -308,7 +300,11 @@ impl EguiPipeline {
             let push_constants = PushConstants {
                 screen_size: [self.window_size.0 as f32, self.window_size.1 as f32],
                 vertex_buffer: buffer_device_address,
-                font_texture_id: self.textures[&emesh.texture_id] as u32,
+                font_texture_id: match emesh.texture_id {
+                    // user textures are engine textures, referenced by their bindless index
+                    EguiTextureId::User(id) => id as u32,
+                    managed => self.textures[&managed],
+                },
                 padding: 0,
             };
             let vertices = &emesh.vertices;
@@ -333,18 +329,11 @@ impl EguiPipeline {
             self.mesh_buffers[image_index].1.allocation.unmap(device);
         }
     }
-    pub fn end_frame(
-        &mut self,
-        window: &Window,
-        texture_manager: &mut TextureManager,
-        device: &Device,
-        allocator: &mut Allocator,
-    ) -> FullOutput {
+    pub fn end_frame(&mut self, window: &Window, texture_manager: &mut TextureManager) -> FullOutput {
         let output = self.context.end_frame();
         for to_free in &output.textures_delta.free {
-            if self.textures.get(to_free).is_some() {
-                let texture_id = self.textures.remove(to_free);
-                texture_manager.free(texture_id.unwrap(), device, allocator);
+            if let Some(texture_id) = self.textures.remove(to_free) {
+                texture_manager.free(texture_id);
             }
         }
         self.egui_winit.handle_platform_output(window, output.platform_output.clone());
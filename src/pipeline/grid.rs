@@ -1,11 +1,9 @@
-use crate::pipeline::PipelineBuilder;
+use crate::gpu_layout;
+use crate::pipeline::{create_layout, shader_stage, PipelineBuilder, Shader};
 
-use crate::util::{load_shader_module, DeletionQueue};
+use crate::util::DeletionQueue;
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
-use image::EncodableLayout;
-use std::ffi::CStr;
-use std::fs;
 
 pub struct GridPipeline {
     viewport: vk::Viewport,
@@ -19,33 +17,28 @@ pub struct GridPipeline {
 struct PushConstants {
     scene_data: vk::DeviceAddress,
 }
+gpu_layout!(PushConstants { scene_data });
+
+// the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 2] = [Shader::new::<PushConstants>("grid.vert"), Shader::new::<PushConstants>("grid.frag")];
 
 impl GridPipeline {
-    pub fn new(device: &ash::Device, window_size: (u32, u32), deletion_queue: &mut DeletionQueue) -> Self {
-        let vertex_shader = load_shader_module(device, fs::read("src/shaders/spirv/grid.vert.spv").unwrap().as_bytes())
-            .expect("Failed to load vertex shader module");
-        let fragment_shader = load_shader_module(device, fs::read("src/shaders/spirv/grid.frag.spv").unwrap().as_bytes())
-            .expect("Failed to load fragment shader module");
+    pub fn new(
+        device: &ash::Device,
+        window_size: (u32, u32),
+        deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
+        samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+    ) -> Self {
+        let [vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
 
-        let push_constant_range = [vk::PushConstantRange::default()
-            .offset(0)
-            .size(std::mem::size_of::<PushConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)];
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&[])
-            .push_constant_ranges(&push_constant_range);
-        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None).unwrap() };
+        let layout = create_layout::<PushConstants>(device, None, vk::ShaderStageFlags::VERTEX);
         let pipeline_builder = PipelineBuilder {
             layout: Some(layout),
             shader_stages: vec![
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::VERTEX)
-                    .module(vertex_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::FRAGMENT)
-                    .module(fragment_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
+                shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+                shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
             ],
             color_blend_attachment: vk::PipelineColorBlendAttachmentState::default()
                 .blend_enable(true)
@@ -59,7 +52,7 @@ impl GridPipeline {
             ..Default::default()
         };
 
-        let pipeline = pipeline_builder.build(device);
+        let pipeline = pipeline_builder.samples(samples).depth_format(depth_format).build(device, cache);
 
         unsafe {
             device.destroy_shader_module(vertex_shader, None);
@@ -104,7 +97,7 @@ impl GridPipeline {
     pub fn draw(&self, device: &Device, cmd: vk::CommandBuffer, target_view: vk::ImageView, scene_data: vk::DeviceAddress) {
         let color_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(target_view)
-            .image_layout(vk::ImageLayout::GENERAL);
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
         let color_attachments = [color_attachment];
         let render_info = vk::RenderingInfo::default()
             .color_attachments(&color_attachments)
@@ -1,69 +1,90 @@
-use crate::pipeline::PipelineBuilder;
+use crate::gpu_layout;
+use crate::pipeline::compiler::{ShaderCompiler, ShaderError};
+use crate::pipeline::{create_layout, shader_stage, PipelineBuilder, Shader};
 use crate::scene::mesh::Mesh;
-use crate::util::{load_shader_module, DeletionQueue};
+use crate::util::DeletionQueue;
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
+use hashbrown::HashMap;
+use log::info;
 
-use crate::asset::material::MaterialManager;
+use crate::asset::material::{Material, MaterialManager};
 use crate::scene::light::LightManager;
-use image::EncodableLayout;
-use std::ffi::CStr;
-use std::fs;
 
 pub struct MeshPipeline {
     viewport: vk::Viewport,
     scissor: vk::Rect2D,
-    pipeline: vk::Pipeline,
+    pipelines: HashMap<MeshVariant, vk::Pipeline>, // variants that failed to compile map to the default one
+    cache: vk::PipelineCache,                      // for variants built later on
+    samples: vk::SampleCountFlags,                 // of the attachments, see AppSettings::msaa
+    depth_format: vk::Format,
     pub layout: vk::PipelineLayout,
     window_size: (u32, u32),
 }
+
+/// The features a mesh is drawn with. Each combination is its own variant of the mesh shaders, compiled with the
+/// matching defines set instead of branching at runtime. Materials pick their variant, see `RawMaterial::variant`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MeshVariant {
+    pub normal_map: bool,
+    pub alpha_mask: bool,
+    pub shadow_pcf: bool,
+}
+
+impl MeshVariant {
+    // in the order of the fields
+    pub(crate) const DEFINES: [&'static str; 3] = ["HAS_NORMAL_MAP", "ALPHA_MASK", "SHADOW_PCF"];
+
+    fn defines(&self) -> Vec<&'static str> {
+        [self.normal_map, self.alpha_mask, self.shadow_pcf]
+            .into_iter()
+            .zip(Self::DEFINES)
+            .filter_map(|(enabled, define)| enabled.then_some(define))
+            .collect()
+    }
+}
+
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone, Debug)]
 struct PushConstants {
     transform: [[f32; 4]; 4],
     scene_data: vk::DeviceAddress,
     vertex_buffer: vk::DeviceAddress,
-    material_buffer: vk::DeviceAddress,
+    material: vk::DeviceAddress,
     light_buffer: vk::DeviceAddress,
 }
+gpu_layout!(PushConstants {
+    transform,
+    scene_data,
+    vertex_buffer,
+    material,
+    light_buffer
+});
+
+// the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 2] = [Shader::new::<PushConstants>("mesh.vert"), Shader::new::<PushConstants>("mesh.frag")];
 
 impl MeshPipeline {
+    /// Builds the default variant and `variants`, e.g. the ones of the pipeline being replaced.
     pub fn new(
         device: &Device,
         window_size: (u32, u32),
         deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
+        samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
         bindless_set_layout: vk::DescriptorSetLayout,
+        compiler: &mut ShaderCompiler,
+        variants: impl IntoIterator<Item = MeshVariant>,
     ) -> Self {
-        let vertex_shader = load_shader_module(device, fs::read("src/shaders/spirv/mesh.vert.spv").unwrap().as_bytes())
-            .expect("Failed to load vertex shader module");
-        let fragment_shader = load_shader_module(device, fs::read("src/shaders/spirv/mesh.frag.spv").unwrap().as_bytes())
-            .expect("Failed to load fragment shader module");
-
-        let push_constant_range = [vk::PushConstantRange::default()
-            .offset(0)
-            .size(std::mem::size_of::<PushConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)];
-        let binding = [bindless_set_layout];
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&binding)
-            .push_constant_ranges(&push_constant_range);
-        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None).unwrap() };
-        let pipeline_builder = PipelineBuilder {
-            layout: Some(layout),
-            shader_stages: vec![
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::VERTEX)
-                    .module(vertex_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::FRAGMENT)
-                    .module(fragment_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
-            ],
-            ..Default::default()
-        };
+        let [vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
 
-        let pipeline = pipeline_builder.build(device);
+        let layout = create_layout::<PushConstants>(
+            device,
+            Some(bindless_set_layout),
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        );
+        let pipeline = Self::build_variant(device, cache, samples, depth_format, layout, vertex_shader, fragment_shader);
 
         unsafe {
             device.destroy_shader_module(vertex_shader, None);
@@ -85,12 +106,114 @@ impl MeshPipeline {
             height: window_size.1,
         });
 
-        Self {
+        let mut mesh_pipeline = Self {
             viewport,
             scissor,
-            pipeline,
+            pipelines: HashMap::from([(MeshVariant::default(), pipeline)]),
+            cache,
+            samples,
+            depth_format,
             layout,
             window_size,
+        };
+        for variant in variants {
+            if !mesh_pipeline.pipelines.contains_key(&variant) {
+                mesh_pipeline.add_variant(device, compiler, deletion_queue, variant);
+            }
+        }
+        mesh_pipeline
+    }
+
+    fn build_variant(
+        device: &Device,
+        cache: vk::PipelineCache,
+        samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+        layout: vk::PipelineLayout,
+        vertex_shader: vk::ShaderModule,
+        fragment_shader: vk::ShaderModule,
+    ) -> vk::Pipeline {
+        PipelineBuilder {
+            layout: Some(layout),
+            shader_stages: vec![
+                shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+                shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+            ],
+            ..Default::default()
+        }
+        .samples(samples)
+        .depth_format(depth_format)
+        .build(device, cache)
+    }
+
+    /// Compiles the shaders with the variant's defines and builds its pipeline. If they fail to compile, the errors are
+    /// added to the compiler's and the variant is drawn like the default one until the shaders are recompiled.
+    fn add_variant(&mut self, device: &Device, compiler: &mut ShaderCompiler, deletion_queue: &mut DeletionQueue, variant: MeshVariant) {
+        let defines = variant.defines();
+        let pipeline = match SHADERS.map(|shader| shader.compile(device, compiler, &defines)) {
+            [Ok(vertex_shader), Ok(fragment_shader)] => {
+                let pipeline = Self::build_variant(
+                    device,
+                    self.cache,
+                    self.samples,
+                    self.depth_format,
+                    self.layout,
+                    vertex_shader,
+                    fragment_shader,
+                );
+                unsafe {
+                    device.destroy_shader_module(vertex_shader, None);
+                    device.destroy_shader_module(fragment_shader, None);
+                }
+                deletion_queue.push(move |device, _allocator| unsafe {
+                    device.destroy_pipeline(pipeline, None);
+                });
+                info!("Built mesh pipeline variant with {:?}", defines);
+                pipeline
+            }
+            results => {
+                for (shader, result) in SHADERS.iter().zip(results) {
+                    match result {
+                        Ok(module) => unsafe { device.destroy_shader_module(module, None) },
+                        Err(message) => compiler.errors.push(ShaderError {
+                            shader: format!("{} ({})", shader.name, defines.join(", ")),
+                            message,
+                        }),
+                    }
+                }
+                self.pipelines[&MeshVariant::default()]
+            }
+        };
+        self.pipelines.insert(variant, pipeline);
+    }
+
+    /// The variants built so far, to build them again when the pipeline is recreated.
+    pub fn variants(&self) -> Vec<MeshVariant> {
+        self.pipelines.keys().copied().collect()
+    }
+
+    /// Builds the variants the meshes' materials need that haven't been built yet. Has to be called before `draw`.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        compiler: &mut ShaderCompiler,
+        deletion_queue: &mut DeletionQueue,
+        meshes: &[&Mesh],
+        material_manager: &MaterialManager,
+        shadow_pcf: bool,
+    ) {
+        for mesh in meshes {
+            let variant = Self::variant(material_manager.get_material(mesh.material).unwrap(), shadow_pcf);
+            if !self.pipelines.contains_key(&variant) {
+                self.add_variant(device, compiler, deletion_queue, variant);
+            }
+        }
+    }
+
+    fn variant(material: &Material, shadow_pcf: bool) -> MeshVariant {
+        MeshVariant {
+            shadow_pcf,
+            ..material.data.variant()
         }
     }
 
@@ -116,14 +239,15 @@ impl MeshPipeline {
         scene_data: vk::DeviceAddress,
         material_manager: &MaterialManager,
         light_manager: &LightManager,
+        shadow_pcf: bool,
     ) {
         let color_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(target_view)
-            .image_layout(vk::ImageLayout::GENERAL);
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
         let color_attachments = [color_attachment];
         let depth_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(depth_view)
-            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
             .clear_value(vk::ClearValue {
@@ -132,6 +256,7 @@ impl MeshPipeline {
         let render_info = vk::RenderingInfo::default()
             .color_attachments(&color_attachments)
             .depth_attachment(&depth_attachment)
+            .stencil_attachment(&depth_attachment)
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
                 extent: vk::Extent2D {
@@ -151,15 +276,21 @@ impl MeshPipeline {
                 &[],
             );
             device.cmd_begin_rendering(cmd, &render_info);
-            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
 
             device.cmd_set_viewport(cmd, 0, &[self.viewport]);
             device.cmd_set_scissor(cmd, 0, &[self.scissor]);
+            let mut bound_pipeline = None;
             for mesh in meshes {
+                let material = material_manager.get_material(mesh.material).unwrap();
+                let pipeline = self.pipelines[&Self::variant(material, shadow_pcf)];
+                if bound_pipeline != Some(pipeline) {
+                    device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    bound_pipeline = Some(pipeline);
+                }
                 let push_constants = PushConstants {
                     scene_data,
                     vertex_buffer: mesh.device_address(),
-                    material_buffer: material_manager.get_material(mesh.material).unwrap().device_address(device),
+                    material: material.device_address(device),
                     transform: mesh.transform.to_cols_array_2d(),
                     light_buffer: light_manager.device_address(device),
                 };
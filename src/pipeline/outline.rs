@@ -0,0 +1,226 @@
+use crate::gpu_layout;
+use crate::pipeline::{create_layout, shader_stage, PipelineBuilder, Shader};
+use crate::scene::mesh::Mesh;
+use crate::util::DeletionQueue;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+
+/// Outlines the selected meshes. The meshes are first drawn into the stencil buffer, then drawn again pushed outwards
+/// along their normals, only where the stencil isn't set.
+pub struct OutlinePipeline {
+    viewport: vk::Viewport,
+    scissor: vk::Rect2D,
+    mask_pipeline: vk::Pipeline,
+    outline_pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    window_size: (u32, u32),
+}
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct PushConstants {
+    transform: [[f32; 4]; 4],
+    scene_data: vk::DeviceAddress,
+    vertex_buffer: vk::DeviceAddress,
+    color: [f32; 4],
+    viewport_size: [f32; 2],
+    width: f32,
+    padding: f32,
+}
+gpu_layout!(PushConstants {
+    transform,
+    scene_data,
+    vertex_buffer,
+    color,
+    viewport_size,
+    width,
+    padding
+});
+
+// the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 2] = [
+    Shader::new::<PushConstants>("outline.vert"),
+    Shader::new::<PushConstants>("outline.frag"),
+];
+
+impl OutlinePipeline {
+    const STENCIL_REFERENCE: u32 = 1;
+
+    pub fn new(
+        device: &Device,
+        window_size: (u32, u32),
+        deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
+        samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+    ) -> Self {
+        let [vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
+
+        let layout = create_layout::<PushConstants>(device, None, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        let shader_stages = vec![
+            shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+            shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+        ];
+
+        // the outline is visible through other geometry, so neither pass tests depth
+        let mask_pipeline = PipelineBuilder {
+            layout: Some(layout),
+            shader_stages: shader_stages.clone(),
+            color_blend_attachment: vk::PipelineColorBlendAttachmentState::default()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::empty()),
+            ..Default::default()
+        }
+        .depth_test(false, false)
+        .stencil(
+            vk::StencilOpState::default()
+                .compare_op(vk::CompareOp::ALWAYS)
+                .pass_op(vk::StencilOp::REPLACE)
+                .fail_op(vk::StencilOp::KEEP)
+                .depth_fail_op(vk::StencilOp::KEEP)
+                .reference(Self::STENCIL_REFERENCE)
+                .compare_mask(0xff)
+                .write_mask(0xff),
+        )
+        .samples(samples)
+        .depth_format(depth_format)
+        .build(device, cache);
+        let outline_pipeline = PipelineBuilder {
+            layout: Some(layout),
+            shader_stages,
+            color_blend_attachment: vk::PipelineColorBlendAttachmentState::default()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .color_blend_op(vk::BlendOp::ADD),
+            ..Default::default()
+        }
+        .depth_test(false, false)
+        .stencil(
+            vk::StencilOpState::default()
+                .compare_op(vk::CompareOp::NOT_EQUAL)
+                .pass_op(vk::StencilOp::KEEP)
+                .fail_op(vk::StencilOp::KEEP)
+                .depth_fail_op(vk::StencilOp::KEEP)
+                .reference(Self::STENCIL_REFERENCE)
+                .compare_mask(0xff)
+                .write_mask(0),
+        )
+        .samples(samples)
+        .depth_format(depth_format)
+        .build(device, cache);
+
+        unsafe {
+            device.destroy_shader_module(vertex_shader, None);
+            device.destroy_shader_module(fragment_shader, None);
+        }
+
+        deletion_queue.push(move |device, _allocator| unsafe {
+            device.destroy_pipeline_layout(layout, None);
+            device.destroy_pipeline(mask_pipeline, None);
+            device.destroy_pipeline(outline_pipeline, None);
+        });
+
+        let mut pipeline = Self {
+            viewport: Default::default(),
+            scissor: Default::default(),
+            mask_pipeline,
+            outline_pipeline,
+            layout,
+            window_size,
+        };
+        pipeline.resize(window_size);
+        pipeline
+    }
+
+    pub fn resize(&mut self, window_size: (u32, u32)) {
+        self.window_size = window_size;
+        self.viewport = vk::Viewport::default()
+            .width(window_size.0 as f32)
+            .height(window_size.1 as f32)
+            .max_depth(1.0);
+        self.scissor = vk::Rect2D::default().extent(vk::Extent2D {
+            width: window_size.0,
+            height: window_size.1,
+        });
+    }
+
+    /// Draws an outline of `width` pixels around the given meshes. Clears the stencil buffer.
+    pub fn draw(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        meshes: &[&Mesh],
+        target_view: vk::ImageView,
+        depth_view: vk::ImageView,
+        scene_data: vk::DeviceAddress,
+        color: [f32; 4],
+        width: f32,
+    ) {
+        if meshes.is_empty() {
+            return;
+        }
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(target_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let color_attachments = [color_attachment];
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(depth_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+        let stencil_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(depth_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+            });
+        let render_info = vk::RenderingInfo::default()
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment)
+            .stencil_attachment(&stencil_attachment)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: self.window_size.0,
+                    height: self.window_size.1,
+                },
+            })
+            .layer_count(1)
+            .view_mask(0);
+        unsafe {
+            device.cmd_begin_rendering(cmd, &render_info);
+            device.cmd_set_viewport(cmd, 0, &[self.viewport]);
+            device.cmd_set_scissor(cmd, 0, &[self.scissor]);
+            for (pipeline, width) in [(self.mask_pipeline, 0.0), (self.outline_pipeline, width)] {
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                for mesh in meshes {
+                    let push_constants = PushConstants {
+                        transform: mesh.transform.to_cols_array_2d(),
+                        scene_data,
+                        vertex_buffer: mesh.device_address(),
+                        color,
+                        viewport_size: [self.window_size.0 as f32, self.window_size.1 as f32],
+                        width,
+                        padding: 0.0,
+                    };
+                    device.cmd_push_constants(
+                        cmd,
+                        self.layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        bytemuck::cast_slice(&[push_constants]),
+                    );
+                    device.cmd_bind_index_buffer(cmd, mesh.index_buffer(), 0, vk::IndexType::UINT32);
+                    device.cmd_draw_indexed(cmd, mesh.indices.len() as u32, 1, 0, 0, 0);
+                }
+            }
+            device.cmd_end_rendering(cmd);
+        }
+    }
+}
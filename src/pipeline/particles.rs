@@ -0,0 +1,293 @@
+use crate::asset::material::MaterialManager;
+use crate::gpu_layout;
+use crate::pipeline::{create_layout, dispatch_1d, push_constants, shader_stage, ComputePipelineBuilder, PipelineBuilder, Shader};
+use crate::scene::particles::{ParticleBlend, ParticleSystem};
+use crate::util::{memory_barrier, DeletionQueue};
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+
+/// Simulates particles with a compute shader and draws them as camera-facing quads, one instance per particle.
+pub struct ParticlePipeline {
+    viewport: vk::Viewport,
+    scissor: vk::Rect2D,
+    simulate_pipeline: vk::Pipeline,
+    simulate_layout: vk::PipelineLayout,
+    alpha_pipeline: vk::Pipeline,
+    additive_pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    window_size: (u32, u32),
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct SimulatePushConstants {
+    particle_buffer: vk::DeviceAddress,
+    origin: [f32; 4],
+    velocity: [f32; 4], // w: random spread
+    gravity: [f32; 4],  // w: time step in seconds
+    lifetime: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    count: u32,
+    seed: u32,
+    padding: u32,
+}
+gpu_layout!(SimulatePushConstants {
+    particle_buffer,
+    origin,
+    velocity,
+    gravity,
+    lifetime,
+    spawn_start,
+    spawn_count,
+    count,
+    seed,
+    padding
+});
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct DrawPushConstants {
+    scene_data: vk::DeviceAddress,
+    particle_buffer: vk::DeviceAddress,
+    material: vk::DeviceAddress,
+    curve_buffer: vk::DeviceAddress,
+    flipbook_columns: u32,
+    flipbook_rows: u32,
+}
+gpu_layout!(DrawPushConstants {
+    scene_data,
+    particle_buffer,
+    material,
+    curve_buffer,
+    flipbook_columns,
+    flipbook_rows
+});
+
+// the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 3] = [
+    Shader::new::<SimulatePushConstants>("particles.comp"),
+    Shader::new::<DrawPushConstants>("particle.vert"),
+    Shader::new::<DrawPushConstants>("particle.frag"),
+];
+
+impl ParticlePipeline {
+    const WORKGROUP_SIZE: u32 = 64; // local_size_x in particles.comp
+
+    pub fn new(
+        device: &Device,
+        window_size: (u32, u32),
+        deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
+        samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+        bindless_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let [compute_shader, vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
+
+        let simulate_layout = create_layout::<SimulatePushConstants>(device, None, vk::ShaderStageFlags::COMPUTE);
+        let simulate_pipeline = ComputePipelineBuilder {
+            shader: Some(compute_shader),
+            layout: Some(simulate_layout),
+        }
+        .build(device, cache);
+
+        let layout = create_layout::<DrawPushConstants>(
+            device,
+            Some(bindless_set_layout),
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        );
+        let shader_stages = vec![
+            shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+            shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+        ];
+
+        // particles aren't sorted, so they test depth without writing it
+        let [alpha_pipeline, additive_pipeline] = [vk::BlendFactor::ONE_MINUS_SRC_ALPHA, vk::BlendFactor::ONE].map(|dst_factor| {
+            PipelineBuilder {
+                layout: Some(layout),
+                shader_stages: shader_stages.clone(),
+                color_blend_attachment: vk::PipelineColorBlendAttachmentState::default()
+                    .blend_enable(true)
+                    .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                    .dst_color_blend_factor(dst_factor)
+                    .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                    .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                    .color_write_mask(vk::ColorComponentFlags::RGBA)
+                    .alpha_blend_op(vk::BlendOp::ADD)
+                    .color_blend_op(vk::BlendOp::ADD),
+                ..Default::default()
+            }
+            .depth_test(true, false)
+            .samples(samples)
+            .depth_format(depth_format)
+            .build(device, cache)
+        });
+
+        unsafe {
+            device.destroy_shader_module(compute_shader, None);
+            device.destroy_shader_module(vertex_shader, None);
+            device.destroy_shader_module(fragment_shader, None);
+        }
+
+        deletion_queue.push(move |device, _allocator| unsafe {
+            device.destroy_pipeline_layout(simulate_layout, None);
+            device.destroy_pipeline_layout(layout, None);
+            device.destroy_pipeline(simulate_pipeline, None);
+            device.destroy_pipeline(alpha_pipeline, None);
+            device.destroy_pipeline(additive_pipeline, None);
+        });
+
+        let mut pipeline = Self {
+            viewport: Default::default(),
+            scissor: Default::default(),
+            simulate_pipeline,
+            simulate_layout,
+            alpha_pipeline,
+            additive_pipeline,
+            layout,
+            window_size,
+        };
+        pipeline.resize(window_size);
+        pipeline
+    }
+
+    pub fn resize(&mut self, window_size: (u32, u32)) {
+        self.window_size = window_size;
+        self.viewport = vk::Viewport::default()
+            .width(window_size.0 as f32)
+            .height(window_size.1 as f32)
+            .max_depth(1.0);
+        self.scissor = vk::Rect2D::default().extent(vk::Extent2D {
+            width: window_size.0,
+            height: window_size.1,
+        });
+    }
+
+    /// Advances all particles by the system's time step and spawns new ones. Has to be recorded outside of rendering, in a
+    /// render graph pass that writes the particle buffers.
+    pub fn simulate(&self, device: &Device, cmd: vk::CommandBuffer, particles: &ParticleSystem) {
+        if particles.iter().next().is_none() {
+            return;
+        }
+        let mut cleared = false;
+        for emitter in particles.iter().filter(|emitter| emitter.fresh) {
+            emitter.clear(device, cmd);
+            cleared = true;
+        }
+        // only read when drawing, which the render graph synchronizes with
+        for emitter in particles.iter().filter(|emitter| emitter.curves_dirty) {
+            emitter.upload_curves(device, cmd);
+        }
+        // the render graph only synchronizes with other passes, not within this one
+        if cleared {
+            memory_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            );
+        }
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.simulate_pipeline);
+        }
+        for emitter in particles.iter() {
+            let settings = &emitter.settings;
+            let constants = SimulatePushConstants {
+                particle_buffer: emitter.device_address(device),
+                origin: emitter.origin.extend(1.0).to_array(),
+                velocity: settings.velocity.extend(settings.spread).to_array(),
+                gravity: settings.gravity.extend(particles.dt).to_array(),
+                lifetime: settings.lifetime,
+                spawn_start: emitter.spawn_start,
+                spawn_count: emitter.spawn_count,
+                count: emitter.count(),
+                seed: particles.seed,
+                padding: 0,
+            };
+            push_constants(device, cmd, self.simulate_layout, vk::ShaderStageFlags::COMPUTE, &constants);
+            dispatch_1d(device, cmd, emitter.count(), Self::WORKGROUP_SIZE);
+        }
+    }
+
+    pub fn draw(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        particles: &ParticleSystem,
+        target_view: vk::ImageView,
+        depth_view: vk::ImageView,
+        bindless_descriptor_set: vk::DescriptorSet,
+        scene_data: vk::DeviceAddress,
+        material_manager: &MaterialManager,
+    ) {
+        if particles.iter().next().is_none() {
+            return;
+        }
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(target_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let color_attachments = [color_attachment];
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(depth_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+        let render_info = vk::RenderingInfo::default()
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment)
+            .stencil_attachment(&depth_attachment)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: self.window_size.0,
+                    height: self.window_size.1,
+                },
+            })
+            .layer_count(1)
+            .view_mask(0);
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[bindless_descriptor_set],
+                &[],
+            );
+            device.cmd_begin_rendering(cmd, &render_info);
+            device.cmd_set_viewport(cmd, 0, &[self.viewport]);
+            device.cmd_set_scissor(cmd, 0, &[self.scissor]);
+            for emitter in particles.iter() {
+                let settings = &emitter.settings;
+                let Some(material) = material_manager.get_material(settings.material) else {
+                    continue;
+                };
+                let pipeline = match settings.blend {
+                    ParticleBlend::Alpha => self.alpha_pipeline,
+                    ParticleBlend::Additive => self.additive_pipeline,
+                };
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                let constants = DrawPushConstants {
+                    scene_data,
+                    particle_buffer: emitter.device_address(device),
+                    material: material.device_address(device),
+                    curve_buffer: emitter.curve_address(device),
+                    flipbook_columns: settings.flipbook.0,
+                    flipbook_rows: settings.flipbook.1,
+                };
+                push_constants(
+                    device,
+                    cmd,
+                    self.layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    &constants,
+                );
+                device.cmd_draw(cmd, 6, emitter.count(), 0, 0);
+            }
+            device.cmd_end_rendering(cmd);
+        }
+    }
+}
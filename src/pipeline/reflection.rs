@@ -0,0 +1,374 @@
+use crate::asset::material::PbrMaterial;
+use crate::pipeline::{egui, GpuSceneData, Vertex};
+use crate::scene::billboard::RawBillboard;
+use crate::scene::light::RawLight;
+use crate::scene::particles::{RawCurveSample, RawParticle};
+use hashbrown::HashMap;
+
+/// A field of a Rust type that is mirrored in the shaders.
+#[derive(Debug, Copy, Clone)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// The fields of a Rust type in declaration order, and its size including padding.
+pub struct Layout {
+    pub fields: Vec<Field>,
+    pub size: usize,
+}
+
+/// A Rust type whose layout has to match a struct in the shaders. Implemented with `gpu_layout!`.
+pub trait GpuLayout {
+    fn layout() -> Layout;
+}
+
+/// Implements `GpuLayout` for a type from the list of its fields.
+#[macro_export]
+macro_rules! gpu_layout {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::pipeline::reflection::GpuLayout for $ty {
+            fn layout() -> $crate::pipeline::reflection::Layout {
+                $crate::pipeline::reflection::Layout {
+                    fields: vec![$($crate::pipeline::reflection::Field {
+                        name: stringify!($field),
+                        offset: std::mem::offset_of!($ty, $field),
+                        size: $crate::pipeline::reflection::size_of_field(|value: &$ty| &value.$field),
+                    }),*],
+                    size: size_of::<$ty>(),
+                }
+            }
+        }
+    };
+}
+
+pub fn size_of_field<T, F>(_field: fn(&T) -> &F) -> usize {
+    size_of::<F>()
+}
+
+// Shader structs that are mirrored by Rust types, by their name in GLSL.
+fn mirrored_structs() -> [(&'static str, Layout); 8] {
+    [
+        ("SceneDataBuffer", GpuSceneData::layout()),
+        ("PbrMaterial", PbrMaterial::layout()),
+        ("Vertex", Vertex::layout()),
+        ("EguiVertex", egui::Vertex::layout()),
+        ("Light", RawLight::layout()),
+        ("Billboard", RawBillboard::layout()),
+        ("Particle", RawParticle::layout()),
+        ("CurveSample", RawCurveSample::layout()),
+    ]
+}
+
+/// Checks the shader's push constant block against `P`, and all structs it shares with Rust against their Rust types.
+/// Members are matched in order and have to agree in name (ignoring case and underscores), offset and size. Rust
+/// fields named `padding` may be missing in the shader.
+pub fn validate<P: GpuLayout>(spirv: &[u32]) -> Result<(), String> {
+    let module = Module::parse(spirv)?;
+    let mut errors = vec![];
+    if let Some(block) = module.push_constants {
+        errors.extend(compare("push constants", &module.members(block), &P::layout(), None));
+    }
+    for (name, layout) in mirrored_structs() {
+        for id in module.structs_named(name) {
+            errors.extend(compare(name, &module.members(id), &layout, module.array_stride_of(id)));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn compare(name: &str, members: &[Member], layout: &Layout, stride: Option<usize>) -> Vec<String> {
+    let normalize = |name: &str| name.replace('_', "").to_lowercase();
+    let fields = layout
+        .fields
+        .iter()
+        .filter(|field| !field.name.starts_with("padding") || members.iter().any(|member| member.name == field.name))
+        .collect::<Vec<_>>();
+    let mut errors = vec![];
+    if fields.len() != members.len() {
+        errors.push(format!(
+            "{}: the shader has {} members ({}), Rust has {} fields ({})",
+            name,
+            members.len(),
+            members.iter().map(|member| member.name.as_str()).collect::<Vec<_>>().join(", "),
+            fields.len(),
+            fields.iter().map(|field| field.name).collect::<Vec<_>>().join(", "),
+        ));
+    }
+    for (member, field) in members.iter().zip(fields) {
+        if normalize(&member.name) != normalize(field.name) {
+            errors.push(format!("{}: member {} is named {} in Rust", name, member.name, field.name));
+        }
+        if member.offset != field.offset {
+            errors.push(format!(
+                "{}: {} is at offset {} in the shader, but {} in Rust",
+                name, member.name, member.offset, field.offset
+            ));
+        }
+        if member.size != field.size {
+            errors.push(format!(
+                "{}: {} is {} bytes in the shader, but {} in Rust",
+                name, member.name, member.size, field.size
+            ));
+        }
+    }
+    if let Some(stride) = stride.filter(|stride| *stride != layout.size) {
+        errors.push(format!(
+            "{}: array stride is {} in the shader, but the Rust size is {}",
+            name, stride, layout.size
+        ));
+    }
+    errors
+}
+
+#[derive(Debug)]
+struct Member {
+    name: String,
+    offset: usize,
+    size: usize,
+}
+
+enum Type {
+    Scalar(usize), // size in bytes
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Array { element: u32, length: u32 }, // length is the id of a constant
+    RuntimeArray { element: u32 },
+    Struct(Vec<u32>),
+    Pointer { storage_class: u32, pointee: u32 },
+    Other,
+}
+
+// The parts of a SPIR-V module that describe memory layouts.
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    offsets: HashMap<(u32, u32), usize>,
+    array_strides: HashMap<u32, usize>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    push_constants: Option<u32>, // struct type of the push constant block
+}
+
+impl Module {
+    const MAGIC: u32 = 0x0723_0203;
+    const HEADER_WORDS: usize = 5;
+    // opcodes
+    const OP_NAME: u32 = 5;
+    const OP_MEMBER_NAME: u32 = 6;
+    const OP_TYPE_BOOL: u32 = 20;
+    const OP_TYPE_INT: u32 = 21;
+    const OP_TYPE_FLOAT: u32 = 22;
+    const OP_TYPE_VECTOR: u32 = 23;
+    const OP_TYPE_MATRIX: u32 = 24;
+    const OP_TYPE_ARRAY: u32 = 28;
+    const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+    const OP_TYPE_STRUCT: u32 = 30;
+    const OP_TYPE_POINTER: u32 = 32;
+    const OP_CONSTANT: u32 = 43;
+    const OP_VARIABLE: u32 = 59;
+    const OP_DECORATE: u32 = 71;
+    const OP_MEMBER_DECORATE: u32 = 72;
+    // decorations
+    const ARRAY_STRIDE: u32 = 6;
+    const OFFSET: u32 = 35;
+    // storage classes
+    const PUSH_CONSTANT: u32 = 9;
+    const PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+    fn parse(spirv: &[u32]) -> Result<Self, String> {
+        if spirv.len() < Self::HEADER_WORDS || spirv[0] != Self::MAGIC {
+            return Err("Not a SPIR-V module".into());
+        }
+        let mut module = Self::default();
+        let mut push_constant_pointer = None;
+        let mut words = &spirv[Self::HEADER_WORDS..];
+        while let Some(&first) = words.first() {
+            let (count, opcode) = ((first >> 16) as usize, first & 0xffff);
+            if count == 0 || count > words.len() {
+                return Err("Malformed SPIR-V module".into());
+            }
+            let operands = &words[1..count];
+            let operand = |i: usize| operands.get(i).copied().unwrap_or_default();
+            match opcode {
+                Self::OP_NAME => {
+                    module.names.insert(operand(0), string(operands.get(1..).unwrap_or_default()));
+                }
+                Self::OP_MEMBER_NAME => {
+                    module
+                        .member_names
+                        .insert((operand(0), operand(1)), string(operands.get(2..).unwrap_or_default()));
+                }
+                Self::OP_TYPE_BOOL => {
+                    module.types.insert(operand(0), Type::Scalar(4));
+                }
+                Self::OP_TYPE_INT | Self::OP_TYPE_FLOAT => {
+                    module.types.insert(operand(0), Type::Scalar(operand(1) as usize / 8));
+                }
+                Self::OP_TYPE_VECTOR => {
+                    let (component, count) = (operand(1), operand(2));
+                    module.types.insert(operand(0), Type::Vector { component, count });
+                }
+                Self::OP_TYPE_MATRIX => {
+                    let (column, count) = (operand(1), operand(2));
+                    module.types.insert(operand(0), Type::Matrix { column, count });
+                }
+                Self::OP_TYPE_ARRAY => {
+                    let (element, length) = (operand(1), operand(2));
+                    module.types.insert(operand(0), Type::Array { element, length });
+                }
+                Self::OP_TYPE_RUNTIME_ARRAY => {
+                    module.types.insert(operand(0), Type::RuntimeArray { element: operand(1) });
+                }
+                Self::OP_TYPE_STRUCT => {
+                    module
+                        .types
+                        .insert(operand(0), Type::Struct(operands.get(1..).unwrap_or_default().to_vec()));
+                }
+                Self::OP_TYPE_POINTER => {
+                    let (storage_class, pointee) = (operand(1), operand(2));
+                    module.types.insert(operand(0), Type::Pointer { storage_class, pointee });
+                }
+                Self::OP_CONSTANT => {
+                    module.constants.insert(operand(1), operand(2));
+                }
+                Self::OP_VARIABLE if operand(2) == Self::PUSH_CONSTANT => {
+                    push_constant_pointer = Some(operand(0));
+                }
+                Self::OP_DECORATE if operand(1) == Self::ARRAY_STRIDE => {
+                    module.array_strides.insert(operand(0), operand(2) as usize);
+                }
+                Self::OP_MEMBER_DECORATE if operand(2) == Self::OFFSET => {
+                    module.offsets.insert((operand(0), operand(1)), operand(3) as usize);
+                }
+                _ => {}
+            }
+            words = &words[count..];
+        }
+        module.push_constants = push_constant_pointer.and_then(|pointer| match module.types.get(&pointer) {
+            Some(Type::Pointer { pointee, .. }) => Some(*pointee),
+            _ => None,
+        });
+        Ok(module)
+    }
+
+    // Only structs with explicit offsets, i.e. in buffers. Local copies of a struct are a separate type without them.
+    fn structs_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = u32> + 'a {
+        self.names
+            .iter()
+            .filter(move |(id, struct_name)| *struct_name == name && matches!(self.types.get(*id), Some(Type::Struct(_))))
+            .map(|(id, _)| *id)
+            .filter(|id| self.offsets.contains_key(&(*id, 0)))
+    }
+
+    fn members(&self, id: u32) -> Vec<Member> {
+        let Some(Type::Struct(members)) = self.types.get(&id) else {
+            return vec![];
+        };
+        members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| Member {
+                name: self.member_names.get(&(id, i as u32)).cloned().unwrap_or_default(),
+                offset: self.offsets.get(&(id, i as u32)).copied().unwrap_or_default(),
+                size: self.size_of(*member),
+            })
+            .collect()
+    }
+
+    // The stride of arrays of the struct, e.g. the light buffer's, which has to match the size of the Rust type.
+    fn array_stride_of(&self, id: u32) -> Option<usize> {
+        self.types.iter().find_map(|(array, ty)| match ty {
+            Type::Array { element, .. } | Type::RuntimeArray { element } if *element == id => self.array_strides.get(array).copied(),
+            _ => None,
+        })
+    }
+
+    fn size_of(&self, id: u32) -> usize {
+        match self.types.get(&id).unwrap_or(&Type::Other) {
+            Type::Scalar(size) => *size,
+            Type::Vector { component, count } => self.size_of(*component) * *count as usize,
+            Type::Matrix { column, count } => self.size_of(*column) * *count as usize,
+            Type::Array { element, length } => {
+                let length = self.constants.get(length).copied().unwrap_or_default() as usize;
+                let stride = self.array_strides.get(&id).copied().unwrap_or_else(|| self.size_of(*element));
+                stride * length
+            }
+            Type::RuntimeArray { .. } => 0,
+            Type::Struct(members) => (0..members.len())
+                .map(|i| self.offsets.get(&(id, i as u32)).copied().unwrap_or_default() + self.size_of(members[i]))
+                .max()
+                .unwrap_or_default(),
+            Type::Pointer {
+                storage_class: Self::PHYSICAL_STORAGE_BUFFER,
+                ..
+            } => size_of::<u64>(),
+            Type::Pointer { .. } | Type::Other => 0,
+        }
+    }
+}
+
+// A literal string, nul-terminated and packed into words.
+fn string(words: &[u32]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::compiler::ShaderCompiler;
+    use crate::pipeline::{mesh, shaders};
+    use std::path::Path;
+
+    #[test]
+    fn shaders_match_rust_types() {
+        let shaders = shaders();
+        let compiler = ShaderCompiler::new();
+        let mut failures = vec![];
+        for path in ShaderCompiler::stages() {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let result = compiler.compile(&path, &[]).and_then(|spirv| {
+                let shader = shaders
+                    .iter()
+                    .find(|shader| shader.name == name)
+                    .ok_or("not used by any pipeline, so there are no push constants to check it against")?;
+                shader.validate(&spirv)
+            });
+            if let Err(e) = result {
+                failures.push(format!("{}: {}", name, e));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn mesh_variants_match_rust_types() {
+        let compiler = ShaderCompiler::new();
+        let defines = mesh::MeshVariant::DEFINES;
+        let mut failures = vec![];
+        // every combination of defines
+        for bits in 0..1 << defines.len() {
+            let enabled = (0..defines.len())
+                .filter(|i| bits & (1 << i) != 0)
+                .map(|i| defines[i])
+                .collect::<Vec<_>>();
+            for shader in mesh::SHADERS {
+                let path = Path::new(ShaderCompiler::SOURCE_DIR).join(shader.name);
+                if let Err(e) = compiler.compile(&path, &enabled).and_then(|spirv| shader.validate(&spirv)) {
+                    failures.push(format!("{} {:?}: {}", shader.name, enabled, e));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
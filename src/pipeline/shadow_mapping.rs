@@ -1,14 +1,11 @@
-use crate::pipeline::PipelineBuilder;
+use crate::gpu_layout;
+use crate::pipeline::{create_layout, shader_stage, PipelineBuilder, Shader};
 use crate::scene::mesh::Mesh;
-use crate::util::{load_shader_module, DeletionQueue};
+use crate::util::DeletionQueue;
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
 
 use crate::scene::light::{LightId, LightManager};
-use crate::DEPTH_FORMAT;
-use image::EncodableLayout;
-use std::ffi::CStr;
-use std::fs;
 
 pub struct ShadowMappingPipeline {
     viewport: vk::Viewport,
@@ -24,34 +21,39 @@ struct PushConstants {
     vertex_buffer: vk::DeviceAddress,
     light_buffer: vk::DeviceAddress,
 }
+gpu_layout!(PushConstants {
+    transform,
+    scene_data,
+    vertex_buffer,
+    light_buffer
+});
 pub const SHADOW_MAP_SIZE: (u32, u32) = (2048, 2048);
+pub const SHADOW_MAP_FORMAT: vk::Format = vk::Format::D32_SFLOAT; // shadow maps are sampled, so they don't share the stencil format
+                                                                  // the shaders, in the order they're loaded in
+pub(crate) const SHADERS: [Shader; 2] = [
+    Shader::new::<PushConstants>("shadow_mapping.vert"),
+    Shader::new::<PushConstants>("shadow_mapping.frag"),
+];
+
 impl ShadowMappingPipeline {
-    pub fn new(device: &Device, deletion_queue: &mut DeletionQueue, bindless_set_layout: vk::DescriptorSetLayout) -> Self {
-        let vertex_shader = load_shader_module(device, fs::read("src/shaders/spirv/shadow_mapping.vert.spv").unwrap().as_bytes())
-            .expect("Failed to load vertex shader module");
-        let fragment_shader = load_shader_module(device, fs::read("src/shaders/spirv/shadow_mapping.frag.spv").unwrap().as_bytes())
-            .expect("Failed to load fragment shader module");
+    pub fn new(
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        cache: vk::PipelineCache,
+        bindless_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let [vertex_shader, fragment_shader] = SHADERS.map(|shader| shader.load(device));
 
-        let push_constant_range = [vk::PushConstantRange::default()
-            .offset(0)
-            .size(std::mem::size_of::<PushConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)];
-        let binding = [bindless_set_layout];
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&binding)
-            .push_constant_ranges(&push_constant_range);
-        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None).unwrap() };
+        let layout = create_layout::<PushConstants>(
+            device,
+            Some(bindless_set_layout),
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        );
         let pipeline_builder = PipelineBuilder {
             layout: Some(layout),
             shader_stages: vec![
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::VERTEX)
-                    .module(vertex_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(vk::ShaderStageFlags::FRAGMENT)
-                    .module(fragment_shader)
-                    .name(CStr::from_bytes_with_nul(b"main\0").unwrap()),
+                shader_stage(vk::ShaderStageFlags::VERTEX, vertex_shader),
+                shader_stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader),
             ],
             rasterization: vk::PipelineRasterizationStateCreateInfo::default()
                 .polygon_mode(vk::PolygonMode::FILL)
@@ -61,7 +63,7 @@ impl ShadowMappingPipeline {
                 .depth_bias_enable(true)
                 .depth_bias_constant_factor(1.25)
                 .depth_bias_slope_factor(1.5),
-            render_info: vk::PipelineRenderingCreateInfo::default().depth_attachment_format(DEPTH_FORMAT),
+            render_info: vk::PipelineRenderingCreateInfo::default().depth_attachment_format(SHADOW_MAP_FORMAT),
             depth_stencil: vk::PipelineDepthStencilStateCreateInfo::default()
                 .depth_test_enable(true)
                 .depth_write_enable(true)
@@ -69,7 +71,7 @@ impl ShadowMappingPipeline {
             ..Default::default()
         };
 
-        let pipeline = pipeline_builder.build(device);
+        let pipeline = pipeline_builder.build(device, cache);
 
         unsafe {
             device.destroy_shader_module(vertex_shader, None);
@@ -0,0 +1,717 @@
+use crate::resource::image::AllocatedImage;
+use crate::resource::{AllocUsage, Allocator};
+use crate::util::DeletionQueue;
+use ash::{vk, Device};
+use std::cell::RefCell;
+
+/// How a pass uses an image, which determines the layout the image is transitioned to and what barriers wait for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageUsage {
+    ColorAttachment,
+    DepthStencilAttachment,
+    DepthAttachment, // for depth-only formats
+    Sampled(vk::PipelineStageFlags2),
+    TransferSrc,
+    TransferDst,
+    Present, // only as the final usage of an exported image
+}
+
+impl ImageUsage {
+    fn layout(self) -> vk::ImageLayout {
+        match self {
+            ImageUsage::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageUsage::DepthStencilAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageUsage::DepthAttachment => vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            ImageUsage::Sampled(_) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageUsage::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageUsage::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageUsage::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    fn stage(self) -> vk::PipelineStageFlags2 {
+        match self {
+            ImageUsage::ColorAttachment => vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            ImageUsage::DepthStencilAttachment | ImageUsage::DepthAttachment => {
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+            }
+            ImageUsage::Sampled(stage) => stage,
+            ImageUsage::TransferSrc | ImageUsage::TransferDst => vk::PipelineStageFlags2::ALL_TRANSFER,
+            ImageUsage::Present => vk::PipelineStageFlags2::NONE,
+        }
+    }
+
+    fn access(self, write: bool) -> vk::AccessFlags2 {
+        match (self, write) {
+            (ImageUsage::ColorAttachment, false) => vk::AccessFlags2::COLOR_ATTACHMENT_READ,
+            (ImageUsage::ColorAttachment, true) => vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            (ImageUsage::DepthStencilAttachment | ImageUsage::DepthAttachment, false) => vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ,
+            (ImageUsage::DepthStencilAttachment | ImageUsage::DepthAttachment, true) => {
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            (ImageUsage::Sampled(_), _) => vk::AccessFlags2::SHADER_SAMPLED_READ,
+            (ImageUsage::TransferSrc, _) => vk::AccessFlags2::TRANSFER_READ,
+            (ImageUsage::TransferDst, _) => vk::AccessFlags2::TRANSFER_WRITE,
+            (ImageUsage::Present, _) => vk::AccessFlags2::NONE,
+        }
+    }
+}
+
+/// How a pass uses a buffer. Buffers are synchronized with global memory barriers, so they're only tracked by name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+    Storage(vk::PipelineStageFlags2),
+    TransferDst,
+}
+
+impl BufferUsage {
+    fn stage(self) -> vk::PipelineStageFlags2 {
+        match self {
+            BufferUsage::Storage(stage) => stage,
+            BufferUsage::TransferDst => vk::PipelineStageFlags2::ALL_TRANSFER,
+        }
+    }
+
+    fn access(self, write: bool) -> vk::AccessFlags2 {
+        match (self, write) {
+            (BufferUsage::Storage(_), false) => vk::AccessFlags2::SHADER_STORAGE_READ,
+            (BufferUsage::Storage(_), true) => vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            (BufferUsage::TransferDst, _) => vk::AccessFlags2::TRANSFER_WRITE,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+/// A transient image, which the graph allocates for the frame if a pass that isn't culled uses it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    pub samples: vk::SampleCountFlags,
+}
+
+struct Image {
+    name: String,
+    aspect: vk::ImageAspectFlags,
+    transient: Option<ImageDesc>,
+    image: vk::Image, // null for transient images until the graph is executed
+    view: vk::ImageView,
+    initial_layout: vk::ImageLayout,
+    exported: Option<ImageUsage>, // the usage it's left in after the graph
+}
+
+/// The last access to a resource, which the next one has to wait for.
+#[derive(Copy, Clone)]
+struct State {
+    layout: vk::ImageLayout, // UNDEFINED for buffers
+    stage: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    written: bool,
+}
+
+impl State {
+    // resources come from previous frames, so their first access waits for everything before it
+    fn imported(layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            stage: vk::PipelineStageFlags2::ALL_COMMANDS,
+            access: vk::AccessFlags2::MEMORY_WRITE,
+            written: true,
+        }
+    }
+}
+
+/// A use of a resource in a pass, merged from everything the pass declared for it.
+#[derive(Copy, Clone)]
+struct Access {
+    layout: vk::ImageLayout,
+    stage: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    write: bool,
+}
+
+impl Access {
+    fn merge(&mut self, other: Access, resource: &str) {
+        assert_eq!(self.layout, other.layout, "{} is used in two layouts in the same pass", resource);
+        self.stage |= other.stage;
+        self.access |= other.access;
+        self.write |= other.write;
+    }
+}
+
+struct Pass<'a> {
+    name: String,
+    images: Vec<(ImageHandle, Access)>,
+    buffers: Vec<(BufferHandle, Access)>,
+    record: Box<dyn FnOnce(&PassContext) + 'a>,
+}
+
+/// What a pass records its commands with.
+pub struct PassContext<'g> {
+    pub device: &'g Device,
+    pub cmd: vk::CommandBuffer,
+    images: &'g [Image],
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, handle: ImageHandle) -> vk::Image {
+        self.images[handle.0].image
+    }
+
+    pub fn view(&self, handle: ImageHandle) -> vk::ImageView {
+        self.images[handle.0].view
+    }
+}
+
+/// Declares the resources a pass uses, see `RenderGraph::pass`.
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    name: String,
+    images: Vec<(ImageHandle, Access)>,
+    buffers: Vec<(BufferHandle, Access)>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn read_image(self, image: ImageHandle, usage: ImageUsage) -> Self {
+        self.image(image, usage, false)
+    }
+
+    /// Writing includes reading, e.g. attachments that are loaded.
+    pub fn write_image(self, image: ImageHandle, usage: ImageUsage) -> Self {
+        self.image(image, usage, true)
+    }
+
+    fn image(mut self, image: ImageHandle, usage: ImageUsage, write: bool) -> Self {
+        let access = Access {
+            layout: usage.layout(),
+            stage: usage.stage(),
+            access: usage.access(write),
+            write,
+        };
+        match self.images.iter_mut().find(|(handle, _)| *handle == image) {
+            Some((_, existing)) => existing.merge(access, &self.graph.images[image.0].name),
+            None => self.images.push((image, access)),
+        }
+        self
+    }
+
+    pub fn read_buffer(self, buffer: BufferHandle, usage: BufferUsage) -> Self {
+        self.buffer(buffer, usage, false)
+    }
+
+    pub fn write_buffer(self, buffer: BufferHandle, usage: BufferUsage) -> Self {
+        self.buffer(buffer, usage, true)
+    }
+
+    fn buffer(mut self, buffer: BufferHandle, usage: BufferUsage, write: bool) -> Self {
+        let access = Access {
+            layout: vk::ImageLayout::UNDEFINED,
+            stage: usage.stage(),
+            access: usage.access(write),
+            write,
+        };
+        match self.buffers.iter_mut().find(|(handle, _)| *handle == buffer) {
+            Some((_, existing)) => existing.merge(access, &self.graph.buffers[buffer.0]),
+            None => self.buffers.push((buffer, access)),
+        }
+        self
+    }
+
+    /// Adds the pass, which records its commands with `record` once the graph is executed.
+    pub fn record(self, record: impl FnOnce(&PassContext) + 'a) {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            images: self.images,
+            buffers: self.buffers,
+            record: Box::new(record),
+        });
+    }
+}
+
+/// The passes of a frame, in the order they're recorded in. Passes declare which images and buffers they read and
+/// write, the graph derives the layout transitions and barriers between them from that. Passes that nothing exported
+/// depends on are culled.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    images: Vec<Image>,
+    buffers: Vec<String>,
+    passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    /// An image that lives outside the graph, in `initial_layout` at the start of the frame.
+    pub fn import_image(
+        &mut self,
+        name: impl Into<String>,
+        image: vk::Image,
+        view: vk::ImageView,
+        aspect: vk::ImageAspectFlags,
+        initial_layout: vk::ImageLayout,
+    ) -> ImageHandle {
+        self.images.push(Image {
+            name: name.into(),
+            aspect,
+            transient: None,
+            image,
+            view,
+            initial_layout,
+            exported: None,
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// An image that only lives for the frame. Its contents are undefined before the first pass writes it.
+    pub fn create_image(&mut self, name: impl Into<String>, desc: ImageDesc) -> ImageHandle {
+        self.images.push(Image {
+            name: name.into(),
+            aspect: desc.aspect,
+            transient: Some(desc),
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            exported: None,
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// Keeps the passes writing the image and leaves it in `usage`'s layout after the graph.
+    pub fn export_image(&mut self, image: ImageHandle, usage: ImageUsage) {
+        self.images[image.0].exported = Some(usage);
+    }
+
+    pub fn import_buffer(&mut self, name: impl Into<String>) -> BufferHandle {
+        self.buffers.push(name.into());
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    pub fn pass<'g>(&'g mut self, name: impl Into<String>) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            name: name.into(),
+            images: vec![],
+            buffers: vec![],
+        }
+    }
+
+    /// Which passes are needed for the exported images, walking back from the last pass. A pass is needed if it writes
+    /// something a needed pass uses afterwards.
+    fn needed_passes(&self) -> Vec<bool> {
+        let mut needed_images = self.images.iter().map(|image| image.exported.is_some()).collect::<Vec<_>>();
+        let mut needed_buffers = vec![false; self.buffers.len()];
+        let mut needed_passes = vec![false; self.passes.len()];
+        for (i, pass) in self.passes.iter().enumerate().rev() {
+            let writes_needed = pass.images.iter().any(|(image, access)| access.write && needed_images[image.0])
+                || pass.buffers.iter().any(|(buffer, access)| access.write && needed_buffers[buffer.0]);
+            if writes_needed {
+                needed_passes[i] = true;
+                pass.images.iter().for_each(|(image, _)| needed_images[image.0] = true);
+                pass.buffers.iter().for_each(|(buffer, _)| needed_buffers[buffer.0] = true);
+            }
+        }
+        needed_passes
+    }
+
+    /// Records the passes that aren't culled into `cmd`, with barriers in between. Transient images are taken from
+    /// `transient_images`. The allocator is only borrowed while they're allocated, since passes may allocate too.
+    pub fn execute(
+        mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        transient_images: &mut TransientImages,
+        allocator: &RefCell<Allocator>,
+    ) -> RenderGraphInfo {
+        let needed = self.needed_passes();
+        let mut info = RenderGraphInfo::default();
+
+        let mut used = vec![false; self.images.len()];
+        for (pass, _) in self.passes.iter().zip(&needed).filter(|(_, needed)| **needed) {
+            pass.images.iter().for_each(|(image, _)| used[image.0] = true);
+        }
+        let mut taken = vec![];
+        for (i, image) in self.images.iter_mut().enumerate() {
+            if let (Some(desc), true) = (image.transient, used[i]) {
+                let index = transient_images.acquire(device, &mut allocator.borrow_mut(), desc, &image.name, &taken);
+                taken.push(index);
+                (image.image, image.view) = transient_images.handles(index);
+                info.transient_images.push(format!(
+                    "{} ({:?}, {}x{}, {} samples)",
+                    image.name,
+                    desc.format,
+                    desc.extent.width,
+                    desc.extent.height,
+                    desc.samples.as_raw()
+                ));
+            }
+        }
+
+        let mut image_states = self
+            .images
+            .iter()
+            .map(|image| State::imported(image.initial_layout))
+            .collect::<Vec<_>>();
+        let mut buffer_states = vec![State::imported(vk::ImageLayout::UNDEFINED); self.buffers.len()];
+        for (pass, needed) in self.passes.into_iter().zip(needed) {
+            let mut pass_info = PassInfo {
+                name: pass.name,
+                culled: !needed,
+                ..Default::default()
+            };
+            for (image, access) in &pass.images {
+                let list = if access.write {
+                    &mut pass_info.writes
+                } else {
+                    &mut pass_info.reads
+                };
+                list.push(self.images[image.0].name.clone());
+            }
+            for (buffer, access) in &pass.buffers {
+                let list = if access.write {
+                    &mut pass_info.writes
+                } else {
+                    &mut pass_info.reads
+                };
+                list.push(self.buffers[buffer.0].clone());
+            }
+            if !needed {
+                info.passes.push(pass_info);
+                continue;
+            }
+
+            let mut image_barriers = vec![];
+            for (image, access) in &pass.images {
+                let resource = &self.images[image.0];
+                if let Some(barrier) = Self::transition(&mut image_states[image.0], *access) {
+                    pass_info.barriers.push(Self::describe(&resource.name, &barrier));
+                    image_barriers.push(Self::image_barrier(resource, barrier));
+                }
+            }
+            let mut memory_barriers = vec![];
+            for (buffer, access) in &pass.buffers {
+                if let Some(barrier) = Self::transition(&mut buffer_states[buffer.0], *access) {
+                    pass_info.barriers.push(Self::describe(&self.buffers[buffer.0], &barrier));
+                    memory_barriers.push(
+                        vk::MemoryBarrier2::default()
+                            .src_stage_mask(barrier.0.stage)
+                            .src_access_mask(barrier.0.access)
+                            .dst_stage_mask(barrier.1.stage)
+                            .dst_access_mask(barrier.1.access),
+                    );
+                }
+            }
+            Self::barrier(device, cmd, &image_barriers, &memory_barriers);
+
+            (pass.record)(&PassContext {
+                device,
+                cmd,
+                images: &self.images,
+            });
+            info.passes.push(pass_info);
+        }
+
+        let mut final_barriers = vec![];
+        for (i, image) in self.images.iter().enumerate() {
+            let Some(usage) = image.exported else {
+                continue;
+            };
+            let access = Access {
+                layout: usage.layout(),
+                stage: usage.stage(),
+                access: usage.access(false),
+                write: false,
+            };
+            if let Some(barrier) = Self::transition(&mut image_states[i], access) {
+                info.final_barriers.push(Self::describe(&image.name, &barrier));
+                final_barriers.push(Self::image_barrier(image, barrier));
+            }
+        }
+        Self::barrier(device, cmd, &final_barriers, &[]);
+        info
+    }
+
+    /// Updates the state of a resource for the next access. Returns the states to synchronize between, if the access
+    /// has to wait for the previous ones.
+    fn transition(state: &mut State, access: Access) -> Option<(State, State)> {
+        if state.layout == access.layout && !state.written && !access.write {
+            // reads don't have to wait for each other, but a later write has to wait for all of them
+            state.stage |= access.stage;
+            state.access |= access.access;
+            return None;
+        }
+        let previous = *state;
+        *state = State {
+            layout: access.layout,
+            stage: access.stage,
+            access: access.access,
+            written: access.write,
+        };
+        Some((previous, *state))
+    }
+
+    fn image_barrier(image: &Image, (src, dst): (State, State)) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(src.stage)
+            .src_access_mask(src.access)
+            .dst_stage_mask(dst.stage)
+            .dst_access_mask(dst.access)
+            .old_layout(src.layout)
+            .new_layout(dst.layout)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(image.aspect)
+                    .level_count(vk::REMAINING_MIP_LEVELS)
+                    .layer_count(vk::REMAINING_ARRAY_LAYERS),
+            )
+            .image(image.image)
+    }
+
+    fn barrier(
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        image_barriers: &[vk::ImageMemoryBarrier2],
+        memory_barriers: &[vk::MemoryBarrier2],
+    ) {
+        if image_barriers.is_empty() && memory_barriers.is_empty() {
+            return;
+        }
+        let dependency_info = vk::DependencyInfo::default()
+            .image_memory_barriers(image_barriers)
+            .memory_barriers(memory_barriers);
+        unsafe { device.cmd_pipeline_barrier2(cmd, &dependency_info) }
+    }
+
+    fn describe(resource: &str, (src, dst): &(State, State)) -> String {
+        if src.layout == dst.layout {
+            format!("{}: {:?} -> {:?}", resource, src.stage, dst.stage)
+        } else {
+            format!("{}: {:?} -> {:?}", resource, src.layout, dst.layout)
+        }
+    }
+}
+
+/// What the graph did when it was last executed, for the debug UI.
+#[derive(Default)]
+pub struct RenderGraphInfo {
+    pub passes: Vec<PassInfo>,
+    pub transient_images: Vec<String>,
+    pub final_barriers: Vec<String>, // transitions of exported images
+}
+
+#[derive(Default)]
+pub struct PassInfo {
+    pub name: String,
+    pub culled: bool,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+    pub barriers: Vec<String>, // recorded before the pass
+}
+
+/// The images backing transient resources. They're kept across frames and reused as long as a graph asks for an image
+/// with the same description, e.g. until the window is resized.
+#[derive(Default)]
+pub struct TransientImages {
+    images: Vec<(ImageDesc, AllocatedImage, bool)>, // whether it was used since the last garbage collection
+}
+
+impl TransientImages {
+    /// Finds an image matching `desc` that isn't in `taken` yet or allocates a new one. Returns its index.
+    fn acquire(&mut self, device: &Device, allocator: &mut Allocator, desc: ImageDesc, name: &str, taken: &[usize]) -> usize {
+        let existing = self
+            .images
+            .iter()
+            .enumerate()
+            .position(|(i, (image_desc, _, _))| *image_desc == desc && !taken.contains(&i));
+        let index = existing.unwrap_or_else(|| {
+            let image = AllocatedImage::new(
+                device,
+                allocator,
+                vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1,
+                },
+                1,
+                desc.samples,
+                desc.format,
+                desc.usage,
+                AllocUsage::GpuOnly,
+                desc.aspect,
+                vk::ImageCreateFlags::empty(),
+                Some(name.into()),
+            );
+            self.images.push((desc, image, false));
+            self.images.len() - 1
+        });
+        self.images[index].2 = true;
+        index
+    }
+
+    fn handles(&self, index: usize) -> (vk::Image, vk::ImageView) {
+        let image = &self.images[index].1;
+        (image.image, image.view)
+    }
+
+    /// Hands images no graph used since the last call to the (per-frame) deletion queue.
+    pub fn collect_garbage(&mut self, deletion_queue: &mut DeletionQueue) {
+        let (used, unused): (Vec<_>, Vec<_>) = self.images.drain(..).partition(|(_, _, used)| *used);
+        for (_, image, _) in unused {
+            deletion_queue.push(move |device, allocator| image.destroy(device, allocator));
+        }
+        self.images = used.into_iter().map(|(desc, image, _)| (desc, image, false)).collect();
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for (_, image, _) in self.images.drain(..) {
+            image.destroy(device, allocator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render_graph::{Access, BufferUsage, ImageDesc, ImageHandle, ImageUsage, RenderGraph, State};
+    use ash::vk;
+
+    fn transient(graph: &mut RenderGraph, name: &str) -> ImageHandle {
+        graph.create_image(
+            name,
+            ImageDesc {
+                format: vk::Format::R8G8B8A8_UNORM,
+                extent: vk::Extent2D { width: 1, height: 1 },
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                aspect: vk::ImageAspectFlags::COLOR,
+                samples: vk::SampleCountFlags::TYPE_1,
+            },
+        )
+    }
+
+    fn access(usage: ImageUsage, write: bool) -> Access {
+        Access {
+            layout: usage.layout(),
+            stage: usage.stage(),
+            access: usage.access(write),
+            write,
+        }
+    }
+
+    #[test]
+    fn passes_nothing_exported_depends_on_are_culled() {
+        let mut graph = RenderGraph::default();
+        let target = graph.import_image(
+            "Target",
+            vk::Image::null(),
+            vk::ImageView::null(),
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+        );
+        graph.export_image(target, ImageUsage::Present);
+        let shadow = transient(&mut graph, "Shadow");
+        let scratch = transient(&mut graph, "Scratch");
+        let particles = graph.import_buffer("Particles");
+        let unused = graph.import_buffer("Unused");
+
+        graph
+            .pass("Unused image")
+            .write_image(scratch, ImageUsage::ColorAttachment)
+            .record(|_| {});
+        graph
+            .pass("Unused buffer")
+            .write_buffer(unused, BufferUsage::Storage(vk::PipelineStageFlags2::COMPUTE_SHADER))
+            .record(|_| {});
+        graph.pass("Shadow").write_image(shadow, ImageUsage::ColorAttachment).record(|_| {});
+        graph
+            .pass("Simulate")
+            .write_buffer(particles, BufferUsage::Storage(vk::PipelineStageFlags2::COMPUTE_SHADER))
+            .record(|_| {});
+        graph
+            .pass("Draw")
+            .read_image(shadow, ImageUsage::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER))
+            .read_buffer(particles, BufferUsage::Storage(vk::PipelineStageFlags2::VERTEX_SHADER))
+            .write_image(target, ImageUsage::ColorAttachment)
+            .record(|_| {});
+        // only reads the target, and what it writes isn't used
+        graph
+            .pass("Debug")
+            .read_image(target, ImageUsage::TransferSrc)
+            .write_image(scratch, ImageUsage::TransferDst)
+            .record(|_| {});
+
+        assert_eq!(graph.needed_passes(), [false, false, true, true, true, false]);
+    }
+
+    #[test]
+    fn writes_to_exported_images_are_kept_even_if_overwritten() {
+        let mut graph = RenderGraph::default();
+        let target = transient(&mut graph, "Target");
+        graph.export_image(target, ImageUsage::TransferSrc);
+        graph.pass("Clear").write_image(target, ImageUsage::TransferDst).record(|_| {});
+        graph.pass("Draw").write_image(target, ImageUsage::ColorAttachment).record(|_| {});
+
+        assert_eq!(graph.needed_passes(), [true, true]);
+    }
+
+    #[test]
+    fn first_access_waits_for_previous_frames() {
+        let mut state = State::imported(vk::ImageLayout::UNDEFINED);
+        let (src, dst) = RenderGraph::transition(&mut state, access(ImageUsage::TransferDst, true)).unwrap();
+        assert_eq!(src.layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(src.stage, vk::PipelineStageFlags2::ALL_COMMANDS);
+        assert_eq!(dst.layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(dst.access, vk::AccessFlags2::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn reads_in_the_same_layout_share_a_barrier() {
+        let mut state = State::imported(vk::ImageLayout::UNDEFINED);
+        RenderGraph::transition(&mut state, access(ImageUsage::ColorAttachment, true)).unwrap();
+        let vertex = ImageUsage::Sampled(vk::PipelineStageFlags2::VERTEX_SHADER);
+        let fragment = ImageUsage::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER);
+
+        let (src, dst) = RenderGraph::transition(&mut state, access(vertex, false)).unwrap();
+        assert_eq!(src.layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(
+            src.access,
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(dst.layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert!(RenderGraph::transition(&mut state, access(fragment, false)).is_none());
+
+        // the next write waits for both reads
+        let (src, dst) = RenderGraph::transition(&mut state, access(ImageUsage::ColorAttachment, true)).unwrap();
+        assert_eq!(
+            src.stage,
+            vk::PipelineStageFlags2::VERTEX_SHADER | vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(src.layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(dst.layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    }
+
+    #[test]
+    fn reads_in_another_layout_are_transitioned() {
+        let mut state = State::imported(vk::ImageLayout::UNDEFINED);
+        RenderGraph::transition(&mut state, access(ImageUsage::ColorAttachment, true)).unwrap();
+        RenderGraph::transition(&mut state, access(ImageUsage::TransferSrc, false)).unwrap();
+        let (src, dst) = RenderGraph::transition(
+            &mut state,
+            access(ImageUsage::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER), false),
+        )
+        .unwrap();
+        assert_eq!(src.layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(dst.layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn writes_wait_for_writes() {
+        let mut state = State::imported(vk::ImageLayout::UNDEFINED);
+        RenderGraph::transition(&mut state, access(ImageUsage::ColorAttachment, true)).unwrap();
+        let (src, dst) = RenderGraph::transition(&mut state, access(ImageUsage::ColorAttachment, true)).unwrap();
+        assert_eq!(src.layout, dst.layout);
+        assert!(src.access.contains(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE));
+    }
+}
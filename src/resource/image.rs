@@ -2,7 +2,7 @@ use crate::asset::texture::TextureKind;
 use crate::resource::buffer::AllocatedBuffer;
 use crate::resource::immediate_submit::SubmitContext;
 use crate::resource::{AllocUsage, Allocation, Allocator, LOG_ALLOCATIONS};
-use crate::util::transition_image;
+use crate::util::{transition_image, transition_image_mip};
 use ash::vk::DeviceSize;
 use ash::{vk, Device};
 use gpu_alloc_ash::AshMemoryDevice;
@@ -15,15 +15,23 @@ pub struct AllocatedImage {
     pub allocation: Allocation,
     pub extent: vk::Extent3D,
     pub format: vk::Format,
+    pub mip_levels: u32,
     pub label: Option<String>,
     // pub kind: ImageKind,
 }
 
+/// Number of mip levels in a full mip chain down to 1x1 for the given extent.
+pub fn mip_level_count(extent: vk::Extent3D) -> u32 {
+    32 - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
 impl AllocatedImage {
     pub fn new(
         device: &Device,
         allocator: &mut Allocator,
         extent: vk::Extent3D,
+        mip_levels: u32,
+        samples: vk::SampleCountFlags,
         format: vk::Format,
         image_usages: vk::ImageUsageFlags,
         alloc_usages: AllocUsage,
@@ -36,10 +44,10 @@ impl AllocatedImage {
             .image_type(vk::ImageType::TYPE_2D)
             .format(format)
             .extent(extent)
-            .mip_levels(1)
+            .mip_levels(mip_levels)
             .flags(flags)
             .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(image_usages)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
@@ -85,7 +93,7 @@ impl AllocatedImage {
                 vk::ImageSubresourceRange::default()
                     .aspect_mask(image_aspect)
                     .base_mip_level(0)
-                    .level_count(1)
+                    .level_count(mip_levels)
                     .base_array_layer(0)
                     .layer_count(1),
             );
@@ -96,26 +104,78 @@ impl AllocatedImage {
             allocation,
             extent,
             format,
+            mip_levels,
             label,
             // kind,
         }
     }
 
+    /// Uploads `data` into the first mip level. If the image has more than one mip level, the rest of the chain is generated
+    /// with blits, so the format must support `BLIT_SRC`, `BLIT_DST` and linear filtering.
     pub fn write<'a>(&'a self, data: &'a [u8], ctx: &mut SubmitContext) {
+        self.upload(&[data], ctx);
+        if self.mip_levels > 1 {
+            self.generate_mipmaps(ctx);
+        } else {
+            transition_image(
+                &ctx.device,
+                ctx.cmd_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        }
+    }
+
+    /// Uploads precomputed mip levels, starting with the largest one. Expects exactly one slice per mip level.
+    pub fn write_levels(&self, levels: &[&[u8]], ctx: &mut SubmitContext) {
+        assert_eq!(levels.len(), self.mip_levels as usize, "expected one slice per mip level");
+        self.upload(levels, ctx);
+        transition_image(
+            &ctx.device,
+            ctx.cmd_buffer,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+
+    // Copies the given levels into the image through a single staging buffer. Leaves the image in TRANSFER_DST_OPTIMAL.
+    fn upload(&self, levels: &[&[u8]], ctx: &mut SubmitContext) {
+        let size = levels.iter().map(|level| level.len()).sum::<usize>();
         let mut staging = AllocatedBuffer::new(
             &ctx.device,
             &mut ctx.allocator.borrow_mut(),
             vk::BufferUsageFlags::TRANSFER_SRC,
             AllocUsage::UploadToHost,
-            data.len() as DeviceSize,
+            size as DeviceSize,
             Some(format!("Staging buffer for image '{}'", self.label.clone().unwrap_or_default())),
         );
+        let mut copy_regions = Vec::with_capacity(levels.len());
         unsafe {
             let data_ptr = staging
                 .allocation
                 .map(AshMemoryDevice::wrap(&ctx.device), 0, staging.size as usize)
                 .unwrap();
-            std::ptr::copy_nonoverlapping(data.as_ptr(), data_ptr.as_ptr(), data.len());
+            let mut offset = 0;
+            for (mip_level, data) in levels.iter().enumerate() {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), data_ptr.as_ptr().add(offset), data.len());
+                copy_regions.push(
+                    vk::BufferImageCopy::default()
+                        .buffer_offset(offset as DeviceSize)
+                        .buffer_row_length(0)
+                        .buffer_image_height(0)
+                        .image_subresource(
+                            vk::ImageSubresourceLayers::default()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .mip_level(mip_level as u32)
+                                .base_array_layer(0)
+                                .layer_count(1),
+                        )
+                        .image_extent(self.mip_extent(mip_level as u32)),
+                );
+                offset += data.len();
+            }
         }
         transition_image(
             &ctx.device,
@@ -124,19 +184,6 @@ impl AllocatedImage {
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
         );
-        let copy_region = vk::BufferImageCopy::default()
-            .buffer_offset(0)
-            .buffer_row_length(0)
-            .buffer_image_height(0)
-            .image_subresource(
-                vk::ImageSubresourceLayers::default()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .mip_level(0)
-                    .base_array_layer(0)
-                    .layer_count(1),
-            )
-            .image_extent(self.extent);
-        let copy_regions = [copy_region];
         unsafe {
             ctx.device.cmd_copy_buffer_to_image(
                 ctx.cmd_buffer,
@@ -146,16 +193,92 @@ impl AllocatedImage {
                 &copy_regions,
             );
         }
-        transition_image(
+        ctx.add_cleanup(Box::from(|device: &Device, allocator: &mut Allocator| {
+            staging.destroy(device, allocator);
+        }));
+    }
+
+    /// Fills mip levels 1.. by successively blitting each level into the next one.
+    /// Expects all levels to be in TRANSFER_DST_OPTIMAL, leaves them in SHADER_READ_ONLY_OPTIMAL.
+    pub fn generate_mipmaps(&self, ctx: &mut SubmitContext) {
+        for mip_level in 1..self.mip_levels {
+            let src = self.mip_extent(mip_level - 1);
+            let dst = self.mip_extent(mip_level);
+            transition_image_mip(
+                &ctx.device,
+                ctx.cmd_buffer,
+                self.image,
+                mip_level - 1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+            let blit = vk::ImageBlit::default()
+                .src_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: src.width as i32,
+                        y: src.height as i32,
+                        z: 1,
+                    },
+                ])
+                .dst_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: dst.width as i32,
+                        y: dst.height as i32,
+                        z: 1,
+                    },
+                ])
+                .src_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(mip_level - 1)
+                        .base_array_layer(0)
+                        .layer_count(1),
+                )
+                .dst_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(mip_level)
+                        .base_array_layer(0)
+                        .layer_count(1),
+                );
+            unsafe {
+                ctx.device.cmd_blit_image(
+                    ctx.cmd_buffer,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+            }
+            transition_image_mip(
+                &ctx.device,
+                ctx.cmd_buffer,
+                self.image,
+                mip_level - 1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        }
+        transition_image_mip(
             &ctx.device,
             ctx.cmd_buffer,
             self.image,
+            self.mip_levels - 1,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         );
-        ctx.add_cleanup(Box::from(|device: &Device, allocator: &mut Allocator| {
-            staging.destroy(device, allocator);
-        }));
+    }
+
+    pub fn mip_extent(&self, mip_level: u32) -> vk::Extent3D {
+        vk::Extent3D {
+            width: (self.extent.width >> mip_level).max(1),
+            height: (self.extent.height >> mip_level).max(1),
+            depth: 1,
+        }
     }
 
     pub(crate) fn destroy(self, device: &Device, allocator: &mut Allocator) {
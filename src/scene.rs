@@ -2,5 +2,7 @@ pub mod billboard;
 pub mod light;
 pub mod mesh;
 pub mod model;
+pub mod particles;
+pub mod primitive;
 mod viewport;
 pub mod world;
@@ -1,12 +1,64 @@
-use crate::asset::material::MaterialId;
-use glam::{Vec2, Vec4};
+use crate::asset::material::{MaterialId, MaterialManager};
+use crate::gpu_layout;
+use crate::resource::buffer::AllocatedBuffer;
+use crate::resource::immediate_submit::SubmitContext;
+use crate::resource::{AllocUsage, Allocator};
+use crate::scene::light::{LightId, LightManager};
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+/// How a billboard is oriented. Has to match the constants in billboard.vert.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BillboardMode {
+    Spherical = 0,   // always faces the camera
+    Cylindrical = 1, // only rotates around the world's up axis
+    Fixed = 2,       // keeps the orientation of its model
+}
+
+impl BillboardMode {
+    pub const ALL: [BillboardMode; 3] = [BillboardMode::Spherical, BillboardMode::Cylindrical, BillboardMode::Fixed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BillboardMode::Spherical => "Spherical",
+            BillboardMode::Cylindrical => "Cylindrical",
+            BillboardMode::Fixed => "Fixed",
+        }
+    }
+}
+
+/// What unit a billboard's size is in. Has to match the constants in billboard.vert.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SizeMode {
+    World = 0,
+    Screen = 1, // pixels, so the billboard keeps its size on screen regardless of distance
+}
+
+impl SizeMode {
+    pub const ALL: [SizeMode; 2] = [SizeMode::World, SizeMode::Screen];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SizeMode::World => "World units",
+            SizeMode::Screen => "Pixels",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Billboard {
     pub center: Vec4,
-    pub size: Vec2,
+    pub size: Vec2, // half extents, in the unit given by size_mode
     pub uvs: [Vec2; 4],
     pub material: MaterialId,
+    pub tint: Vec4,
+    pub light: Option<LightId>, // whose color the tint is multiplied with, e.g. for icons marking lights
+    pub mode: BillboardMode,
+    pub size_mode: SizeMode,
+    pub rotation: Quat,     // world rotation of the model, used by BillboardMode::Fixed
+    pub ignore_depth: bool, // drawn on top of all geometry, e.g. for editor icons
+    pub blend: bool,        // alpha blended, so drawn back to front without writing depth
 }
 
 impl Billboard {
@@ -16,6 +68,205 @@ impl Billboard {
             size,
             uvs,
             material,
+            tint: Vec4::ONE,
+            light: None,
+            mode: BillboardMode::Spherical,
+            size_mode: SizeMode::World,
+            rotation: Quat::IDENTITY,
+            ignore_depth: false,
+            blend: false,
         }
     }
+
+    /// The vectors from the center to the right and top edges in world space, as billboard.vert computes them.
+    pub fn axes(&self, view: Mat4, proj: Mat4, viewport_height: f32) -> (Vec3, Vec3) {
+        let (camera_right, camera_up) = (view.row(0).xyz(), view.row(1).xyz());
+        let (right, up) = match self.mode {
+            BillboardMode::Spherical => (camera_right, camera_up),
+            // the camera's right axis is always horizontal, so only up has to be fixed to the world's Y axis
+            BillboardMode::Cylindrical => (camera_right, Vec3::Y * camera_up.y.signum()),
+            BillboardMode::Fixed => (self.rotation * Vec3::X, self.rotation * Vec3::NEG_Y),
+        };
+        let scale = match self.size_mode {
+            SizeMode::World => 1.0,
+            // world units per pixel at the billboard's distance from the camera
+            SizeMode::Screen => {
+                let depth = (view * self.center.xyz().extend(1.0)).z;
+                2.0 * depth.abs() / (viewport_height * proj.y_axis.y.abs())
+            }
+        };
+        (right * self.size.x * scale, up * self.size.y * scale)
+    }
+}
+
+// One billboard in the instance buffer, see the Billboard struct in globals.glsl.
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+pub(crate) struct RawBillboard {
+    center: [f32; 4],
+    rotation: [f32; 4],
+    tint: [f32; 4],
+    uvs: [[f32; 2]; 4],
+    size: [f32; 2],
+    material: vk::DeviceAddress,
+    mode: u32,
+    size_mode: u32,
+    light: i32, // index into the light buffer, or -1
+    padding: u32,
+}
+gpu_layout!(RawBillboard {
+    center,
+    rotation,
+    tint,
+    uvs,
+    size,
+    material,
+    mode,
+    size_mode,
+    light,
+    padding
+});
+
+/// All visible billboards packed into one buffer, so they are drawn with an instanced call per pipeline. Opaque
+/// billboards come first, then blended ones and then the ones ignoring depth, the latter two sorted back to front.
+pub struct BillboardBuffer {
+    buffer: AllocatedBuffer,
+    pub counts: [u32; 3],      // opaque, blended, ignoring depth
+    sorted: Vec<RawBillboard>, // CPU copy of the blended and ignoring depth ranges, re-sorted when the camera moves
+    resorted: bool,            // the sorted ranges changed this frame, so they have to be uploaded again
+}
+
+impl BillboardBuffer {
+    const PREALLOC_COUNT: u64 = 256; // how many billboards to preallocate space for
+    const MAX_UPDATE_SIZE: usize = 65536; // the most vkCmdUpdateBuffer takes at once
+
+    pub fn new(device: &Device, allocator: &mut Allocator) -> Self {
+        let buffer = AllocatedBuffer::new(
+            device,
+            allocator,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            AllocUsage::GpuOnly,
+            Self::PREALLOC_COUNT * size_of::<RawBillboard>() as u64,
+            Some("Billboard Buffer".to_string()),
+        );
+        Self {
+            buffer,
+            counts: [0; 3],
+            sorted: vec![],
+            resorted: false,
+        }
+    }
+
+    /// Whether the order depends on where the camera is.
+    pub fn is_sorted(&self) -> bool {
+        self.counts[1] + self.counts[2] > 0
+    }
+
+    /// Whether [`Self::sort`] changed the order this frame, see [`Self::record_sorted`].
+    pub fn resorted(&self) -> bool {
+        self.resorted
+    }
+
+    /// Rewrites the whole buffer, growing it if needed. Blended billboards are sorted by their distance to `eye`.
+    pub fn write(
+        &mut self,
+        billboards: &[&Billboard],
+        eye: Vec3,
+        ctx: &mut SubmitContext,
+        light_manager: &LightManager,
+        material_manager: &MaterialManager,
+    ) {
+        let pass = |billboard: &Billboard| match (billboard.ignore_depth, billboard.blend) {
+            (true, _) => 2,
+            (false, true) => 1,
+            (false, false) => 0,
+        };
+        let distance = |billboard: &Billboard| billboard.center.xyz().distance_squared(eye);
+        let mut sorted = billboards.to_vec();
+        sorted.sort_by(|a, b| {
+            pass(a).cmp(&pass(b)).then_with(|| match pass(a) {
+                0 => std::cmp::Ordering::Equal,
+                _ => distance(b).total_cmp(&distance(a)),
+            })
+        });
+
+        self.counts = [0; 3];
+        for billboard in &sorted {
+            self.counts[pass(billboard)] += 1;
+        }
+        self.sorted.clear();
+        self.resorted = false;
+        if sorted.is_empty() {
+            return;
+        }
+
+        let data = sorted
+            .iter()
+            .map(|billboard| RawBillboard {
+                center: billboard.center.to_array(),
+                rotation: billboard.rotation.to_array(),
+                tint: billboard.tint.to_array(),
+                uvs: billboard.uvs.map(|uv| uv.to_array()),
+                size: billboard.size.to_array(),
+                material: material_manager
+                    .get_material(billboard.material)
+                    .unwrap()
+                    .device_address(&ctx.device),
+                mode: billboard.mode as u32,
+                size_mode: billboard.size_mode as u32,
+                light: billboard
+                    .light
+                    .and_then(|light| light_manager.index_of(light))
+                    .map_or(-1, |index| index as i32),
+                padding: 0,
+            })
+            .collect::<Vec<_>>();
+        let size = (data.len() * size_of::<RawBillboard>()) as u64;
+        if size > self.buffer.size {
+            self.buffer.resize(ctx, size.next_power_of_two());
+        }
+        let cleanup = self
+            .buffer
+            .write(&data, 0, &ctx.device, &mut ctx.allocator.borrow_mut(), ctx.cmd_buffer);
+        ctx.add_cleanup(cleanup);
+        self.sorted = data[self.counts[0] as usize..].to_vec();
+    }
+
+    /// Sorts the blended and ignoring depth billboards back to front as seen from `eye` again, if the camera moved. Only
+    /// reorders the CPU copy, the frame uploads it with [`Self::record_sorted`].
+    pub fn sort(&mut self, eye: Vec3, camera_moved: bool) {
+        self.resorted = camera_moved && self.is_sorted();
+        if !self.resorted {
+            return;
+        }
+        let distance = |billboard: &RawBillboard| Vec4::from_array(billboard.center).xyz().distance_squared(eye);
+        let (blended, ignoring_depth) = self.sorted.split_at_mut(self.counts[1] as usize);
+        for range in [blended, ignoring_depth] {
+            range.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        }
+    }
+
+    /// Records uploading the ranges reordered by [`Self::sort`], leaving the opaque billboards alone. vkCmdUpdateBuffer
+    /// copies the data into the command buffer, so there's no staging buffer to keep alive for the frame.
+    pub fn record_sorted(&self, device: &Device, cmd: vk::CommandBuffer) {
+        if !self.resorted {
+            return;
+        }
+        let offset = self.counts[0] as usize * size_of::<RawBillboard>();
+        let data: &[u8] = bytemuck::cast_slice(&self.sorted);
+        for (i, chunk) in data.chunks(Self::MAX_UPDATE_SIZE).enumerate() {
+            let chunk_offset = (offset + i * Self::MAX_UPDATE_SIZE) as vk::DeviceSize;
+            unsafe {
+                device.cmd_update_buffer(cmd, self.buffer.buffer, chunk_offset, chunk);
+            }
+        }
+    }
+
+    pub fn device_address(&self, device: &Device) -> vk::DeviceAddress {
+        self.buffer.device_address(device)
+    }
+
+    pub fn destroy(self, device: &Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+    }
 }
@@ -1,20 +1,22 @@
 use crate::asset::texture::TextureKind;
 use crate::asset::texture::{Texture, TextureId, TextureManager};
-use crate::pipeline::shadow_mapping::{ShadowMappingPipeline, SHADOW_MAP_SIZE};
+use crate::gpu_layout;
+use crate::pipeline::shadow_mapping::{ShadowMappingPipeline, SHADOW_MAP_FORMAT, SHADOW_MAP_SIZE};
 use crate::resource::buffer::AllocatedBuffer;
 use crate::resource::immediate_submit::SubmitContext;
 use crate::resource::AllocUsage;
 use crate::scene::mesh::Mesh;
-use crate::DEPTH_FORMAT;
 use ash::vk;
 use bytemuck::{Pod, Zeroable};
 use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
 use log::{debug, error};
 use std::cell::RefCell;
+use std::f32::consts::{FRAC_PI_3, PI};
 use std::rc::Rc;
 use std::sync::RwLock;
 
 pub type LightId = usize;
+#[derive(Clone)]
 pub struct Light {
     pub id: LightId,
     pub meta: LightMeta,
@@ -22,12 +24,55 @@ pub struct Light {
 }
 
 // this is used for calculating e.g. viewproj on CPU and is not sent to the GPU
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum LightMeta {
     Spotlight { fov: f32, extent: (f32, f32) },
     Pointlight,
+    Directional,
+}
+
+impl LightMeta {
+    pub const SPOTLIGHT: LightMeta = LightMeta::Spotlight {
+        fov: FRAC_PI_3,
+        extent: (1000.0, 1000.0),
+    };
+    pub const ALL: [LightMeta; 3] = [Self::SPOTLIGHT, LightMeta::Pointlight, LightMeta::Directional];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightMeta::Spotlight { .. } => "Spotlight",
+            LightMeta::Pointlight => "Point light",
+            LightMeta::Directional => "Directional light",
+        }
+    }
+
+    /// Brings the GPU data in line with the light type. Spotlights get a default cone if they don't have a valid one, point
+    /// lights shine in all directions and directional lights lose their position (w = 0).
+    pub fn apply(&self, light: &mut RawLight) {
+        let (w, cone) = match self {
+            LightMeta::Spotlight { .. } if light.outer_angle > 0.0 && light.outer_angle < PI => (1.0, None),
+            LightMeta::Spotlight { .. } => (1.0, Some((45.0f32.to_radians(), 30.0f32.to_radians()))),
+            LightMeta::Pointlight => (1.0, Some((PI, PI))),
+            LightMeta::Directional => (0.0, Some((PI, PI))),
+        };
+        light.position[3] = w;
+        if let Some((outer_angle, inner_angle)) = cone {
+            light.outer_angle = outer_angle;
+            light.inner_angle = inner_angle;
+        }
+        light.update_viewproj();
+    }
 }
 
 impl Light {
+    /// A light of the given type at the origin, pointing down.
+    pub fn new(meta: LightMeta, color: [f32; 3], intensity: f32) -> Self {
+        let mut light = Self::new_spotlight(Vec3::ZERO, color, FRAC_PI_3, (1000.0, 1000.0), Vec3::NEG_Y, intensity);
+        light.meta = meta;
+        meta.apply(&mut light.data);
+        light
+    }
+
     pub fn new_spotlight(
         position: impl Into<[f32; 3]>,
         color: [f32; 3],
@@ -38,7 +83,7 @@ impl Light {
     ) -> Self {
         let position = position.into();
         let dir = dir.into();
-        let cutoff_angle = 180.0f32.to_radians().cos();
+        let outer_angle = 180.0f32.to_radians().cos();
         let view = Mat4::look_to_lh(Vec3::from(position), Vec3::from(dir), -Vec3::Y);
         let proj = Mat4::orthographic_lh(-10.0, 10.0, -10.0, 10.0, 0.1, 100.0);
         Self {
@@ -50,7 +95,7 @@ impl Light {
                 viewproj: (proj * view).to_cols_array_2d(),
                 direction: [dir[0], dir[1], dir[2], 0.0],
                 intensity,
-                cutoff_angle,
+                outer_angle,
                 inner_angle: 180.0f32.to_radians(),
                 radius: 100.0,
                 shadow_map: 0,
@@ -67,19 +112,45 @@ pub struct RawLight {
     pub viewproj: [[f32; 4]; 4],
     pub direction: [f32; 4],
     pub intensity: f32, // luminous intensity I in candela
-    pub cutoff_angle: f32,
+    pub outer_angle: f32,
     pub inner_angle: f32,
     pub radius: f32,
     pub shadow_map: TextureId,
 }
+gpu_layout!(RawLight {
+    position,
+    color,
+    viewproj,
+    direction,
+    intensity,
+    outer_angle,
+    inner_angle,
+    radius,
+    shadow_map
+});
 
 impl RawLight {
     pub fn update_viewproj(&mut self) {
         // let view = Mat4::look_to_rh(Vec4::from(self.position).xyz(), Vec4::from(self.direction).xyz(), -Vec3::Y);
-        let view = Mat4::look_at_lh(Vec4::from(self.position).xyz(), Vec4::ZERO.xyz(), -Vec3::Y);
+        let eye = if self.is_directional() {
+            // directional lights have no position, so they're placed far enough away along their direction
+            Vec4::from(self.direction).xyz().normalize_or_zero() * -50.0
+        } else {
+            Vec4::from(self.position).xyz()
+        };
+        let view = Mat4::look_at_lh(eye, Vec4::ZERO.xyz(), -Vec3::Y);
         let proj = Mat4::orthographic_lh(-10.0, 10.0, -10.0, 10.0, 0.1, 100.0);
         self.viewproj = (proj * view).to_cols_array_2d();
     }
+
+    pub fn is_directional(&self) -> bool {
+        self.position[3] == 0.0
+    }
+
+    /// Moves the light, keeping w which tells directional lights apart.
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position.extend(self.position[3]).to_array();
+    }
 }
 
 pub struct LightManager {
@@ -112,10 +183,21 @@ impl LightManager {
     // Adds a light to the manager and returns its id. Resizes buffer if needed.
     pub fn add_light(&mut self, mut light: Light, ctx: &mut SubmitContext, texture_manager: Rc<RefCell<TextureManager>>) -> LightId {
         light.id = self.max_id;
+        self.max_id += 1;
+        self.insert_light(light, ctx, texture_manager)
+    }
+
+    // Re-adds a light previously returned by take_light, keeping its id. The shadow map is recreated.
+    pub fn restore_light(&mut self, light: Light, ctx: &mut SubmitContext, texture_manager: Rc<RefCell<TextureManager>>) -> LightId {
+        self.insert_light(light, ctx, texture_manager)
+    }
+
+    fn insert_light(&mut self, mut light: Light, ctx: &mut SubmitContext, texture_manager: Rc<RefCell<TextureManager>>) -> LightId {
+        let id = light.id;
 
         let shadow_map = Texture::new(
             TextureManager::DEFAULT_SAMPLER_LINEAR,
-            DEPTH_FORMAT,
+            SHADOW_MAP_FORMAT,
             ctx,
             Some(format!("shadow_map_{}", light.id)),
             vk::Extent3D {
@@ -123,20 +205,27 @@ impl LightManager {
                 height: SHADOW_MAP_SIZE.1,
                 depth: 1,
             },
+            1,
             TextureKind::Depth,
         );
         let shadow_map_id = texture_manager.borrow_mut().add_texture(shadow_map, &ctx.device, true);
         light.data.shadow_map = shadow_map_id;
 
         self.lights.push(light);
-        self.max_id += 1;
         if self.lights.len() as u64 > self.buffer.size / size_of::<RawLight>() as u64 {
             self.resize(ctx);
         }
         self.rewrite_buffer(ctx);
 
         self.count_dirty = true;
-        self.max_id - 1
+        id
+    }
+
+    /// Switches the light type. The GPU data still has to be updated, e.g. with [`LightMeta::apply`].
+    pub fn set_meta(&mut self, id: LightId, meta: LightMeta) {
+        if let Some(light) = self.lights.iter_mut().find(|light| light.id == id) {
+            light.meta = meta;
+        }
     }
 
     pub fn get_light(&self, id: LightId) -> Option<&Light> {
@@ -174,14 +263,24 @@ impl LightManager {
         ctx.add_cleanup(cleanup);
     }
 
-    // Removes a light and rewrites the entire buffer. Does not shrink the buffer.
-    pub fn remove_light(&mut self, id: LightId, ctx: &mut SubmitContext) {
-        self.lights.retain(|light| light.id != id);
+    // Removes a light, releases its shadow map and rewrites the entire buffer. Does not shrink the buffer.
+    pub fn remove_light(&mut self, id: LightId, ctx: &mut SubmitContext, texture_manager: &mut TextureManager) {
+        self.take_light(id, ctx, texture_manager);
+    }
+
+    // Like remove_light, but hands the light back so it can be restored later.
+    pub fn take_light(&mut self, id: LightId, ctx: &mut SubmitContext, texture_manager: &mut TextureManager) -> Option<Light> {
+        let index = self.index_of(id)?;
+        let mut light = self.lights.remove(index);
+        texture_manager.release(light.data.shadow_map);
+        light.data.shadow_map = 0;
         self.count_dirty = true;
         self.rewrite_buffer(ctx);
+        Some(light)
     }
 
-    fn index_of(&self, id: LightId) -> Option<usize> {
+    /// Where the light is in the light buffer. Changes when lights before it are removed.
+    pub fn index_of(&self, id: LightId) -> Option<usize> {
         self.lights.iter().position(|light| light.id == id)
     }
 
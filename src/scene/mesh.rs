@@ -6,6 +6,7 @@ use crate::resource::{AllocUsage, Allocator};
 use ash::{vk, Device};
 use glam::{Mat4, Vec2, Vec3};
 use gpu_alloc_ash::AshMemoryDevice;
+use std::sync::Arc;
 
 pub struct GpuMesh {
     index_buffer: AllocatedBuffer,
@@ -13,9 +14,10 @@ pub struct GpuMesh {
     vertex_address: vk::DeviceAddress,
 }
 
-#[derive(Default)]
+/// Clones share the GPU buffers, which are destroyed along with the last mesh using them.
+#[derive(Default, Clone)]
 pub struct Mesh {
-    pub mem: Option<GpuMesh>,
+    pub mem: Option<Arc<GpuMesh>>,
     pub vertices: Vec<Vec3>,
     pub indices: Vec<u32>,
     pub normals: Vec<Vec3>,
@@ -110,11 +112,11 @@ impl Mesh {
         };
         // return empty FnOnce closure
 
-        self.mem = Some(GpuMesh {
+        self.mem = Some(Arc::new(GpuMesh {
             vertex_buffer,
             vertex_address: buffer_device_address,
             index_buffer,
-        });
+        }));
 
         ctx.add_cleanup(Box::from(move |device: &Device, allocator: &mut Allocator| {
             staging.destroy(device, allocator);
@@ -128,8 +130,13 @@ impl Mesh {
         self.mem.as_ref().unwrap().index_buffer.buffer
     }
 
+    /// Size of the mesh's vertex and index buffers, 0 if it hasn't been uploaded.
+    pub fn gpu_size(&self) -> vk::DeviceSize {
+        self.mem.as_ref().map_or(0, |mem| mem.vertex_buffer.size + mem.index_buffer.size)
+    }
+
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
-        if let Some(mem) = self.mem.take() {
+        if let Some(mem) = self.mem.take().and_then(Arc::into_inner) {
             mem.vertex_buffer.destroy(device, allocator);
             mem.index_buffer.destroy(device, allocator);
         }
@@ -2,6 +2,7 @@ use crate::resource::Allocator;
 use crate::scene::billboard::Billboard;
 use crate::scene::light::LightId;
 use crate::scene::mesh::Mesh;
+use crate::scene::particles::EmitterSettings;
 use ash::Device;
 use glam::Mat4;
 
@@ -12,10 +13,13 @@ pub struct Model {
     pub id: ModelId,
     pub meshes: Vec<Mesh>,
     pub children: Vec<ModelId>,
+    pub parent: Option<ModelId>, // kept in sync with the parent's children by World
     pub label: Option<String>,
     pub transform: Mat4,
     pub light: Option<LightId>,
     pub billboard: Option<Billboard>,
+    pub emitter: Option<EmitterSettings>,
+    pub hidden: bool, // hides the model and its descendants
 }
 
 impl Model {
@@ -30,10 +34,13 @@ impl Model {
             id: 0,
             meshes,
             children: Vec::new(),
+            parent: None,
             label,
             transform,
             light,
             billboard,
+            emitter: None,
+            hidden: false,
         }
     }
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
@@ -0,0 +1,277 @@
+use crate::asset::material::{MaterialId, MaterialManager};
+use crate::gpu_layout;
+use crate::resource::buffer::AllocatedBuffer;
+use crate::resource::{AllocUsage, Allocator};
+use crate::scene::model::ModelId;
+use crate::util::DeletionQueue;
+use crate::World;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use hashbrown::HashMap;
+use std::ops::{Add, Mul};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParticleBlend {
+    Alpha,
+    Additive,
+}
+
+impl ParticleBlend {
+    pub const ALL: [ParticleBlend; 2] = [ParticleBlend::Alpha, ParticleBlend::Additive];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParticleBlend::Alpha => "Alpha",
+            ParticleBlend::Additive => "Additive",
+        }
+    }
+}
+
+/// Makes a model emit particles from its origin. Color and size follow keyframed curves over each particle's lifetime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitterSettings {
+    pub max_particles: u32,
+    pub spawn_rate: f32, // particles per second
+    pub lifetime: f32,   // seconds
+    pub velocity: Vec3,  // initial velocity in world space
+    pub spread: f32,     // random offset added to each component of the initial velocity
+    pub gravity: Vec3,
+    pub color: Vec<(f32, Vec4)>, // keyframes by the fraction of the lifetime they're at, in order, see sample_curve
+    pub size: Vec<(f32, f32)>,   // likewise, half extents in world units, like billboards
+    pub material: MaterialId,
+    pub flipbook: (u32, u32), // columns and rows of frames in the material's texture, played over the lifetime
+    pub blend: ParticleBlend,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            max_particles: 1024,
+            spawn_rate: 100.0,
+            lifetime: 2.0,
+            velocity: Vec3::new(0.0, 2.0, 0.0),
+            spread: 0.5,
+            gravity: Vec3::new(0.0, -1.0, 0.0),
+            color: vec![(0.0, Vec4::ONE), (1.0, Vec4::new(1.0, 1.0, 1.0, 0.0))],
+            size: vec![(0.0, 0.05), (1.0, 0.01)],
+            material: MaterialManager::DEFAULT_MATERIAL,
+            flipbook: (1, 1),
+            blend: ParticleBlend::Alpha,
+        }
+    }
+}
+
+/// A value that curves can interpolate, e.g. a color or a size.
+pub trait CurveValue: Copy + Default + Add<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>> CurveValue for T {}
+
+/// Linearly interpolates between the keyframes around `t`. Before the first and after the last keyframe the curve
+/// holds their value.
+pub fn sample_curve<T: CurveValue>(keys: &[(f32, T)], t: f32) -> T {
+    let next = keys.partition_point(|(time, _)| *time <= t);
+    match (next.checked_sub(1).map(|i| keys[i]), keys.get(next).copied()) {
+        (Some((start, a)), Some((end, b))) => {
+            let s = (t - start) / (end - start);
+            a * (1.0 - s) + b * s
+        }
+        (Some((_, value)), None) | (None, Some((_, value))) => value,
+        (None, None) => T::default(),
+    }
+}
+
+// One particle in an emitter's buffer, see the Particle struct in globals.glsl. Dead once age reaches lifetime.
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+pub(crate) struct RawParticle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+}
+gpu_layout!(RawParticle {
+    position,
+    age,
+    velocity,
+    lifetime
+});
+
+// The curves at one point of the lifetime, see the CurveSample struct in globals.glsl.
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+pub(crate) struct RawCurveSample {
+    color: [f32; 4],
+    size: f32,
+}
+gpu_layout!(RawCurveSample { color, size });
+
+/// The GPU state of one model's emitter. New particles are spawned into a ring, replacing the oldest ones.
+pub struct Emitter {
+    pub settings: EmitterSettings,
+    pub origin: Vec3,
+    pub spawn_start: u32, // index of the first particle spawned this frame
+    pub spawn_count: u32,
+    pub fresh: bool,        // created this frame, so the buffer still has to be cleared
+    pub curves_dirty: bool, // the curves changed this frame, so the curve buffer has to be rewritten
+    buffer: AllocatedBuffer,
+    curve_buffer: AllocatedBuffer, // the curves sampled at evenly spaced points, which the vertex shader interpolates
+    spawn_accumulator: f32,        // fractional particles carried over to the next frame
+}
+
+impl Emitter {
+    pub const CURVE_SAMPLES: usize = 32; // CURVE_SAMPLES in globals.glsl
+
+    fn new(settings: EmitterSettings, device: &Device, allocator: &mut Allocator) -> Self {
+        let buffer = AllocatedBuffer::new(
+            device,
+            allocator,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            AllocUsage::GpuOnly,
+            settings.max_particles.max(1) as u64 * size_of::<RawParticle>() as u64,
+            Some("Particle Buffer".to_string()),
+        );
+        let curve_buffer = AllocatedBuffer::new(
+            device,
+            allocator,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            AllocUsage::GpuOnly,
+            (Self::CURVE_SAMPLES * size_of::<RawCurveSample>()) as u64,
+            Some("Particle Curve Buffer".to_string()),
+        );
+        Self {
+            settings,
+            origin: Vec3::ZERO,
+            spawn_start: 0,
+            spawn_count: 0,
+            fresh: true,
+            curves_dirty: true,
+            buffer,
+            curve_buffer,
+            spawn_accumulator: 0.0,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.settings.max_particles.max(1)
+    }
+
+    pub fn device_address(&self, device: &Device) -> vk::DeviceAddress {
+        self.buffer.device_address(device)
+    }
+
+    pub fn curve_address(&self, device: &Device) -> vk::DeviceAddress {
+        self.curve_buffer.device_address(device)
+    }
+
+    /// Records clearing the buffer. Zeroed particles have a lifetime of zero, so they start out dead.
+    pub fn clear(&self, device: &Device, cmd: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_fill_buffer(cmd, self.buffer.buffer, 0, vk::WHOLE_SIZE, 0);
+        }
+    }
+
+    /// Records writing the curves, sampled from the start to the end of the lifetime, to the curve buffer.
+    pub fn upload_curves(&self, device: &Device, cmd: vk::CommandBuffer) {
+        let samples = (0..Self::CURVE_SAMPLES)
+            .map(|i| {
+                let t = i as f32 / (Self::CURVE_SAMPLES - 1) as f32;
+                RawCurveSample {
+                    color: sample_curve(&self.settings.color, t).to_array(),
+                    size: sample_curve(&self.settings.size, t),
+                }
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            device.cmd_update_buffer(cmd, self.curve_buffer.buffer, 0, bytemuck::cast_slice(&samples));
+        }
+    }
+
+    fn buffers(self) -> [AllocatedBuffer; 2] {
+        [self.buffer, self.curve_buffer]
+    }
+
+    // Works out which particles are spawned this frame.
+    fn advance(&mut self, dt: f32) {
+        self.spawn_start = (self.spawn_start + self.spawn_count) % self.count();
+        self.spawn_accumulator += self.settings.spawn_rate.max(0.0) * dt;
+        let spawned = self.spawn_accumulator.floor();
+        self.spawn_accumulator -= spawned;
+        self.spawn_count = (spawned as u32).min(self.count());
+    }
+}
+
+/// Simulates the particles of all visible models with emitters on the GPU. Emitters are kept in sync with the models,
+/// so they follow edits, undo and deletion without further bookkeeping.
+#[derive(Default)]
+pub struct ParticleSystem {
+    emitters: HashMap<ModelId, Emitter>,
+    stale_buffers: Vec<AllocatedBuffer>, // of removed emitters, destroyed once the GPU is done with them
+    pub seed: u32,                       // changes every frame, so spawned particles get different random velocities
+    pub dt: f32,                         // seconds the particles are advanced by this frame
+}
+
+impl ParticleSystem {
+    /// Matches the emitters to the models and advances them by `dt` seconds. Recreates an emitter's buffer if its
+    /// particle count changed.
+    pub fn update(&mut self, world: &World, dt: f32, device: &Device, allocator: &mut Allocator) {
+        self.seed = self.seed.wrapping_add(1);
+        self.dt = dt;
+        for emitter in self.emitters.values_mut() {
+            emitter.fresh = false;
+            emitter.curves_dirty = false;
+        }
+        let models = world.get_emitters();
+        let stale = self
+            .emitters
+            .keys()
+            .filter(|id| !models.iter().any(|(model, _, _)| model == *id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in stale {
+            self.stale_buffers.extend(self.emitters.remove(&id).unwrap().buffers());
+        }
+
+        for (model, settings, origin) in models {
+            let resized = self
+                .emitters
+                .get(&model)
+                .is_some_and(|emitter| emitter.settings.max_particles != settings.max_particles);
+            if resized {
+                self.stale_buffers.extend(self.emitters.remove(&model).unwrap().buffers());
+            }
+            let emitter = self
+                .emitters
+                .entry(model)
+                .or_insert_with(|| Emitter::new(settings.clone(), device, allocator));
+            if emitter.settings.color != settings.color || emitter.settings.size != settings.size {
+                emitter.curves_dirty = true;
+            }
+            emitter.settings = settings.clone();
+            emitter.origin = origin;
+            emitter.advance(dt);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Emitter> {
+        self.emitters.values()
+    }
+
+    /// Hands the buffers of removed emitters to the given (per-frame) deletion queue.
+    pub fn collect_garbage(&mut self, deletion_queue: &mut DeletionQueue) {
+        for buffer in self.stale_buffers.drain(..) {
+            deletion_queue.push(move |device, allocator| buffer.destroy(device, allocator));
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for (_, emitter) in self.emitters.drain() {
+            for buffer in emitter.buffers() {
+                buffer.destroy(device, allocator);
+            }
+        }
+        for buffer in self.stale_buffers.drain(..) {
+            buffer.destroy(device, allocator);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::asset::material::MaterialManager;
+use crate::scene::mesh::Mesh;
+use glam::{Mat4, Vec2, Vec3};
+use hashbrown::HashMap;
+use std::f32::consts::{PI, TAU};
+
+/// Built-in meshes for blocking out scenes. All of them are centered on the origin with Y up and wound counter-clockwise
+/// like glTF meshes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Primitive {
+    Cube {
+        size: f32,
+    },
+    UvSphere {
+        radius: f32,
+        segments: u32, // around the Y axis
+        rings: u32,
+    },
+    Icosphere {
+        radius: f32,
+        subdivisions: u32,
+    },
+    Plane {
+        size: f32,
+        subdivisions: u32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Torus {
+        radius: f32, // from the center to the middle of the tube
+        tube_radius: f32,
+        segments: u32,
+        tube_segments: u32,
+    },
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 7] = [
+        Primitive::Cube { size: 1.0 },
+        Primitive::UvSphere {
+            radius: 0.5,
+            segments: 32,
+            rings: 16,
+        },
+        Primitive::Icosphere {
+            radius: 0.5,
+            subdivisions: 2,
+        },
+        Primitive::Plane {
+            size: 2.0,
+            subdivisions: 1,
+        },
+        Primitive::Cylinder {
+            radius: 0.5,
+            height: 1.0,
+            segments: 32,
+        },
+        Primitive::Cone {
+            radius: 0.5,
+            height: 1.0,
+            segments: 32,
+        },
+        Primitive::Torus {
+            radius: 0.5,
+            tube_radius: 0.2,
+            segments: 32,
+            tube_segments: 16,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Cube { .. } => "Cube",
+            Primitive::UvSphere { .. } => "UV Sphere",
+            Primitive::Icosphere { .. } => "Icosphere",
+            Primitive::Plane { .. } => "Plane",
+            Primitive::Cylinder { .. } => "Cylinder",
+            Primitive::Cone { .. } => "Cone",
+            Primitive::Torus { .. } => "Torus",
+        }
+    }
+
+    /// Generates the mesh with the default material. It still has to be uploaded.
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh {
+            material: MaterialManager::DEFAULT_MATERIAL,
+            transform: Mat4::IDENTITY,
+            ..Default::default()
+        };
+        match *self {
+            Primitive::Cube { size } => cube(&mut mesh, size),
+            Primitive::UvSphere { radius, segments, rings } => uv_sphere(&mut mesh, radius, segments.max(3), rings.max(2)),
+            Primitive::Icosphere { radius, subdivisions } => icosphere(&mut mesh, radius, subdivisions),
+            Primitive::Plane { size, subdivisions } => plane(&mut mesh, size, subdivisions.max(1)),
+            Primitive::Cylinder { radius, height, segments } => {
+                cylinder(&mut mesh, radius, radius, height, segments.max(3));
+            }
+            Primitive::Cone { radius, height, segments } => cylinder(&mut mesh, radius, 0.0, height, segments.max(3)),
+            Primitive::Torus {
+                radius,
+                tube_radius,
+                segments,
+                tube_segments,
+            } => torus(&mut mesh, radius, tube_radius, segments.max(3), tube_segments.max(3)),
+        }
+        mesh
+    }
+}
+
+fn push_vertex(mesh: &mut Mesh, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+    mesh.vertices.push(position);
+    mesh.normals.push(normal);
+    mesh.uvs.push(uv);
+    mesh.vertices.len() as u32 - 1
+}
+
+// Indices of a grid of `columns` x `rows` quads whose vertices were pushed row by row starting at `first`. The quads face
+// along the cross product of the directions in which columns and rows advance.
+fn push_grid(mesh: &mut Mesh, first: u32, columns: u32, rows: u32) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = first + row * (columns + 1) + column;
+            let b = a + columns + 1;
+            mesh.indices.extend([a, a + 1, b, a + 1, b + 1, b]);
+        }
+    }
+}
+
+fn cube(mesh: &mut Mesh, size: f32) {
+    // face normal and two edge directions whose cross product is the normal
+    let faces = [
+        (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+        (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+        (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+    ];
+    for (normal, u, v) in faces {
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let first = mesh.vertices.len() as u32;
+        for (x, y) in corners {
+            let position = (normal + u * x + v * y) * size * 0.5;
+            push_vertex(mesh, position, normal, Vec2::new((x + 1.0) * 0.5, (1.0 - y) * 0.5));
+        }
+        mesh.indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+}
+
+fn uv_sphere(mesh: &mut Mesh, radius: f32, segments: u32, rings: u32) {
+    let first = mesh.vertices.len() as u32;
+    // the first and last column coincide, so the UVs don't wrap around
+    for ring in 0..=rings {
+        let theta = PI * ring as f32 / rings as f32;
+        for segment in 0..=segments {
+            let phi = TAU * segment as f32 / segments as f32;
+            let normal = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+            let uv = Vec2::new(segment as f32 / segments as f32, ring as f32 / rings as f32);
+            push_vertex(mesh, normal * radius, normal, uv);
+        }
+    }
+    push_grid(mesh, first, segments, rings);
+}
+
+fn icosphere(mesh: &mut Mesh, radius: f32, subdivisions: u32) {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .map(|(x, y, z)| Vec3::new(x, y, z).normalize())
+    .to_vec();
+    #[rustfmt::skip]
+    let mut triangles = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    // every subdivision splits each triangle into four, sharing the new vertices between neighbours
+    for _ in 0..subdivisions.min(6) {
+        let mut midpoints = HashMap::<(u32, u32), u32>::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(((positions[a as usize] + positions[b as usize]) * 0.5).normalize());
+                positions.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // spherical UVs, which stretch across the seam since vertices aren't split there
+    let first = mesh.vertices.len() as u32;
+    for normal in positions {
+        let uv = Vec2::new(0.5 + normal.z.atan2(normal.x) / TAU, normal.y.acos() / PI);
+        push_vertex(mesh, normal * radius, normal, uv);
+    }
+    mesh.indices.extend(triangles.into_iter().flatten().map(|index| first + index));
+}
+
+fn plane(mesh: &mut Mesh, size: f32, subdivisions: u32) {
+    let first = mesh.vertices.len() as u32;
+    // rows advance along X and columns along Z, so the quads face up
+    for row in 0..=subdivisions {
+        for column in 0..=subdivisions {
+            let uv = Vec2::new(row as f32, column as f32) / subdivisions as f32;
+            let position = Vec3::new(uv.x - 0.5, 0.0, uv.y - 0.5) * size;
+            push_vertex(mesh, position, Vec3::Y, uv);
+        }
+    }
+    push_grid(mesh, first, subdivisions, subdivisions);
+}
+
+// A cylinder, or a cone if the top radius is zero, with both ends capped.
+fn cylinder(mesh: &mut Mesh, bottom_radius: f32, top_radius: f32, height: f32, segments: u32) {
+    let (top, bottom) = (height * 0.5, -height * 0.5);
+    // the side normals tilt upwards as the side narrows
+    let slope = (bottom_radius - top_radius) / height;
+    let first = mesh.vertices.len() as u32;
+    for (y, radius, v) in [(top, top_radius, 0.0), (bottom, bottom_radius, 1.0)] {
+        for segment in 0..=segments {
+            let phi = TAU * segment as f32 / segments as f32;
+            let direction = Vec3::new(phi.cos(), 0.0, phi.sin());
+            let normal = (direction + Vec3::Y * slope).normalize();
+            let uv = Vec2::new(segment as f32 / segments as f32, v);
+            push_vertex(mesh, direction * radius + Vec3::Y * y, normal, uv);
+        }
+    }
+    push_grid(mesh, first, segments, 1);
+
+    for (y, radius, normal) in [(top, top_radius, Vec3::Y), (bottom, bottom_radius, Vec3::NEG_Y)] {
+        if radius <= 0.0 {
+            continue;
+        }
+        let center = push_vertex(mesh, Vec3::Y * y, normal, Vec2::splat(0.5));
+        for segment in 0..segments {
+            let [a, b] = [segment, segment + 1].map(|segment| {
+                let phi = TAU * segment as f32 / segments as f32;
+                let direction = Vec3::new(phi.cos(), 0.0, phi.sin());
+                let uv = Vec2::new(0.5 + direction.x * 0.5, 0.5 + direction.z * 0.5);
+                push_vertex(mesh, direction * radius + Vec3::Y * y, normal, uv)
+            });
+            if normal == Vec3::Y {
+                mesh.indices.extend([center, b, a]);
+            } else {
+                mesh.indices.extend([center, a, b]);
+            }
+        }
+    }
+}
+
+fn torus(mesh: &mut Mesh, radius: f32, tube_radius: f32, segments: u32, tube_segments: u32) {
+    let first = mesh.vertices.len() as u32;
+    for tube_segment in 0..=tube_segments {
+        // starts at the top of the tube and goes around the outside first
+        let theta = PI / 2.0 - TAU * tube_segment as f32 / tube_segments as f32;
+        for segment in 0..=segments {
+            let phi = TAU * segment as f32 / segments as f32;
+            let direction = Vec3::new(phi.cos(), 0.0, phi.sin());
+            let normal = direction * theta.cos() + Vec3::Y * theta.sin();
+            let uv = Vec2::new(segment as f32 / segments as f32, tube_segment as f32 / tube_segments as f32);
+            push_vertex(mesh, direction * radius + normal * tube_radius, normal, uv);
+        }
+    }
+    push_grid(mesh, first, segments, tube_segments);
+}
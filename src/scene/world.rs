@@ -1,19 +1,29 @@
+use crate::asset::material::MaterialManager;
+use crate::asset::texture::TextureManager;
+use crate::camera::{Camera, Ray};
 use crate::resource::immediate_submit::SubmitContext;
 use crate::resource::Allocator;
-use crate::scene::billboard::Billboard;
-use crate::scene::light::LightManager;
+use crate::scene::billboard::{Billboard, BillboardBuffer};
+use crate::scene::light::{LightId, LightManager};
 use crate::scene::mesh::Mesh;
 use crate::scene::model::{Model, ModelId};
+use crate::scene::particles::EmitterSettings;
+use crate::util::DeletionQueue;
 use ash::Device;
 use egui::ahash::HashMap;
 use glam::Vec2;
-use glam::{Mat4, Vec4};
-use hashbrown::HashSet;
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[derive(Default)]
 pub struct World {
     pub models: HashMap<ModelId, Model>,
     max_id: ModelId,
+    stale_meshes: Vec<Mesh>,       // meshes of removed models, destroyed once the GPU is done with them
+    light_icon: Option<Billboard>, // template for the billboards marking lights
+    billboard_buffer: Option<BillboardBuffer>,
+    billboards_dirty: bool, // whether the billboard buffer has to be rewritten
 }
 
 impl World {
@@ -21,56 +31,196 @@ impl World {
         self.max_id + 1
     }
 
-    pub fn add_model(&mut self, mut model: Model) -> ModelId {
-        if model.light.is_some() {
-            let child = Model::new(
-                vec![],
-                Mat4::IDENTITY,
-                None,
-                Some(Billboard {
-                    center: Vec4::ZERO,
-                    size: Vec2::from([0.1, 0.1]),
-                    uvs: [Vec2::ZERO; 4],
-                    material: 0,
-                }),
-                None,
-            );
-            let child = self.add_model(child);
-            model.children.push(child);
+    /// Sets the billboard that [`Self::add_model`] gives models with lights from now on.
+    pub fn set_light_icon(&mut self, billboard: Billboard) {
+        self.light_icon = Some(billboard);
+    }
+
+    pub fn add_model(&mut self, model: Model) -> ModelId {
+        let billboard = model.light.map(|light| {
+            let mut billboard = self
+                .light_icon
+                .clone()
+                .unwrap_or_else(|| Billboard::new(Vec4::ZERO, Vec2::from([0.1, 0.1]), [Vec2::ZERO; 4], 0));
+            billboard.light = Some(light);
+            Model::new(vec![], Mat4::IDENTITY, None, Some(billboard), None)
+        });
+
+        let id = self.insert_model(model);
+        if let Some(billboard) = billboard {
+            let child = self.add_model(billboard);
+            self.set_parent(child, Some(id), None);
         }
+        id
+    }
 
+    // Adds the model as is, without the billboard child add_model gives lights.
+    fn insert_model(&mut self, mut model: Model) -> ModelId {
         let id = self.next_free_id();
         model.id = id;
         self.models.insert(id, model);
         self.max_id += 1;
+        self.billboards_dirty = true;
         id
     }
 
+    /// Deep-copies the model and its descendants, placing the copy right after the original. The copies' meshes share
+    /// the GPU buffers of the originals, while attached lights are duplicated with their own shadow maps.
+    pub fn duplicate_model(
+        &mut self,
+        id: ModelId,
+        light_manager: &mut LightManager,
+        ctx: &mut SubmitContext,
+        texture_manager: Rc<RefCell<TextureManager>>,
+    ) -> Option<ModelId> {
+        let placement = self.placement(id);
+        let copy = self.copy_subtree(id, light_manager, ctx, texture_manager, &mut HashMap::default())?;
+        if let Some((parent, index)) = placement {
+            self.set_parent(copy, Some(parent), Some(index + 1));
+        }
+        Some(copy)
+    }
+
+    fn copy_subtree(
+        &mut self,
+        id: ModelId,
+        light_manager: &mut LightManager,
+        ctx: &mut SubmitContext,
+        texture_manager: Rc<RefCell<TextureManager>>,
+        lights: &mut HashMap<LightId, LightId>, // originals to copies, so billboards marking lights follow the copies
+    ) -> Option<ModelId> {
+        let model = self.models.get(&id)?;
+        let light = model
+            .light
+            .and_then(|light| light_manager.get_light(light).cloned())
+            .map(|light| (light.id, light_manager.add_light(light, ctx, texture_manager.clone())));
+        lights.extend(light);
+        let mut billboard = model.billboard.clone();
+        if let Some(billboard) = &mut billboard {
+            billboard.light = billboard.light.map(|light| lights.get(&light).copied().unwrap_or(light));
+        }
+        let copy = Model {
+            meshes: model.meshes.clone(),
+            label: model.label.clone(),
+            transform: model.transform,
+            light: light.map(|(_, copy)| copy),
+            billboard,
+            emitter: model.emitter.clone(),
+            hidden: model.hidden,
+            ..Default::default()
+        };
+        let children = model.children.clone();
+        let copy = self.insert_model(copy);
+        for child in children {
+            if let Some(child) = self.copy_subtree(child, light_manager, ctx, texture_manager.clone(), lights) {
+                self.set_parent(child, Some(copy), None);
+            }
+        }
+        Some(copy)
+    }
+
+    // Models that are drawn, i.e. neither they nor any of their ancestors are hidden.
+    fn visible_models(&self) -> impl Iterator<Item = &Model> {
+        self.models.values().filter(|model| self.is_visible(model.id))
+    }
+
     pub fn get_meshes(&self) -> Vec<&Mesh> {
-        self.models.iter().flat_map(|(_, model)| model.meshes.iter()).collect()
+        self.visible_models().flat_map(|model| model.meshes.iter()).collect()
+    }
+
+    /// Meshes of the given models and all of their visible descendants.
+    pub fn get_subtree_meshes(&self, ids: impl Iterator<Item = ModelId>) -> Vec<&Mesh> {
+        let mut meshes = vec![];
+        let mut stack = ids.filter(|id| self.is_visible(*id)).collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if let Some(model) = self.models.get(&id).filter(|model| !model.hidden) {
+                meshes.extend(model.meshes.iter());
+                stack.extend(model.children.iter());
+            }
+        }
+        meshes
+    }
+
+    /// Visible models with particle emitters, along with where they are in the world.
+    pub fn get_emitters(&self) -> Vec<(ModelId, &EmitterSettings, Vec3)> {
+        self.visible_models()
+            .filter_map(|model| Some((model.id, model.emitter.as_ref()?, self.world_transform(model.id).w_axis.xyz())))
+            .collect()
     }
 
     pub fn get_billboards(&self) -> Vec<&Billboard> {
-        self.models.iter().filter_map(|(_, model)| model.billboard.as_ref()).collect()
+        self.visible_models().filter_map(|model| model.billboard.as_ref()).collect()
+    }
+
+    /// Has the billboard buffer rewritten before the next frame. Needed after changing billboards or their visibility
+    /// directly through [`Self::models`].
+    pub fn billboards_changed(&mut self) {
+        self.billboards_dirty = true;
+    }
+
+    pub fn update_billboard(&mut self, model: ModelId, billboard: Billboard) {
+        if let Some(model) = self.models.get_mut(&model) {
+            model.billboard = Some(billboard);
+            self.billboards_dirty = true;
+        }
+    }
+
+    /// Whether the billboard buffer has to be rewritten, because billboards changed.
+    pub fn billboards_outdated(&self) -> bool {
+        self.billboards_dirty
     }
 
+    /// Sorts the billboards that are drawn back to front as seen from `eye` again if the camera moved, see
+    /// [`BillboardBuffer::sort`].
+    pub fn sort_billboards(&mut self, eye: Vec3, camera_moved: bool) {
+        if let Some(buffer) = &mut self.billboard_buffer {
+            buffer.sort(eye, camera_moved);
+        }
+    }
+
+    /// Packs the visible billboards into the billboard buffer, sorting them as seen from `eye`.
+    pub fn write_billboards(
+        &mut self,
+        eye: Vec3,
+        ctx: &mut SubmitContext,
+        light_manager: &LightManager,
+        material_manager: &MaterialManager,
+    ) {
+        self.billboards_dirty = false;
+        let mut buffer = self
+            .billboard_buffer
+            .take()
+            .unwrap_or_else(|| BillboardBuffer::new(&ctx.device, &mut ctx.allocator.borrow_mut()));
+        buffer.write(&self.get_billboards(), eye, ctx, light_manager, material_manager);
+        self.billboard_buffer = Some(buffer);
+    }
+
+    pub fn billboard_buffer(&self) -> Option<&BillboardBuffer> {
+        self.billboard_buffer.as_ref()
+    }
+
+    /// Models without a parent, ordered by id so the order stays stable between frames.
     pub fn get_toplevel_model_ids(&self) -> Vec<ModelId> {
-        let all_children = self
+        let mut ids = self
             .models
-            .iter()
-            .flat_map(|(_, model)| model.children.iter())
-            .collect::<HashSet<_>>();
-        self.models
-            .keys()
-            .copied()
-            .filter(|model| !all_children.contains(&model))
-            .collect::<Vec<_>>()
+            .values()
+            .filter(|model| model.parent.is_none())
+            .map(|model| model.id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
     }
 
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
         for (_, mut model) in self.models.drain() {
             model.destroy(device, allocator);
         }
+        for mut mesh in self.stale_meshes.drain(..) {
+            mesh.destroy(device, allocator);
+        }
+        if let Some(buffer) = self.billboard_buffer.take() {
+            buffer.destroy(device, allocator);
+        }
     }
 
     /*
@@ -80,6 +230,129 @@ impl World {
         for (_, mut model) in self.models.drain() {
             model.destroy(device, allocator);
         }
+        self.billboards_dirty = true;
+    }
+
+    /// Takes the model and all of its descendants out of the world without destroying them. The subtree's root comes first.
+    pub fn detach_subtree(&mut self, id: ModelId) -> Vec<Model> {
+        if let Some(parent) = self.parent_of(id) {
+            self.models.get_mut(&parent).unwrap().children.retain(|child| *child != id);
+        }
+        let mut detached = vec![];
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(model) = self.models.remove(&id) {
+                stack.extend(model.children.iter().rev());
+                detached.push(model);
+            }
+        }
+        self.billboards_dirty = true;
+        detached
+    }
+
+    /// Puts a subtree returned by [`Self::detach_subtree`] back, optionally as the `index`th child of `parent`.
+    pub fn attach_subtree(&mut self, models: Vec<Model>, parent: Option<(ModelId, usize)>) {
+        let Some(root) = models.first().map(|model| model.id) else {
+            return;
+        };
+        for model in models {
+            self.models.insert(model.id, model);
+        }
+        self.billboards_dirty = true;
+        self.models.get_mut(&root).unwrap().parent = None;
+        if let Some((parent, index)) = parent {
+            self.set_parent(root, Some(parent), Some(index));
+        }
+    }
+
+    /// Destroys detached models. Their meshes go through [`Self::collect_garbage`] like those of removed models.
+    pub fn discard_models(&mut self, models: Vec<Model>) {
+        for mut model in models {
+            self.stale_meshes.append(&mut model.meshes);
+        }
+    }
+
+    pub fn parent_of(&self, id: ModelId) -> Option<ModelId> {
+        self.models.get(&id).and_then(|model| model.parent)
+    }
+
+    /// The model's parent and its position among the parent's children.
+    pub fn placement(&self, id: ModelId) -> Option<(ModelId, usize)> {
+        let parent = self.parent_of(id)?;
+        let index = self.models[&parent].children.iter().position(|child| *child == id).unwrap();
+        Some((parent, index))
+    }
+
+    /// Whether `ancestor` is `id` itself or one of its ancestors.
+    pub fn is_ancestor(&self, ancestor: ModelId, id: ModelId) -> bool {
+        let mut current = Some(id);
+        while let Some(model) = current {
+            if model == ancestor {
+                return true;
+            }
+            current = self.parent_of(model);
+        }
+        false
+    }
+
+    /// Whether neither the model nor any of its ancestors are hidden.
+    pub fn is_visible(&self, id: ModelId) -> bool {
+        let mut current = self.models.get(&id);
+        while let Some(model) = current {
+            if model.hidden {
+                return false;
+            }
+            current = model.parent.and_then(|parent| self.models.get(&parent));
+        }
+        true
+    }
+
+    /// Moves the model under `parent`, at `index` among its children or last, or to the top level. The local transform is
+    /// kept, so the model moves along with its new parent. Returns false if the model would become its own ancestor.
+    pub fn set_parent(&mut self, id: ModelId, parent: Option<ModelId>, index: Option<usize>) -> bool {
+        if !self.models.contains_key(&id) || parent.is_some_and(|parent| !self.models.contains_key(&parent) || self.is_ancestor(id, parent))
+        {
+            return false;
+        }
+        if let Some(old) = self.parent_of(id) {
+            self.models.get_mut(&old).unwrap().children.retain(|child| *child != id);
+        }
+        if let Some(parent) = parent {
+            let children = &mut self.models.get_mut(&parent).unwrap().children;
+            children.insert(index.unwrap_or(children.len()).min(children.len()), id);
+        }
+        self.models.get_mut(&id).unwrap().parent = parent;
+        // the model now inherits visibility from its new ancestors
+        self.billboards_dirty = true;
+        true
+    }
+
+    /// Like [`Self::set_parent`], but adjusts the local transform so the model stays where it is in the world. Transforms
+    /// still have to be updated afterwards.
+    pub fn reparent(&mut self, id: ModelId, parent: Option<ModelId>, index: Option<usize>) -> bool {
+        let world_transform = self.world_transform(id);
+        if !self.set_parent(id, parent, index) {
+            return false;
+        }
+        let parent_transform = parent.map(|parent| self.world_transform(parent)).unwrap_or(Mat4::IDENTITY);
+        self.models.get_mut(&id).unwrap().transform = parent_transform.inverse() * world_transform;
+        true
+    }
+
+    /// The model's transform including all of its ancestors.
+    pub fn world_transform(&self, id: ModelId) -> Mat4 {
+        let transform = self.models.get(&id).map(|model| model.transform).unwrap_or(Mat4::IDENTITY);
+        match self.parent_of(id) {
+            Some(parent) => self.world_transform(parent) * transform,
+            None => transform,
+        }
+    }
+
+    /// Hands the meshes of removed models to the given (per-frame) deletion queue.
+    pub fn collect_garbage(&mut self, deletion_queue: &mut DeletionQueue) {
+        for mut mesh in self.stale_meshes.drain(..) {
+            deletion_queue.push(move |device, allocator| mesh.destroy(device, allocator));
+        }
     }
 
     pub fn update_transforms(&mut self, model: ModelId, parent: Mat4, light_manager: &mut LightManager, ctx: &mut SubmitContext) {
@@ -92,7 +365,7 @@ impl World {
             light_manager.update_light(
                 light,
                 |light| {
-                    light.position = transform.w_axis.to_array();
+                    light.set_position(transform.w_axis.xyz());
                     light.update_viewproj();
                 },
                 ctx,
@@ -101,6 +374,8 @@ impl World {
 
         if let Some(billboard) = &mut model.billboard {
             billboard.center = transform.w_axis;
+            billboard.rotation = transform.to_scale_rotation_translation().1;
+            self.billboards_dirty = true;
         }
 
         for child in model.children.clone().as_slice() {
@@ -108,9 +383,44 @@ impl World {
         }
     }
 
-    pub fn update_billboard(&mut self, billboard: ModelId, center: Vec4, uvs: [Vec2; 4]) {
-        let model = self.models.get_mut(&billboard).unwrap();
-        model.billboard.as_mut().unwrap().center = center;
-        model.billboard.as_mut().unwrap().uvs = uvs;
+    /// Finds the model hit first by the ray, testing mesh triangles and billboard quads. Billboards drawn on top of
+    /// geometry are hit before anything else.
+    pub fn pick(&self, ray: &Ray, camera: &Camera) -> Option<ModelId> {
+        let (view, proj) = (camera.view(), camera.proj());
+        let mut closest: Option<(ModelId, f32)> = None;
+        let mut on_top: Option<(ModelId, f32)> = None;
+        for model in self.visible_models() {
+            let id = &model.id;
+            for mesh in model.meshes.iter() {
+                // the ray parameter stays the same in model space, so hits of different meshes are still comparable
+                let local = ray.transformed(mesh.transform.inverse());
+                let hit = mesh
+                    .indices
+                    .chunks_exact(3)
+                    .filter_map(|tri| {
+                        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| mesh.vertices[i as usize]);
+                        local.intersect_triangle(a, b, c)
+                    })
+                    .min_by(f32::total_cmp);
+                if let Some(t) = hit.filter(|t| closest.map_or(true, |(_, closest)| *t < closest)) {
+                    closest = Some((*id, t));
+                }
+            }
+            if let Some(billboard) = &model.billboard {
+                let center = billboard.center.xyz();
+                let (right, up) = billboard.axes(view, proj, camera.extent.1);
+                let Some(t) = ray.intersect_plane(center, right.cross(up)) else {
+                    continue;
+                };
+                // the offset projected onto each axis, in multiples of the half extents
+                let offset = ray.at(t) - center;
+                let inside = offset.dot(right).abs() <= right.length_squared() && offset.dot(up).abs() <= up.length_squared();
+                let closest = if billboard.ignore_depth { &mut on_top } else { &mut closest };
+                if inside && closest.map_or(true, |(_, closest)| t < closest) {
+                    *closest = Some((*id, t));
+                }
+            }
+        }
+        on_top.or(closest).map(|(id, _)| id)
     }
 }
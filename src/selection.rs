@@ -0,0 +1,59 @@
+use crate::scene::model::ModelId;
+use crate::World;
+
+/// The models selected in the editor, shared by viewport picking, the gizmo and the UI panels.
+#[derive(Default)]
+pub struct Selection {
+    models: Vec<ModelId>, // in the order they were selected
+}
+
+impl Selection {
+    /// The most recently selected model, which the gizmo and inspector act on.
+    pub fn primary(&self) -> Option<ModelId> {
+        self.models.last().copied()
+    }
+
+    pub fn contains(&self, model: ModelId) -> bool {
+        self.models.contains(&model)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ModelId> + '_ {
+        self.models.iter().copied()
+    }
+
+    /// Selected models whose ancestors aren't selected as well. Operations on whole subtrees use these, so that nothing is
+    /// moved or copied twice.
+    pub fn roots(&self, world: &World) -> Vec<ModelId> {
+        self.iter()
+            .filter(|model| {
+                !world
+                    .parent_of(*model)
+                    .is_some_and(|parent| self.iter().any(|selected| world.is_ancestor(selected, parent)))
+            })
+            .collect()
+    }
+
+    /// Replaces the selection with a single model.
+    pub fn set(&mut self, model: ModelId) {
+        self.models.clear();
+        self.models.push(model);
+    }
+
+    /// Adds the model to the selection, or removes it if it's already selected.
+    pub fn toggle(&mut self, model: ModelId) {
+        if let Some(index) = self.models.iter().position(|selected| *selected == model) {
+            self.models.remove(index);
+        } else {
+            self.models.push(model);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.models.clear();
+    }
+
+    /// Drops models that no longer exist, e.g. after they were deleted.
+    pub fn retain(&mut self, f: impl FnMut(&ModelId) -> bool) {
+        self.models.retain(f);
+    }
+}
@@ -4,15 +4,22 @@
 //push constants block
 layout( push_constant, scalar ) uniform constants
 {
-    mat4 transform;  // model matrix
-    vec2 size;       // size of the billboard
-    vec2[4] uv;
-    SceneDataBuffer sceneDataBuffer;
-    PbrMaterial pbrMaterial;
+    SceneDataBuffer sceneData;
+    BillboardBuffer billboardBuffer;
     LightBuffer lightBuffer;
+    float viewportHeight;
 } PushConstants;
 
 layout (location = 0) out vec2 texCoords;
+layout (location = 1) flat out uint billboardIndex;
+layout (location = 2) flat out vec4 tint;
+
+// see BillboardMode and SizeMode
+const uint MODE_SPHERICAL = 0u;
+const uint MODE_CYLINDRICAL = 1u;
+const uint MODE_FIXED = 2u;
+const uint SIZE_WORLD = 0u;
+const uint SIZE_SCREEN = 1u;
 
 const vec4[] vertices = vec4[](
     vec4(-1.0, -1.0, 0.0, 1.0),
@@ -27,22 +34,52 @@ const int[] uv_map = int[](
 0, 2, 1, 2, 1, 3
 );
 
+vec3 rotate(vec4 q, vec3 v)
+{
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
 void main()
 {
-    mat4 view = PushConstants.sceneDataBuffer.view;
-    mat4 viewproj = PushConstants.sceneDataBuffer.viewproj;
-    vec4 camera_right_world = vec4(view[0][0], view[1][0], view[2][0], 0.0);
-    vec4 camera_up_world = vec4(view[0][1], view[1][1], view[2][1], 0.0);
-    vec4 position = vec4(PushConstants.transform[3].xyz, 1.0);
-    // from position and size.x, size.y, calculate the center of the billboard
-    vec4 world_pos = position
-        + camera_right_world * vertices[gl_VertexIndex].x * PushConstants.size.x
-        + camera_up_world * vertices[gl_VertexIndex].y * PushConstants.size.y;
-
-    gl_Position = viewproj * vec4(world_pos.xyz, 1.0);
+    mat4 view = PushConstants.sceneData.view;
+    mat4 proj = PushConstants.sceneData.proj;
+    mat4 viewproj = PushConstants.sceneData.viewproj;
+    vec3 camera_right_world = vec3(view[0][0], view[1][0], view[2][0]);
+    vec3 camera_up_world = vec3(view[0][1], view[1][1], view[2][1]);
+    Billboard billboard = PushConstants.billboardBuffer.billboards[gl_InstanceIndex];
+    vec3 position = billboard.center.xyz;
+
+    vec3 right = camera_right_world;
+    vec3 up = camera_up_world;
+    if (billboard.mode == MODE_CYLINDRICAL) {
+        // the camera's right axis is always horizontal, so only up has to be fixed to the world's Y axis
+        up = vec3(0.0, camera_up_world.y < 0.0 ? -1.0 : 1.0, 0.0);
+    } else if (billboard.mode == MODE_FIXED) {
+        right = rotate(billboard.rotation, vec3(1.0, 0.0, 0.0));
+        up = rotate(billboard.rotation, vec3(0.0, -1.0, 0.0));
+    }
+
+    vec2 size = billboard.size;
+    if (billboard.sizeMode == SIZE_SCREEN) {
+        // world units per pixel at the billboard's distance from the camera
+        float depth = abs((view * vec4(position, 1.0)).z);
+        size *= 2.0 * depth / (PushConstants.viewportHeight * abs(proj[1][1]));
+    }
+
+    vec3 world_pos = position
+        + right * vertices[gl_VertexIndex].x * size.x
+        + up * vertices[gl_VertexIndex].y * size.y;
+
+    gl_Position = viewproj * vec4(world_pos, 1.0);
 
     int uv_index = uv_map[gl_VertexIndex];
-    vec2 uv_coords = PushConstants.uv[uv_index].xy;
+    vec2 uv_coords = billboard.uvs[uv_index].xy;
     texCoords.x = uv_coords.x;
     texCoords.y = uv_coords.y;
+
+    billboardIndex = uint(gl_InstanceIndex);
+    tint = billboard.tint;
+    if (billboard.light >= 0) {
+        tint.rgb *= PushConstants.lightBuffer.lights[billboard.light].color.rgb;
+    }
 }
\ No newline at end of file
@@ -1,14 +1,14 @@
 #version 450
 #extension GL_EXT_buffer_reference : require
 
-struct Vertex {
-    vec2 position;
+struct EguiVertex {
+    vec2 pos;
     vec2 uv;
     uint color;
 };
 
 layout(buffer_reference, std430) readonly buffer VertexBuffer{
-    Vertex vertices[];
+    EguiVertex vertices[];
 };
 
 //push constants block
@@ -34,10 +34,10 @@ vec4 decodeRGBA(uint encodedColor) {
 }
 
 void main() {
-    Vertex inVertex = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
+    EguiVertex inVertex = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
     gl_Position =
-    vec4(2.0 * inVertex.position.x / PushConstants.screen_size.x - 1.0,
-    2.0 * inVertex.position.y / PushConstants.screen_size.y - 1.0, 0.0, 1.0);
+    vec4(2.0 * inVertex.pos.x / PushConstants.screen_size.x - 1.0,
+    2.0 * inVertex.pos.y / PushConstants.screen_size.y - 1.0, 0.0, 1.0);
     vec4 color = decodeRGBA(inVertex.color);
     outColor = color;
     outUV = vec2(inVertex.uv.x, inVertex.uv.y);
@@ -8,7 +8,7 @@ layout(buffer_reference, scalar) readonly buffer SceneDataBuffer {
     mat4 unproj;
     vec4 ambient_color;
     vec4 camera_position;
-    uint num_lights;
+    uint light_count;
 };
 
 layout(buffer_reference, scalar) readonly buffer PbrMaterial {
@@ -17,6 +17,8 @@ layout(buffer_reference, scalar) readonly buffer PbrMaterial {
     vec4 albedo;
     float metallic;
     float roughness;
+    uint normal_tex;    // only sampled with HAS_NORMAL_MAP
+    float alpha_cutoff; // only tested with ALPHA_MASK
 };
 
 
@@ -35,7 +37,7 @@ layout(buffer_reference, scalar) readonly buffer VertexBuffer {
 struct Light {
     vec4 position;
     vec4 color;
-    mat4 lightspace; // only for spotlights; identity matrix for point lights
+    mat4 viewproj; // only for spotlights; identity matrix for point lights
     vec4 direction;
     float intensity;
     float outer_angle;
@@ -46,4 +48,54 @@ struct Light {
 
 layout(buffer_reference, scalar) readonly buffer LightBuffer {
     Light lights[];
-};
\ No newline at end of file
+};
+
+struct Billboard {
+    vec4 center;
+    vec4 rotation; // quaternion, for fixed billboards
+    vec4 tint;
+    vec2 uvs[4];
+    vec2 size;
+    PbrMaterial material;
+    uint mode;
+    uint sizeMode;
+    int light; // index into the light buffer whose color multiplies the tint, or -1
+    uint padding;
+};
+
+layout(buffer_reference, scalar) readonly buffer BillboardBuffer {
+    Billboard billboards[];
+};
+
+struct Particle {
+    vec3 position;
+    float age;
+    vec3 velocity;
+    float lifetime; // dead once age reaches it
+};
+
+layout(buffer_reference, scalar) buffer ParticleBuffer {
+    Particle particles[];
+};
+
+// An emitter's color and size curves at one point of the particle lifetime
+struct CurveSample {
+    vec4 color;
+    float size;
+};
+
+const uint CURVE_SAMPLES = 32u; // evenly spaced from the start to the end of the lifetime, see Emitter::CURVE_SAMPLES
+
+layout(buffer_reference, scalar) readonly buffer ParticleCurveBuffer {
+    CurveSample samples[CURVE_SAMPLES];
+};
+
+// Interpolates between the samples around t, the fraction of the lifetime that has passed
+CurveSample sampleCurve(ParticleCurveBuffer curves, float t) {
+    float position = clamp(t, 0.0, 1.0) * float(CURVE_SAMPLES - 1u);
+    uint index = min(uint(position), CURVE_SAMPLES - 2u);
+    CurveSample a = curves.samples[index];
+    CurveSample b = curves.samples[index + 1u];
+    float s = position - float(index);
+    return CurveSample(mix(a.color, b.color, s), mix(a.size, b.size, s));
+}
\ No newline at end of file
@@ -4,7 +4,7 @@
 
 layout( push_constant ) uniform constants
 {
-    SceneDataBuffer sceneDataBuffer;
+    SceneDataBuffer sceneData;
 } PushConstants;
 
 layout(location = 2) out vec3 nearPoint;
@@ -26,7 +26,7 @@ vec3 UnprojectPoint(float x, float y, float z, mat4 view, mat4 projection) {
 }
 
 void main() {
-    SceneDataBuffer sceneData = PushConstants.sceneDataBuffer;
+    SceneDataBuffer sceneData = PushConstants.sceneData;
 
     vec3 p = gridPlane[gl_VertexIndex].xyz;
     nearPoint = UnprojectPoint(p.x, p.y, 0.0, sceneData.view, sceneData.proj).xyz; // unprojecting on the near plane
@@ -11,9 +11,9 @@ layout (location = 0) out vec4 outFragColor;
 
 layout( push_constant ) uniform constants {
     mat4 transform;
-    SceneDataBuffer sceneDataBuffer;
+    SceneDataBuffer sceneData;
     VertexBuffer vertexBuffer;
-    PbrMaterial pbrMaterial;
+    PbrMaterial material;
     LightBuffer lightBuffer;
 } PushConstants;
 
@@ -39,7 +39,7 @@ float getSpotAngleAttenuation(vec3 l, vec3 lightDir, float innerAngle, float out
 
 vec3 BSDF(Light light, float roughness, vec3 f0, vec3 n, vec3 diffuseColor, vec3 l) {
     // view vector
-    vec3 v = normalize(PushConstants.sceneDataBuffer.camera_position.xyz - worldPos);
+    vec3 v = normalize(PushConstants.sceneData.camera_position.xyz - worldPos);
     vec3 h = normalize(v + l);
 
     float NoV = abs(dot(n, v)) + 1e-5;
@@ -69,14 +69,20 @@ vec3 BSDF(Light light, float roughness, vec3 f0, vec3 n, vec3 diffuseColor, vec3
 
 vec3 evaluatePunctualLight(Light light, float roughness, vec3 f0, vec3 n, vec3 diffuseColor) {
     // light incident vector
-    vec3 l = normalize(light.position.xyz - worldPos);
+    vec3 l;
+    float attenuation = 1.0;
+    if (light.position.w == 0.0) {
+        // directional lights only have a direction and don't fall off
+        l = normalize(-light.direction.xyz);
+    } else {
+        vec3 posToLight = light.position.xyz - worldPos;
+        l = normalize(posToLight);
+
+        float invRadius = 1.0 / light.radius;
+        attenuation  = getSquareFalloffAttenuation(posToLight, invRadius);
+        attenuation *= getSpotAngleAttenuation(l, light.direction.xyz, light.inner_angle, light.outer_angle);
+    }
     float NoL = clamp(dot(n, l), 0.0, 1.0);
-    vec3 posToLight = light.position.xyz - worldPos;
-
-    float attenuation;
-    float invRadius = 1.0 / light.radius;
-    attenuation  = getSquareFalloffAttenuation(posToLight, invRadius);
-    attenuation *= getSpotAngleAttenuation(l, light.direction.xyz, light.inner_angle, light.outer_angle);
 
     vec3 luminance = (BSDF(light, roughness, f0, n, diffuseColor, l) * light.intensity * attenuation * NoL) * light.color.rgb; // = * light color
     return luminance;
@@ -104,27 +110,76 @@ float textureProj(vec4 shadowCoord, vec2 off, uint shadowMap)
     return shadow;
 }
 
+#ifdef SHADOW_PCF
+// averages the shadow test over the 3x3 neighbouring texels for soft edges
+float filterPCF(vec4 shadowCoord, uint shadowMap)
+{
+    vec2 texelSize = 1.0 / vec2(textureSize(tex[shadowMap], 0));
+    float shadow = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            shadow += textureProj(shadowCoord, vec2(x, y) * texelSize, shadowMap);
+        }
+    }
+    return shadow / 9.0;
+}
+#endif
+
+#ifdef HAS_NORMAL_MAP
+// there are no vertex tangents, so the tangent frame is derived from the screen space derivatives of position and UVs
+vec3 perturbNormal(vec3 n, uint normalMap)
+{
+    vec3 dp1 = dFdx(worldPos);
+    vec3 dp2 = dFdy(worldPos);
+    vec2 duv1 = dFdx(texCoords);
+    vec2 duv2 = dFdy(texCoords);
+
+    vec3 dp2perp = cross(dp2, n);
+    vec3 dp1perp = cross(n, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invmax = inversesqrt(max(dot(t, t), dot(b, b)));
+    mat3 tbn = mat3(t * invmax, b * invmax, n);
+
+    vec3 tangentNormal = texture(tex[normalMap], texCoords).xyz * 2.0 - 1.0;
+    return normalize(tbn * tangentNormal);
+}
+#endif
+
 void main() {
-    PbrMaterial mat = PushConstants.pbrMaterial;
+    PbrMaterial mat = PushConstants.material;
 //    float roughness = perceptualRoughness * perceptualRoughness;
     float roughness = mat.roughness;
     float metallic = mat.metallic;
     float reflectance = 0.0;
-    vec3 baseColor = mat.albedo.rgb * texture(tex[mat.albedo_tex], texCoords).rgb;
+    vec4 albedo = mat.albedo * texture(tex[mat.albedo_tex], texCoords);
+#ifdef ALPHA_MASK
+    if (albedo.a < mat.alpha_cutoff) {
+        discard;
+    }
+#endif
+    vec3 baseColor = albedo.rgb;
     vec3 diffuseColor = (1.0 - metallic) * baseColor.rgb;
     vec3 acc = vec3(0.0);
     vec3 normal = inNormal;
+#ifdef HAS_NORMAL_MAP
+    normal = perturbNormal(normalize(normal), mat.normal_tex);
+#endif
 
     vec3 f0 = 0.16 * reflectance * reflectance * (1.0 - metallic) + baseColor * metallic;
 
-    for(int i = 0; i < PushConstants.sceneDataBuffer.num_lights; i++)
+    for(int i = 0; i < PushConstants.sceneData.light_count; i++)
     {
         Light light = PushConstants.lightBuffer.lights[i];
-        vec4 lightPos = PushConstants.sceneDataBuffer.view * vec4(light.position.xyz, 1.0);
+        vec4 lightPos = PushConstants.sceneData.view * vec4(light.position.xyz, 1.0);
 
-        vec4 fragPosLightSpace = bias * light.lightspace * vec4(worldPos.xyz, 1.0);
+        vec4 fragPosLightSpace = bias * light.viewproj * vec4(worldPos.xyz, 1.0);
         vec4 projCoords = fragPosLightSpace / fragPosLightSpace.w;
+#ifdef SHADOW_PCF
+        float shadow = filterPCF(projCoords, light.shadow_map);
+#else
         float shadow = textureProj(projCoords, vec2(0.0, 0.0), light.shadow_map);
+#endif
         acc += shadow * evaluatePunctualLight(light, roughness, f0, normal, diffuseColor);
     }
     acc = clamp(acc, 0.0, 1.0);
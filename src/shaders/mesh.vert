@@ -9,9 +9,9 @@ layout (location = 2) out vec3 outNormal;
 layout( push_constant ) uniform constants
 {
     mat4 transform;
-    SceneDataBuffer sceneDataBuffer;
+    SceneDataBuffer sceneData;
     VertexBuffer vertexBuffer;
-    PbrMaterial pbrMaterial;
+    PbrMaterial material;
     LightBuffer lightBuffer;
 } PushConstants;
 
@@ -19,7 +19,7 @@ void main()
 {
     //load vertex data from device adress
     Vertex v = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
-    SceneDataBuffer sceneData = PushConstants.sceneDataBuffer;
+    SceneDataBuffer sceneData = PushConstants.sceneData;
     //output data
     outWorldPos = (PushConstants.transform * vec4(v.position, 1.0)).xyz;
     gl_Position = sceneData.viewproj * vec4(outWorldPos, 1.0f);
@@ -0,0 +1,20 @@
+#version 450
+#include "globals.glsl"
+
+//push constants block
+layout( push_constant, scalar ) uniform constants
+{
+    mat4 transform;
+    SceneDataBuffer sceneData;
+    VertexBuffer vertexBuffer;
+    vec4 color;
+    vec2 viewportSize;
+    float width;
+} PushConstants;
+
+layout (location = 0) out vec4 outFragColor;
+
+void main()
+{
+    outFragColor = PushConstants.color;
+}
\ No newline at end of file
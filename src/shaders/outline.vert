@@ -0,0 +1,28 @@
+#version 450
+#include "globals.glsl"
+
+//push constants block
+layout( push_constant, scalar ) uniform constants
+{
+    mat4 transform;
+    SceneDataBuffer sceneData;
+    VertexBuffer vertexBuffer;
+    vec4 color;
+    vec2 viewportSize;
+    float width; // outline width in pixels, 0 when writing the stencil mask
+} PushConstants;
+
+void main()
+{
+    Vertex v = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
+    mat4 viewproj = PushConstants.sceneData.viewproj;
+    vec4 position = viewproj * PushConstants.transform * vec4(v.position, 1.0);
+
+    // push the vertex out along its normal in screen space, scaled by w so the width stays the same in pixels
+    vec3 normal = mat3(transpose(inverse(PushConstants.transform))) * v.normal;
+    vec2 screenNormal = (viewproj * vec4(normal, 0.0)).xy;
+    if (length(screenNormal) > 0.0) {
+        position.xy += normalize(screenNormal) * PushConstants.width * 2.0 / PushConstants.viewportSize * position.w;
+    }
+    gl_Position = position;
+}
\ No newline at end of file
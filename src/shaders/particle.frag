@@ -0,0 +1,24 @@
+#version 450
+#include "globals.glsl"
+#extension GL_EXT_nonuniform_qualifier : enable
+
+layout (location = 0) in vec2 texCoords;
+layout (location = 1) in vec4 color;
+
+layout( push_constant, scalar ) uniform constants
+{
+    SceneDataBuffer sceneData;
+    ParticleBuffer particleBuffer;
+    PbrMaterial material;
+    ParticleCurveBuffer curveBuffer;
+    uint flipbookColumns;
+    uint flipbookRows;
+} PushConstants;
+
+layout (location = 0) out vec4 outFragColor;
+layout (set = 0, binding = 2) uniform sampler2D tex[];
+
+void main() {
+    PbrMaterial material = PushConstants.material;
+    outFragColor = material.albedo * texture(tex[material.albedo_tex], texCoords) * color;
+}
\ No newline at end of file
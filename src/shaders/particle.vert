@@ -0,0 +1,55 @@
+#version 450
+#include "globals.glsl"
+
+layout( push_constant, scalar ) uniform constants
+{
+    SceneDataBuffer sceneData;
+    ParticleBuffer particleBuffer;
+    PbrMaterial material;
+    ParticleCurveBuffer curveBuffer;
+    uint flipbookColumns;
+    uint flipbookRows;
+} PushConstants;
+
+layout (location = 0) out vec2 texCoords;
+layout (location = 1) out vec4 color;
+
+const vec2[] corners = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(-1.0, 1.0),
+    vec2(1.0, -1.0),
+    vec2(-1.0, 1.0),
+    vec2(1.0, -1.0),
+    vec2(1.0, 1.0)
+);
+
+void main()
+{
+    Particle particle = PushConstants.particleBuffer.particles[gl_InstanceIndex];
+    if (particle.age >= particle.lifetime) {
+        // dead particles collapse to a point outside the clip volume
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        return;
+    }
+    float t = particle.age / particle.lifetime;
+
+    // particles always face the camera, like spherical billboards
+    mat4 view = PushConstants.sceneData.view;
+    vec3 right = vec3(view[0][0], view[1][0], view[2][0]);
+    vec3 up = vec3(view[0][1], view[1][1], view[2][1]);
+    vec2 corner = corners[gl_VertexIndex];
+    CurveSample curve = sampleCurve(PushConstants.curveBuffer, t);
+    float size = curve.size;
+    vec3 world_pos = particle.position + (right * corner.x + up * corner.y) * size;
+    gl_Position = PushConstants.sceneData.viewproj * vec4(world_pos, 1.0);
+
+    // the flipbook is played row by row over the particle's lifetime
+    uint columns = max(PushConstants.flipbookColumns, 1u);
+    uint rows = max(PushConstants.flipbookRows, 1u);
+    uint frames = columns * rows;
+    uint frame = min(uint(t * float(frames)), frames - 1u);
+    vec2 cell = vec2(frame % columns, frame / columns);
+    texCoords = (cell + corner * 0.5 + 0.5) / vec2(columns, rows);
+
+    color = curve.color;
+}
\ No newline at end of file
@@ -5,7 +5,7 @@
 layout( push_constant ) uniform constants
 {
     mat4 transform;
-    SceneDataBuffer sceneDataBuffer;
+    SceneDataBuffer sceneData;
     VertexBuffer vertexBuffer;
     LightBuffer lightBuffer;
 } PushConstants;
@@ -14,11 +14,11 @@ void main()
 {
     //load vertex data from device adress
     Vertex v = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
-    SceneDataBuffer sceneData = PushConstants.sceneDataBuffer;
+    SceneDataBuffer sceneData = PushConstants.sceneData;
     // light id = instance index
     Light light = PushConstants.lightBuffer.lights[gl_InstanceIndex];
     //output data
     vec3 outWorldPos = (PushConstants.transform * vec4(v.position, 1.0)).xyz;
-    gl_Position = light.lightspace * vec4(outWorldPos, 1.0f);
+    gl_Position = light.viewproj * vec4(outWorldPos, 1.0f);
 //    gl_Position = sceneData.viewproj * vec4(outWorldPos, 1.0f);
 }
\ No newline at end of file
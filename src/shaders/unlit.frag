@@ -3,16 +3,16 @@
 #extension GL_EXT_nonuniform_qualifier : enable
 
 layout (location = 0) in vec2 texCoords;
+layout (location = 1) flat in uint billboardIndex;
+layout (location = 2) flat in vec4 tint;
 
 
 layout( push_constant, scalar ) uniform constants
 {
-    mat4 transform;  // model matrix
-    vec2 size;       // size of the billboard
-    vec2[4] uv;
-    SceneDataBuffer sceneDataBuffer;
-    PbrMaterial pbrMaterial;
+    SceneDataBuffer sceneData;
+    BillboardBuffer billboardBuffer;
     LightBuffer lightBuffer;
+    float viewportHeight;
 } PushConstants;
 
 layout (location = 0) out vec4 outFragColor;
@@ -21,5 +21,6 @@ layout (set = 0, binding = 2) uniform sampler2D tex[];
 
 void main() {
 
-    outFragColor = PushConstants.pbrMaterial.albedo * texture(tex[PushConstants.pbrMaterial.albedo_tex], texCoords) * vec4(1.0);
+    PbrMaterial material = PushConstants.billboardBuffer.billboards[billboardIndex].material;
+    outFragColor = material.albedo * texture(tex[material.albedo_tex], texCoords) * tint;
 }
\ No newline at end of file
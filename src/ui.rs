@@ -1,86 +1,94 @@
+use crate::asset::icon_atlas::IconAtlas;
 use crate::asset::material::{Material, RawMaterial};
-use crate::asset::texture::{Texture, TextureId, TextureKind};
+use crate::asset::texture::{Texture, TextureKind};
 use crate::camera::Camera;
 use crate::commands::Command;
+use crate::gizmo::Gizmo;
+use crate::history::{Edit, History};
 use crate::observe;
+use crate::pipeline::compiler::ShaderError;
+use crate::render_graph::RenderGraphInfo;
 use crate::resource::immediate_submit::SubmitContext;
-use crate::scene::billboard::Billboard;
+use crate::scene::billboard::{Billboard, BillboardMode, SizeMode};
 use crate::scene::light::{LightManager, LightMeta};
 use crate::scene::model::{Model, ModelId};
+use crate::scene::particles::{sample_curve, CurveValue, EmitterSettings, ParticleBlend};
+use crate::scene::primitive::Primitive;
+use crate::selection::Selection;
 use crate::AppSettings;
 use crate::TextureManager;
 use crate::World;
 use crate::{util, MaterialManager};
-use egui::{Align2, Color32, Rgba, RichText, TextBuffer, TextureFilter, Ui, Widget};
-use glam::{Mat4, Vec2, Vec4};
+use ash::vk;
+use egui::load::SizedTexture;
+use egui::{Align2, Color32, Key, KeyboardShortcut, Modifiers, Rgba, RichText, TextBuffer, Ui, Widget};
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3, Vec4};
+use hashbrown::HashMap;
 use std::cell::{RefCell, RefMut};
 use std::rc::Rc;
 use std::sync::mpsc;
 
-#[derive(Default)]
-struct GuiTexture {
-    loaded_tex: Option<TextureId>,
-    texture: Option<egui::TextureHandle>,
-}
+mod outliner;
+
+use outliner::Outliner;
+
+struct GuiTexture;
 
 impl GuiTexture {
+    // Engine textures live in the bindless set already, so egui samples them directly instead of getting its own copy.
     fn ui(&mut self, ui: &mut egui::Ui, engine_texture: &Texture) {
-        if self.loaded_tex != Some(engine_texture.id) {
-            self.loaded_tex = Some(engine_texture.id);
-            self.texture = None;
-        }
-        let texture: &egui::TextureHandle = self.texture.get_or_insert_with(|| {
-            // Load the texture only once.
-            ui.ctx().load_texture(
-                "texture preview",
-                egui::ColorImage::from_rgba_unmultiplied(
-                    [
-                        engine_texture.image.extent.width as usize,
-                        engine_texture.image.extent.height as usize,
-                    ],
-                    &engine_texture.data,
-                ),
-                egui::TextureOptions {
-                    magnification: if engine_texture.sampler == 0 {
-                        TextureFilter::Nearest
-                    } else {
-                        TextureFilter::Linear
-                    },
-                    minification: if engine_texture.sampler == 0 {
-                        TextureFilter::Nearest
-                    } else {
-                        TextureFilter::Linear
-                    },
-                    wrap_mode: Default::default(),
-                },
-            )
-        });
-
-        // Show the image:
         let size = util::size_image(
             engine_texture.image.extent.width as usize,
             engine_texture.image.extent.height as usize,
             256,
         );
         let size = (size.0 as f32, size.1 as f32);
-        ui.image((texture.id(), egui::Vec2::from(size)));
+        ui.image((egui::TextureId::User(engine_texture.id as u64), egui::Vec2::from(size)));
     }
 }
 
+/// Which space transforms are shown and edited in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransformSpace {
+    Local, // relative to the parent
+    World,
+}
+
 pub struct Gui {
     cmd_sender: mpsc::Sender<Command>,
     image: GuiTexture,
     image_lock: bool,
+    transform_space: TransformSpace,
+    outliner: Outliner,
+    clipboard: Option<ModelId>, // model copied in its Actions menu
+    primitives: [Primitive; 7], // parameters of the primitives in the Add menu
+    // Euler angles (degrees) last shown per model along with the rotation they were derived from. Decomposing the rotation
+    // again every frame can flip to an equivalent set of angles mid-drag, so they're reused while the rotation matches.
+    euler_angles: HashMap<ModelId, (Quat, Vec3)>,
 }
 
 impl Gui {
+    const MIN_SCALE: f32 = 0.001; // smallest magnitude a scale component can be set to in the inspector
+
     pub fn new(cmd_sender: mpsc::Sender<Command>) -> Self {
         Self {
             cmd_sender,
-            image: GuiTexture::default(),
+            image: GuiTexture,
             image_lock: false,
+            transform_space: TransformSpace::Local,
+            outliner: Outliner::default(),
+            clipboard: None,
+            primitives: Primitive::ALL,
+            euler_angles: HashMap::new(),
         }
     }
+
+    /// Forgets what's kept per model for models that are no longer in the world, e.g. after deleting them or undoing
+    /// their addition.
+    pub fn forget_removed_models(&mut self, world: &World) {
+        self.euler_angles.retain(|model, _| world.models.contains_key(model));
+    }
+
     pub fn draw(
         &mut self,
         ctx: egui::Context,
@@ -90,11 +98,18 @@ impl Gui {
         texture_manager: Rc<RefCell<TextureManager>>,
         material_manager: Rc<RefCell<MaterialManager>>,
         light_manager: Rc<RefCell<LightManager>>,
+        icons: &IconAtlas,
+        history: &History,
+        gizmo: &mut Gizmo,
+        selection: &mut Selection,
+        shader_errors: &[ShaderError],
+        render_graph: &RenderGraphInfo,
         mut _submit_context: SubmitContext,
     ) {
         ctx.style_mut(|style| {
             style.visuals.window_shadow = egui::epaint::Shadow::NONE;
         });
+        selection.retain(|model| world.borrow().models.contains_key(model));
 
         egui::Window::new("World").show(&ctx, |ui| {
             ui.horizontal(|ui| {
@@ -133,14 +148,52 @@ impl Gui {
                             world.borrow().get_billboards()
                         );
                     }
+                    ui.separator();
+                    for meta in LightMeta::ALL {
+                        if ui.button(format!("Add {}", meta.name())).clicked() {
+                            self.cmd_sender.send(Command::AddLight(meta)).unwrap();
+                            ui.close_menu();
+                        }
+                    }
+                    ui.separator();
+                    for primitive in self.primitives.iter_mut() {
+                        ui.menu_button(primitive.name(), |ui| {
+                            primitive_params(ui, primitive);
+                            if ui.button("Add").clicked() {
+                                self.cmd_sender.send(Command::AddPrimitive(*primitive)).unwrap();
+                                ui.close_menu();
+                            }
+                        });
+                    }
                 });
+                #[cfg(feature = "watch")]
                 if ui.button("Reload Shaders").clicked() {
                     self.cmd_sender.send(Command::ReloadShaders).unwrap();
                 }
             });
 
+            gizmo.ui(ui);
             ui.checkbox(&mut app_settings.show_grid, "Show grid");
             ui.checkbox(&mut app_settings.view_as_light, "View as light");
+            ui.checkbox(&mut app_settings.shadow_pcf, "Soft shadows (PCF)");
+            let mut msaa = app_settings.msaa;
+            egui::ComboBox::from_label("Anti-aliasing (MSAA)")
+                .selected_text(Self::msaa_name(msaa))
+                .show_ui(ui, |ui| {
+                    for option in [1, 2, 4, 8].map(vk::SampleCountFlags::from_raw) {
+                        ui.add_enabled_ui(app_settings.supported_msaa.contains(option), |ui| {
+                            ui.selectable_value(&mut msaa, option, Self::msaa_name(option));
+                        });
+                    }
+                });
+            if msaa != app_settings.msaa {
+                self.cmd_sender.send(Command::SetMsaa(msaa)).unwrap();
+            }
+            ui.horizontal(|ui| {
+                ui.label("Selection outline");
+                ui.color_edit_button_rgba_unmultiplied(&mut app_settings.outline_color);
+                ui.add(egui::Slider::new(&mut app_settings.outline_width, 0.0..=10.0).text("Width"));
+            });
             egui::CollapsingHeader::new("Camera".as_str()).show(ui, |ui| {
                 observe!(
                     camera.target,
@@ -164,17 +217,16 @@ impl Gui {
                 );
             });
             ui.label(RichText::new("Scene").size(16.0));
-            ui.label("Models");
-            let models = world.borrow().get_toplevel_model_ids();
-            for model in models {
-                self.model_div(
-                    ui,
-                    model,
-                    world.clone(),
-                    material_manager.clone(),
-                    &mut light_manager.borrow_mut(),
-                    _submit_context.clone(),
-                );
+            ui.horizontal(|ui| {
+                ui.label("Selected model");
+                ui.selectable_value(&mut self.transform_space, TransformSpace::Local, "Local");
+                ui.selectable_value(&mut self.transform_space, TransformSpace::World, "World");
+            });
+            match selection.primary() {
+                Some(model) => self.model_div(ui, model, world.clone(), material_manager.clone(), selection),
+                None => {
+                    ui.label(RichText::new("Select a model in the outliner or the viewport").weak());
+                }
             }
             ui.separator();
             ui.label("Lights");
@@ -183,20 +235,63 @@ impl Gui {
                 ui.collapsing(format!("Light {}", light_id), |ui| {
                     let mgr = light_manager.borrow();
                     let light = mgr.get_light(light_id).unwrap();
-                    let mut cutoff_angle = light.data.cutoff_angle.to_degrees();
+                    let meta = light.meta;
+                    ui.horizontal(|ui| {
+                        let mut switched = meta;
+                        egui::ComboBox::from_id_source(("Light type", light_id))
+                            .selected_text(meta.name())
+                            .show_ui(ui, |ui| {
+                                for option in LightMeta::ALL {
+                                    // keep the spotlight's parameters if it already is one
+                                    let option = if std::mem::discriminant(&option) == std::mem::discriminant(&meta) {
+                                        meta
+                                    } else {
+                                        option
+                                    };
+                                    ui.selectable_value(&mut switched, option, option.name());
+                                }
+                            });
+                        if switched != meta {
+                            self.cmd_sender
+                                .send(Command::Edit(Edit::LightType {
+                                    light: light_id,
+                                    before: meta,
+                                    after: switched,
+                                    data: light.data,
+                                }))
+                                .unwrap();
+                        }
+                        if icon_button(ui, icons, "delete", "Delete", false).clicked() {
+                            // lights with a model are deleted along with it, so that undoing brings both back
+                            let owner = world
+                                .borrow()
+                                .models
+                                .values()
+                                .find(|model| model.light == Some(light_id))
+                                .map(|model| model.id);
+                            let command = owner.map_or(Command::Edit(Edit::remove_light(light_id)), Command::DeleteModel);
+                            self.cmd_sender.send(command).unwrap();
+                        }
+                    });
+                    let mut outer_angle = light.data.outer_angle.to_degrees();
                     let mut inner_angle = light.data.inner_angle.to_degrees();
                     let mut radius = light.data.radius;
 
                     let mut intensity = light.data.intensity;
                     let mut dir = light.data.direction;
                     let mut color = light.data.color;
+                    let before = light.data;
                     drop(mgr);
                     observe!(
-                        (cutoff_angle, inner_angle, radius, intensity, dir, color),
+                        (outer_angle, inner_angle, radius, intensity, dir, color),
                         {
-                            ui.add(egui::Slider::new(&mut cutoff_angle, 0.0..=180.0).text("Cutoff"));
-                            ui.add(egui::Slider::new(&mut inner_angle, 0.0..=180.0).text("Inner"));
-                            ui.add(egui::Slider::new(&mut radius, 0.0..=100.0).text("Radius"));
+                            if let LightMeta::Spotlight { .. } = meta {
+                                ui.add(egui::Slider::new(&mut outer_angle, 0.0..=180.0).text("Cutoff"));
+                                ui.add(egui::Slider::new(&mut inner_angle, 0.0..=180.0).text("Inner"));
+                            }
+                            if meta != LightMeta::Directional {
+                                ui.add(egui::Slider::new(&mut radius, 0.0..=100.0).text("Radius"));
+                            }
                             ui.add(egui::Slider::new(&mut intensity, 0.0..=150.0).text("Intensity"));
                             ui.horizontal(|ui| {
                                 ui.label("Direction");
@@ -208,21 +303,20 @@ impl Gui {
                             ui.color_edit_button_rgba_unmultiplied(&mut color);
                         },
                         |v| {
-                            _submit_context.clone().immediate_submit(Box::new(|ctx| {
-                                light_manager.borrow_mut().update_light(
-                                    light_id,
-                                    |light| {
-                                        light.cutoff_angle = cutoff_angle.to_radians();
-                                        light.intensity = intensity;
-                                        light.direction = dir;
-                                        light.radius = radius;
-                                        light.inner_angle = inner_angle.to_radians();
-                                        light.color = color;
-                                        light.update_viewproj();
-                                    },
-                                    ctx,
-                                );
-                            }));
+                            let mut after = before;
+                            after.outer_angle = outer_angle.to_radians();
+                            after.intensity = intensity;
+                            after.direction = dir;
+                            after.radius = radius;
+                            after.inner_angle = inner_angle.to_radians();
+                            after.color = color;
+                            self.cmd_sender
+                                .send(Command::Edit(Edit::Light {
+                                    light: light_id,
+                                    before,
+                                    after,
+                                }))
+                                .unwrap();
                         }
                     );
                 });
@@ -251,7 +345,14 @@ impl Gui {
                             ui.add(egui::DragValue::new(&mut center.z).speed(0.01).prefix("Z"));
                         },
                         |v| {
-                            world.borrow_mut().update_billboard(id, v, billboard.uvs);
+                            self.send_billboard_edit(
+                                id,
+                                &billboard,
+                                Billboard {
+                                    center: v,
+                                    ..billboard.clone()
+                                },
+                            );
                         }
                     );
                     ui.horizontal(|ui| {
@@ -268,16 +369,99 @@ impl Gui {
                             )
                             .show_ui(ui, |ui| {
                                 let material_manager = material_manager.borrow();
+                                let mut material = billboard.material;
                                 for (mid, mlabel, _) in material_manager.iter_materials() {
-                                    ui.selectable_value(
-                                        &mut world.borrow_mut().models.get_mut(&id).unwrap().billboard.as_mut().unwrap().material,
-                                        mid,
-                                        mlabel.clone().unwrap_or("Untitled".into()),
+                                    ui.selectable_value(&mut material, mid, mlabel.clone().unwrap_or("Untitled".into()));
+                                }
+                                if material != billboard.material {
+                                    self.send_billboard_edit(
+                                        id,
+                                        &billboard,
+                                        Billboard {
+                                            material,
+                                            ..billboard.clone()
+                                        },
                                     );
                                 }
                             });
                     });
 
+                    ui.horizontal(|ui| {
+                        let mut mode = billboard.mode;
+                        egui::ComboBox::from_id_source(("Billboard mode", id))
+                            .selected_text(mode.name())
+                            .show_ui(ui, |ui| {
+                                for option in BillboardMode::ALL {
+                                    ui.selectable_value(&mut mode, option, option.name());
+                                }
+                            });
+                        let mut size_mode = billboard.size_mode;
+                        egui::ComboBox::from_id_source(("Billboard size mode", id))
+                            .selected_text(size_mode.name())
+                            .show_ui(ui, |ui| {
+                                for option in SizeMode::ALL {
+                                    ui.selectable_value(&mut size_mode, option, option.name());
+                                }
+                            });
+                        let mut ignore_depth = billboard.ignore_depth;
+                        ui.checkbox(&mut ignore_depth, "Ignore depth");
+                        let mut blend = billboard.blend;
+                        ui.checkbox(&mut blend, "Blend");
+                        let edited = Billboard {
+                            mode,
+                            size_mode,
+                            ignore_depth,
+                            blend,
+                            ..billboard.clone()
+                        };
+                        if (mode, size_mode, ignore_depth, blend)
+                            != (billboard.mode, billboard.size_mode, billboard.ignore_depth, billboard.blend)
+                        {
+                            self.send_billboard_edit(id, &billboard, edited);
+                        }
+                    });
+                    let mut size = billboard.size;
+                    observe!(
+                        size,
+                        {
+                            ui.horizontal(|ui| {
+                                ui.label("Size");
+                                ui.add(egui::DragValue::new(&mut size.x).speed(0.01).range(0.0..=f32::MAX).prefix("X"));
+                                ui.add(egui::DragValue::new(&mut size.y).speed(0.01).range(0.0..=f32::MAX).prefix("Y"));
+                            });
+                        },
+                        |v| {
+                            self.send_billboard_edit(
+                                id,
+                                &billboard,
+                                Billboard {
+                                    size: v,
+                                    ..billboard.clone()
+                                },
+                            );
+                        }
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("Tint");
+                        let mut tint = billboard.tint.to_array();
+                        observe!(
+                            tint,
+                            {
+                                ui.color_edit_button_rgba_unmultiplied(&mut tint);
+                            },
+                            |v| {
+                                self.send_billboard_edit(
+                                    id,
+                                    &billboard,
+                                    Billboard {
+                                        tint: Vec4::from(v),
+                                        ..billboard.clone()
+                                    },
+                                );
+                            }
+                        );
+                    });
+
                     // allow setting UVs
                     ui.label("UVs");
                     let mut uvs = billboard.uvs;
@@ -295,7 +479,14 @@ impl Gui {
                             }
                         },
                         |v| {
-                            world.borrow_mut().update_billboard(id, billboard.center, v);
+                            self.send_billboard_edit(
+                                id,
+                                &billboard,
+                                Billboard {
+                                    uvs: v,
+                                    ..billboard.clone()
+                                },
+                            );
                         }
                     );
                 });
@@ -311,7 +502,7 @@ impl Gui {
                     ui.label(RichText::new("Textures").size(16.0));
                     if ui.button("Import").clicked() {
                         if let Some(path) = rfd::FileDialog::new()
-                            .add_filter("Images", &["png", "jpg", "jpeg", "tga", "bmp"])
+                            .add_filter("Images", &["png", "jpg", "jpeg", "tga", "bmp", "ktx2"])
                             .set_directory(std::env::current_dir().unwrap())
                             .pick_file()
                         {
@@ -326,8 +517,16 @@ impl Gui {
                         |ui| {
                             ui.label(format!("Width: {}", texture.image.extent.width));
                             ui.label(format!("Height: {}", texture.image.extent.height));
-                            ui.label(format!("Format: {:?}", texture.image.format));
+                            ui.label(format!("Format: {:?}", texture.format()));
+                            ui.label(format!("Compressed: {}", texture.is_compressed()));
+                            ui.label(format!("Mip levels: {}", texture.image.mip_levels));
+                            ui.label(format!("CPU copy: {}", texture.data.is_some()));
                             ui.label(format!("Sampler: {}", texture.sampler));
+                            ui.label(format!("References: {}", texture_manager.ref_count(texture.id)));
+                            // materials still using a deleted texture fall back to the checkerboard
+                            if !TextureManager::is_builtin(texture.id) && ui.button("Delete").clicked() {
+                                self.cmd_sender.send(Command::DeleteTexture(texture.id)).unwrap();
+                            }
                             if !self.image_lock {
                                 self.image_lock = true;
                                 self.image.ui(ui, texture);
@@ -336,11 +535,119 @@ impl Gui {
                     );
                 }
                 ui.separator();
-                Self::materials(material_manager, _submit_context, ui, texture_manager);
+                Self::materials(&self.cmd_sender, material_manager, _submit_context, ui, texture_manager);
+            });
+
+        egui::Window::new("Outliner").show(&ctx, |ui| {
+            self.outliner.ui(ui, &world.borrow(), selection, icons, &self.cmd_sender);
+        });
+
+        // shown until the shaders compile again, the pipelines keep using the last working version meanwhile
+        if !shader_errors.is_empty() {
+            egui::Window::new(RichText::new("Shader errors").color(Color32::LIGHT_RED))
+                .id(egui::Id::new("Shader errors"))
+                .pivot(Align2::CENTER_BOTTOM)
+                .default_pos((ctx.screen_rect().center().x, ctx.screen_rect().bottom() - 15.0))
+                .show(&ctx, |ui| {
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        for error in shader_errors {
+                            ui.label(RichText::new(&error.shader).strong());
+                            ui.label(RichText::new(&error.message).monospace());
+                        }
+                    });
+                    #[cfg(feature = "watch")]
+                    if ui.button("Retry").clicked() {
+                        self.cmd_sender.send(Command::ReloadShaders).unwrap();
+                    }
+                });
+        }
+
+        egui::Window::new("History").default_open(false).show(&ctx, |ui| {
+            ui.horizontal(|ui| {
+                if icon_button(ui, icons, "undo", "Undo", false).clicked() {
+                    self.cmd_sender.send(Command::Undo).unwrap();
+                }
+                if icon_button(ui, icons, "redo", "Redo", false).clicked() {
+                    self.cmd_sender.send(Command::Redo).unwrap();
+                }
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for edit in history.undo_entries() {
+                    ui.label(edit.describe());
+                }
+                // undone edits that can still be redone
+                for edit in history.redo_entries() {
+                    ui.label(RichText::new(edit.describe()).weak());
+                }
             });
+        });
+
+        egui::Window::new("Render graph").default_open(false).show(&ctx, |ui| {
+            egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                for pass in &render_graph.passes {
+                    let name = RichText::new(&pass.name).strong();
+                    // culled passes are still listed, to see why they were culled
+                    ui.label(if pass.culled { name.weak().strikethrough() } else { name });
+                    for barrier in &pass.barriers {
+                        ui.label(RichText::new(format!("barrier {}", barrier)).monospace().weak());
+                    }
+                    if !pass.reads.is_empty() {
+                        ui.label(format!("reads {}", pass.reads.join(", ")));
+                    }
+                    if !pass.writes.is_empty() {
+                        ui.label(format!("writes {}", pass.writes.join(", ")));
+                    }
+                    ui.separator();
+                }
+                for barrier in &render_graph.final_barriers {
+                    ui.label(RichText::new(format!("barrier {}", barrier)).monospace().weak());
+                }
+                ui.label(format!("Transient images: {}", render_graph.transient_images.len()));
+                for image in &render_graph.transient_images {
+                    ui.label(RichText::new(image).monospace());
+                }
+            });
+        });
+
+        // Ctrl+Shift+Z has to be checked first, since consume_shortcut ignores extra shift
+        if !ctx.wants_keyboard_input() {
+            if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z))) {
+                self.cmd_sender.send(Command::Redo).unwrap();
+            } else if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Z))) {
+                self.cmd_sender.send(Command::Undo).unwrap();
+            }
+            if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::D))) {
+                for model in selection.roots(&world.borrow()) {
+                    self.cmd_sender.send(Command::DuplicateModel(model)).unwrap();
+                }
+            }
+        }
+        // a released drag ends the current history entry, so the next drag can be undone separately
+        if ctx.input(|i| i.pointer.any_released()) {
+            self.cmd_sender.send(Command::EndEdit).unwrap();
+        }
+    }
+
+    fn msaa_name(samples: vk::SampleCountFlags) -> String {
+        match samples.as_raw() {
+            1 => "Off".into(),
+            n => format!("{}×", n),
+        }
+    }
+
+    fn send_billboard_edit(&self, model: ModelId, before: &Billboard, after: Billboard) {
+        self.cmd_sender
+            .send(Command::Edit(Edit::Billboard {
+                model,
+                before: before.clone(),
+                after,
+            }))
+            .unwrap();
     }
 
     fn materials(
+        cmd_sender: &mpsc::Sender<Command>,
         material_manager: Rc<RefCell<MaterialManager>>,
         mut _submit_context: SubmitContext,
         ui: &mut Ui,
@@ -405,21 +712,38 @@ impl Gui {
                                 });
                                 ui.add(egui::Slider::new(&mut mat.metallic, 0.0..=1.0).text("Metallic"));
                                 ui.add(egui::Slider::new(&mut mat.roughness, 0.0..=1.0).text("Roughness"));
+                                ui.label("Normal map");
+                                egui::ComboBox::from_id_source("Normal map")
+                                    .selected_text(
+                                        texture_manager
+                                            .get_texture(mat.normal_tex)
+                                            .unwrap()
+                                            .image
+                                            .label
+                                            .clone()
+                                            .unwrap_or("Untitled".into()),
+                                    )
+                                    .show_ui(ui, |ui| {
+                                        for texture in texture_manager.iter_textures().filter(|t| t.kind == TextureKind::Color) {
+                                            ui.selectable_value(
+                                                &mut mat.normal_tex,
+                                                texture.id,
+                                                texture.image.label.clone().unwrap_or("Untitled".into()),
+                                            );
+                                        }
+                                    });
+                                // 0 disables alpha masking
+                                ui.add(egui::Slider::new(&mut mat.alpha_cutoff, 0.0..=1.0).text("Alpha cutoff"));
                             },
                             |v| {
-                                _submit_context.clone().immediate_submit(Box::new(|ctx| {
-                                    material_manager.borrow_mut().get_material_mut(mid).unwrap().update(
-                                        |m| {
-                                            if let RawMaterial::Pbr(to_change) = m {
-                                                to_change.metallic = mat.metallic;
-                                                to_change.roughness = mat.roughness;
-                                                to_change.albedo_tex = mat.albedo_tex;
-                                                to_change.albedo = Rgba::from(albedo).to_rgba_unmultiplied();
-                                            }
-                                        },
-                                        ctx,
-                                    );
-                                }));
+                                mat.albedo = Rgba::from(albedo).to_rgba_unmultiplied();
+                                cmd_sender
+                                    .send(Command::Edit(Edit::Material {
+                                        material: mid,
+                                        before: RawMaterial::Pbr(data),
+                                        after: RawMaterial::Pbr(mat),
+                                    }))
+                                    .unwrap();
                             }
                         );
                     });
@@ -428,84 +752,289 @@ impl Gui {
         }
     }
     fn model_div(
-        &self,
+        &mut self,
         ui: &mut egui::Ui,
         model: ModelId,
         world: Rc<RefCell<World>>,
         material_manager: Rc<RefCell<MaterialManager>>,
-        light_manager: &mut LightManager,
-        ctx: SubmitContext,
+        selection: &mut Selection,
     ) {
-        let model_name = {
-            let model = &world.borrow().models[&model];
-            let model_name = format!("Untitled ({})", model.id);
-            model
-                .label
-                .clone()
-                .map(|s| s + format!(" ({})", model.id).as_str())
-                .unwrap_or(model_name)
-        };
+        let model_name = model_name(&world.borrow().models[&model]);
 
-        ui.collapsing(model_name, |ui| {
-            ui.menu_button("Actions", |ui| {
-                if ui.button("Delete").clicked() {
-                    self.cmd_sender.send(Command::DeleteModel(model)).unwrap();
+        egui::CollapsingHeader::new(RichText::new(model_name).strong())
+            .id_source("Selected model")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.menu_button("Actions", |ui| {
+                    if ui.button("Deselect").clicked() {
+                        selection.toggle(model);
+                        ui.close_menu();
+                    }
+                    if ui.button("Duplicate").clicked() {
+                        self.cmd_sender.send(Command::DuplicateModel(model)).unwrap();
+                        ui.close_menu();
+                    }
+                    if ui.button("Copy").clicked() {
+                        self.clipboard = Some(model);
+                        ui.close_menu();
+                    }
+                    let clipboard = self.clipboard.filter(|copied| world.borrow().models.contains_key(copied));
+                    if ui.add_enabled(clipboard.is_some(), egui::Button::new("Paste as child")).clicked() {
+                        self.cmd_sender
+                            .send(Command::PasteModel {
+                                model: clipboard.unwrap(),
+                                parent: model,
+                            })
+                            .unwrap();
+                        ui.close_menu();
+                    }
+                    let emitter = world.borrow().models[&model].emitter.clone();
+                    let (label, after) = match emitter {
+                        Some(_) => ("Remove particle emitter", None),
+                        None => ("Add particle emitter", Some(EmitterSettings::default())),
+                    };
+                    if ui.button(label).clicked() {
+                        self.cmd_sender
+                            .send(Command::Edit(Edit::Emitter {
+                                model,
+                                before: emitter,
+                                after,
+                            }))
+                            .unwrap();
+                        ui.close_menu();
+                    }
+                    if ui.button("Delete").clicked() {
+                        self.cmd_sender.send(Command::DeleteModel(model)).unwrap();
+                    }
+                });
+                self.transform_div(ui, model, &world.borrow());
+                let world = world.borrow();
+                let model = &world.models[&model];
+                if let Some(emitter) = &model.emitter {
+                    self.emitter_div(ui, model.id, emitter, &material_manager.borrow());
+                }
+                ui.label("Meshes");
+                for (i, mesh) in model.meshes.iter().enumerate() {
+                    self.mesh_div(ui, model.id, i, mesh, material_manager.clone());
                 }
             });
-            observe!(
-                world.borrow().models[&model].transform.w_axis,
-                {
-                    ui.horizontal(|ui| {
-                        ui.label("Position");
-                        ui.add(
-                            egui::DragValue::new(&mut world.borrow_mut().models.get_mut(&model).unwrap().transform.w_axis.x)
-                                .speed(0.01)
-                                .prefix("X: "),
-                        );
-                        ui.add(
-                            egui::DragValue::new(&mut world.borrow_mut().models.get_mut(&model).unwrap().transform.w_axis.y)
-                                .speed(0.01)
-                                .prefix("Y: "),
-                        );
-                        ui.add(
-                            egui::DragValue::new(&mut world.borrow_mut().models.get_mut(&model).unwrap().transform.w_axis.z)
-                                .speed(0.01)
-                                .prefix("Z: "),
-                        );
+    }
+
+    // Translation, rotation and scale of the model in the selected space. Edits are sent as local transforms.
+    fn transform_div(&mut self, ui: &mut egui::Ui, model: ModelId, world: &World) {
+        let before = world.models[&model].transform;
+        let parent = world
+            .parent_of(model)
+            .map(|parent| world.world_transform(parent))
+            .unwrap_or(Mat4::IDENTITY);
+        let shown = match self.transform_space {
+            TransformSpace::Local => before,
+            TransformSpace::World => parent * before,
+        };
+        let (mut scale, mut rotation, mut translation) = shown.to_scale_rotation_translation();
+        let mut euler = match self.euler_angles.get(&model) {
+            Some((cached, euler)) if cached.dot(rotation).abs() > 1.0 - 1e-6 => *euler,
+            _ => {
+                let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+                Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees())
+            }
+        };
+        let mut quat = rotation;
+
+        let drag_row = |ui: &mut Ui, label: &str, values: &mut [f32], speed: f64, range: std::ops::RangeInclusive<f32>| {
+            ui.horizontal(|ui| {
+                ui.label(label);
+                values
+                    .iter_mut()
+                    .zip(["X: ", "Y: ", "Z: ", "W: "])
+                    .map(|(value, prefix)| {
+                        ui.add(egui::DragValue::new(value).speed(speed).range(range.clone()).prefix(prefix))
+                            .changed()
+                    })
+                    .fold(false, |changed, c| changed | c)
+            })
+            .inner
+        };
+        let mut changed = drag_row(ui, "Position", translation.as_mut(), 0.01, f32::MIN..=f32::MAX);
+        let euler_changed = drag_row(ui, "Rotation", euler.as_mut(), 0.5, f32::MIN..=f32::MAX);
+        let quat_changed = {
+            let mut components = quat.to_array();
+            let changed = drag_row(ui, "Quaternion", &mut components, 0.01, -1.0..=1.0);
+            quat = Quat::from_array(components);
+            changed
+        };
+        // negative scale mirrors the model, but zero scale can't be decomposed again
+        let previous_scale = scale;
+        drag_row(ui, "Scale", scale.as_mut(), 0.01, f32::MIN..=f32::MAX);
+        for (value, previous) in scale.as_mut().iter_mut().zip(previous_scale.to_array()) {
+            if value.abs() < Self::MIN_SCALE {
+                *value = previous;
+            }
+        }
+        changed |= scale != previous_scale;
+
+        if quat_changed && quat.length_squared() > 0.0 {
+            rotation = quat.normalize();
+            let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+            euler = Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees());
+        } else if euler_changed {
+            rotation = Quat::from_euler(EulerRot::XYZ, euler.x.to_radians(), euler.y.to_radians(), euler.z.to_radians());
+        }
+        if !(changed || euler_changed || quat_changed) {
+            return;
+        }
+        self.euler_angles.insert(model, (rotation, euler));
+
+        let edited = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+        let after = match self.transform_space {
+            TransformSpace::Local => edited,
+            TransformSpace::World => parent.inverse() * edited,
+        };
+        self.cmd_sender
+            .send(Command::Edit(Edit::Transform { model, before, after }))
+            .unwrap();
+    }
+
+    // Settings of the model's particle emitter. Every change is sent as an edit of the whole settings.
+    fn emitter_div(&self, ui: &mut egui::Ui, model: ModelId, before: &EmitterSettings, material_manager: &MaterialManager) {
+        let mut settings = before.clone();
+        ui.collapsing("Particle emitter", |ui| {
+            let drag_vec3 = |ui: &mut Ui, label: &str, value: &mut Vec3| {
+                ui.horizontal(|ui| {
+                    ui.label(label);
+                    ui.add(egui::DragValue::new(&mut value.x).speed(0.01).prefix("X: "));
+                    ui.add(egui::DragValue::new(&mut value.y).speed(0.01).prefix("Y: "));
+                    ui.add(egui::DragValue::new(&mut value.z).speed(0.01).prefix("Z: "));
+                });
+            };
+            ui.horizontal(|ui| {
+                ui.label("Max particles");
+                ui.add(egui::DragValue::new(&mut settings.max_particles).range(1..=1 << 20));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Spawn rate");
+                ui.add(
+                    egui::DragValue::new(&mut settings.spawn_rate)
+                        .speed(1.0)
+                        .range(0.0..=f32::MAX)
+                        .suffix("/s"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("Lifetime");
+                ui.add(
+                    egui::DragValue::new(&mut settings.lifetime)
+                        .speed(0.01)
+                        .range(0.01..=f32::MAX)
+                        .suffix("s"),
+                );
+            });
+            drag_vec3(ui, "Velocity", &mut settings.velocity);
+            ui.horizontal(|ui| {
+                ui.label("Spread");
+                ui.add(egui::DragValue::new(&mut settings.spread).speed(0.01).range(0.0..=f32::MAX));
+            });
+            drag_vec3(ui, "Gravity", &mut settings.gravity);
+            ui.label("Color over lifetime");
+            Self::curve_editor(ui, "Color curve", &mut settings.color, |ui, color| {
+                let mut rgba = color.to_array();
+                ui.color_edit_button_rgba_unmultiplied(&mut rgba);
+                *color = Vec4::from_array(rgba);
+            });
+            ui.label("Size over lifetime");
+            Self::curve_editor(ui, "Size curve", &mut settings.size, |ui, size| {
+                ui.add(egui::DragValue::new(size).speed(0.001).range(0.0..=f32::MAX));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Flipbook");
+                ui.add(egui::DragValue::new(&mut settings.flipbook.0).range(1..=64).prefix("Columns: "));
+                ui.add(egui::DragValue::new(&mut settings.flipbook.1).range(1..=64).prefix("Rows: "));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Blend");
+                egui::ComboBox::from_id_source("Particle blend")
+                    .selected_text(settings.blend.name())
+                    .show_ui(ui, |ui| {
+                        for blend in ParticleBlend::ALL {
+                            ui.selectable_value(&mut settings.blend, blend, blend.name());
+                        }
                     });
-                },
-                |_v| {
-                    ctx.clone().immediate_submit(Box::new(|ctx| {
-                        world
-                            .borrow_mut()
-                            .update_transforms(model, glam::Mat4::IDENTITY, light_manager, ctx)
-                    }));
-                }
-            );
-            ui.label("Meshes");
-            let children = {
-                let mut world = world.borrow_mut();
-
-                let model = world.models.get_mut(&model).unwrap();
-                for (i, mesh) in model.meshes.iter_mut().enumerate() {
-                    self.mesh_div(ui, mesh, format!("Mesh {}", i), material_manager.clone());
+            });
+            ui.horizontal(|ui| {
+                ui.label("Material");
+                let selected = material_manager
+                    .get_material(settings.material)
+                    .and_then(|material| material.label.clone())
+                    .unwrap_or("Untitled".into());
+                egui::ComboBox::from_id_source("Particle material")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for (mid, mlabel, _) in material_manager.iter_materials() {
+                            ui.selectable_value(&mut settings.material, mid, mlabel.clone().unwrap_or("Untitled".into()));
+                        }
+                    });
+            });
+        });
+        if settings != *before {
+            self.cmd_sender
+                .send(Command::Edit(Edit::Emitter {
+                    model,
+                    before: Some(before.clone()),
+                    after: Some(settings),
+                }))
+                .unwrap();
+        }
+    }
+
+    // Keyframes of a curve over the particle lifetime, one row each. Keys can't be dragged past their neighbours, so
+    // they stay in order.
+    fn curve_editor<T: CurveValue>(ui: &mut Ui, id: &str, keys: &mut Vec<(f32, T)>, value_editor: impl Fn(&mut Ui, &mut T)) {
+        let mut removed = None;
+        egui::Grid::new(id).num_columns(3).show(ui, |ui| {
+            for i in 0..keys.len() {
+                let min = if i == 0 { 0.0 } else { keys[i - 1].0 };
+                let max = keys.get(i + 1).map_or(1.0, |(time, _)| *time);
+                let (time, value) = &mut keys[i];
+                ui.add(
+                    egui::DragValue::new(time)
+                        .speed(0.01)
+                        .range(min..=max)
+                        .custom_formatter(|fraction, _| format!("{:.0}%", fraction * 100.0))
+                        .custom_parser(|text| text.trim_end_matches('%').parse::<f64>().ok().map(|percent| percent / 100.0)),
+                );
+                value_editor(ui, value);
+                if ui.add_enabled(keys.len() > 1, egui::Button::new("Remove")).clicked() {
+                    removed = Some(i);
                 }
-                model.children.clone()
-            };
-            for child in children {
-                self.model_div(ui, child, world.clone(), material_manager.clone(), light_manager, ctx.clone());
+                ui.end_row();
             }
         });
+        if let Some(i) = removed {
+            keys.remove(i);
+        }
+        // the new key goes into the widest gap, where it doesn't change the curve until it's edited
+        if ui.button("Add key").clicked() {
+            let bounds = [0.0].into_iter().chain(keys.iter().map(|(time, _)| *time)).chain([1.0]);
+            let (start, end) = bounds
+                .clone()
+                .zip(bounds.skip(1))
+                .max_by(|a, b| (a.1 - a.0).total_cmp(&(b.1 - b.0)))
+                .unwrap();
+            let time = (start + end) / 2.0;
+            let index = keys.partition_point(|(key, _)| *key <= time);
+            keys.insert(index, (time, sample_curve(keys, time)));
+        }
     }
 
     fn mesh_div(
         &self,
         ui: &mut egui::Ui,
-        mesh: &mut crate::scene::mesh::Mesh,
-        name: String,
+        model: ModelId,
+        index: usize,
+        mesh: &crate::scene::mesh::Mesh,
         material_manager: Rc<RefCell<MaterialManager>>,
     ) {
-        ui.collapsing(name, |ui| {
+        ui.collapsing(format!("Mesh {}", index), |ui| {
             ui.label(format!("#V / #I: {} / {}", mesh.vertices.len(), mesh.indices.len()));
 
             ui.horizontal(|ui| {
@@ -522,11 +1051,83 @@ impl Gui {
                     )
                     .show_ui(ui, |ui| {
                         let material_manager = material_manager.borrow();
+                        let mut material = mesh.material;
                         for (mid, mlabel, _) in material_manager.iter_materials() {
-                            ui.selectable_value(&mut mesh.material, mid, mlabel.clone().unwrap_or("Untitled".into()));
+                            ui.selectable_value(&mut material, mid, mlabel.clone().unwrap_or("Untitled".into()));
+                        }
+                        if material != mesh.material {
+                            self.cmd_sender
+                                .send(Command::Edit(Edit::MeshMaterial {
+                                    model,
+                                    mesh: index,
+                                    before: mesh.material,
+                                    after: material,
+                                }))
+                                .unwrap();
                         }
                     });
             });
         });
     }
 }
+
+/// The model's label followed by its id, which tells apart models with the same label.
+fn model_name(model: &Model) -> String {
+    format!("{} ({})", model.label.as_deref().unwrap_or("Untitled"), model.id)
+}
+
+/// A button showing the named icon from the atlas, or `text` if the atlas doesn't have it.
+fn icon_button(ui: &mut Ui, icons: &IconAtlas, name: &str, text: impl Into<egui::WidgetText>, selected: bool) -> egui::Response {
+    let text = text.into();
+    match icons.get(name) {
+        Some(icon) => {
+            let uv = egui::Rect::from_min_max(egui::pos2(icon.uv_min.x, icon.uv_min.y), egui::pos2(icon.uv_max.x, icon.uv_max.y));
+            // user textures are engine textures, referenced by their bindless index
+            let texture = SizedTexture::new(egui::TextureId::User(icon.texture as u64), egui::vec2(16.0, 16.0));
+            ui.add(egui::ImageButton::new(egui::Image::new(texture).uv(uv)).selected(selected))
+                .on_hover_text(text.text().to_string())
+        }
+        None => ui.add(egui::Button::new(text).selected(selected)),
+    }
+}
+
+fn primitive_params(ui: &mut Ui, primitive: &mut Primitive) {
+    let length = |ui: &mut Ui, label: &str, value: &mut f32| {
+        ui.add(egui::DragValue::new(value).speed(0.01).range(0.001..=f32::MAX).prefix(label));
+    };
+    let count = |ui: &mut Ui, label: &str, value: &mut u32, range: std::ops::RangeInclusive<u32>| {
+        ui.add(egui::DragValue::new(value).speed(0.1).range(range).prefix(label));
+    };
+    match primitive {
+        Primitive::Cube { size } => length(ui, "Size: ", size),
+        Primitive::UvSphere { radius, segments, rings } => {
+            length(ui, "Radius: ", radius);
+            count(ui, "Segments: ", segments, 3..=256);
+            count(ui, "Rings: ", rings, 2..=256);
+        }
+        Primitive::Icosphere { radius, subdivisions } => {
+            length(ui, "Radius: ", radius);
+            count(ui, "Subdivisions: ", subdivisions, 0..=6);
+        }
+        Primitive::Plane { size, subdivisions } => {
+            length(ui, "Size: ", size);
+            count(ui, "Subdivisions: ", subdivisions, 1..=256);
+        }
+        Primitive::Cylinder { radius, height, segments } | Primitive::Cone { radius, height, segments } => {
+            length(ui, "Radius: ", radius);
+            length(ui, "Height: ", height);
+            count(ui, "Segments: ", segments, 3..=256);
+        }
+        Primitive::Torus {
+            radius,
+            tube_radius,
+            segments,
+            tube_segments,
+        } => {
+            length(ui, "Radius: ", radius);
+            length(ui, "Tube radius: ", tube_radius);
+            count(ui, "Segments: ", segments, 3..=256);
+            count(ui, "Tube segments: ", tube_segments, 3..=256);
+        }
+    }
+}
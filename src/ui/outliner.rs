@@ -0,0 +1,244 @@
+use super::{icon_button, model_name};
+use crate::asset::icon_atlas::IconAtlas;
+use crate::commands::Command;
+use crate::history::Edit;
+use crate::scene::model::{Model, ModelId};
+use crate::selection::Selection;
+use crate::World;
+use egui::collapsing_header::CollapsingState;
+use egui::{Color32, Frame, Id, Key, RichText, Stroke, Ui};
+use hashbrown::HashSet;
+use std::sync::mpsc;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ModelKind {
+    Mesh,
+    Light,
+    Billboard,
+}
+
+impl ModelKind {
+    const ALL: [ModelKind; 3] = [ModelKind::Mesh, ModelKind::Light, ModelKind::Billboard];
+
+    fn of(self, model: &Model) -> bool {
+        match self {
+            ModelKind::Mesh => !model.meshes.is_empty(),
+            ModelKind::Light => model.light.is_some(),
+            ModelKind::Billboard => model.billboard.is_some(),
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ModelKind::Mesh => "Meshes",
+            ModelKind::Light => "Lights",
+            ModelKind::Billboard => "Billboards",
+        }
+    }
+}
+
+/// Tree of all models in the world. Models are selected by clicking, renamed by double-clicking and reparented by
+/// dragging them onto another model.
+#[derive(Default)]
+pub struct Outliner {
+    filter: String,
+    kind: Option<ModelKind>,
+    renaming: Option<(ModelId, String)>,
+}
+
+impl Outliner {
+    pub fn ui(&mut self, ui: &mut Ui, world: &World, selection: &mut Selection, icons: &IconAtlas, cmd_sender: &mpsc::Sender<Command>) {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("Filter by name"));
+            egui::ComboBox::from_id_source("Outliner kind")
+                .selected_text(self.kind.map_or("All", ModelKind::label))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.kind, None, "All");
+                    for kind in ModelKind::ALL {
+                        ui.selectable_value(&mut self.kind, Some(kind), kind.label());
+                    }
+                });
+        });
+        ui.separator();
+
+        let shown = self.shown_models(world);
+        egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+            for model in world.get_toplevel_model_ids() {
+                self.row(ui, world, model, &shown, selection, icons, cmd_sender);
+            }
+        });
+
+        let (_, dropped) = ui.dnd_drop_zone::<ModelId, _>(Frame::none().inner_margin(4.0), |ui| {
+            ui.label(RichText::new("Drop here to move to the top level").weak());
+        });
+        if let Some(dropped) = dropped {
+            Self::reparent(world, *dropped, None, selection, cmd_sender);
+        }
+    }
+
+    fn is_filtered(&self) -> bool {
+        !self.filter.is_empty() || self.kind.is_some()
+    }
+
+    fn matches(&self, model: &Model) -> bool {
+        let filter = self.filter.to_lowercase();
+        model_name(model).to_lowercase().contains(&filter) && self.kind.map_or(true, |kind| kind.of(model))
+    }
+
+    // Models that match the filter along with their ancestors, so matches are still shown in place.
+    fn shown_models(&self, world: &World) -> HashSet<ModelId> {
+        let mut shown = HashSet::new();
+        for model in world.models.values().filter(|model| self.matches(model)) {
+            let mut current = Some(model.id);
+            while let Some(id) = current.filter(|id| shown.insert(*id)) {
+                current = world.parent_of(id);
+            }
+        }
+        shown
+    }
+
+    fn row(
+        &mut self,
+        ui: &mut Ui,
+        world: &World,
+        id: ModelId,
+        shown: &HashSet<ModelId>,
+        selection: &mut Selection,
+        icons: &IconAtlas,
+        cmd_sender: &mpsc::Sender<Command>,
+    ) {
+        if !shown.contains(&id) {
+            return;
+        }
+        let model = &world.models[&id];
+        let header = if model.children.is_empty() {
+            ui.horizontal(|ui| {
+                ui.add_space(ui.spacing().indent);
+                self.header(ui, world, model, selection, icons, cmd_sender)
+            })
+            .inner
+        } else {
+            let mut state = CollapsingState::load_with_default_open(ui.ctx(), ui.make_persistent_id(("Outliner", id)), false);
+            if self.is_filtered() {
+                state.set_open(true);
+            }
+            let header = state.show_header(ui, |ui| self.header(ui, world, model, selection, icons, cmd_sender));
+            let (_, header, _) = header.body(|ui| {
+                for child in model.children.iter() {
+                    self.row(ui, world, *child, shown, selection, icons, cmd_sender);
+                }
+            });
+            header.inner
+        };
+
+        if header.dnd_hover_payload::<ModelId>().is_some() {
+            ui.painter()
+                .rect_stroke(header.rect, 2.0, Stroke::new(1.0, ui.visuals().selection.stroke.color));
+        }
+        if let Some(dropped) = header.dnd_release_payload::<ModelId>() {
+            Self::reparent(world, *dropped, Some(id), selection, cmd_sender);
+        }
+    }
+
+    // Visibility toggle and name of the model. Returns the response covering the row, which models are dropped onto.
+    fn header(
+        &mut self,
+        ui: &mut Ui,
+        world: &World,
+        model: &Model,
+        selection: &mut Selection,
+        icons: &IconAtlas,
+        cmd_sender: &mpsc::Sender<Command>,
+    ) -> egui::Response {
+        let id = model.id;
+        ui.horizontal(|ui| {
+            let eye = if world.is_visible(id) {
+                RichText::new("👁")
+            } else {
+                RichText::new("👁").color(Color32::DARK_GRAY)
+            };
+            if icon_button(ui, icons, "eye", eye, !model.hidden)
+                .on_hover_text("Toggle visibility")
+                .clicked()
+            {
+                cmd_sender
+                    .send(Command::Edit(Edit::Visibility {
+                        model: id,
+                        before: model.hidden,
+                        after: !model.hidden,
+                    }))
+                    .unwrap();
+            }
+
+            if let Some((_, label)) = self.renaming.as_mut().filter(|(renaming, _)| *renaming == id) {
+                let response = ui.add(egui::TextEdit::singleline(label).id(Self::rename_id(id)));
+                if response.lost_focus() {
+                    let (_, label) = self.renaming.take().unwrap();
+                    if !ui.input(|i| i.key_pressed(Key::Escape)) {
+                        let label = (!label.is_empty()).then_some(label);
+                        if label != model.label {
+                            cmd_sender
+                                .send(Command::Edit(Edit::Rename {
+                                    model: id,
+                                    before: model.label.clone(),
+                                    after: label,
+                                }))
+                                .unwrap();
+                        }
+                    }
+                }
+                return;
+            }
+
+            let name = if self.matches(model) {
+                RichText::new(model_name(model))
+            } else {
+                RichText::new(model_name(model)).weak()
+            };
+            let label = ui
+                .dnd_drag_source(Id::new(("Outliner drag", id)), id, |ui| {
+                    ui.selectable_label(selection.contains(id), name)
+                })
+                .inner;
+            if label.double_clicked() {
+                self.renaming = Some((id, model.label.clone().unwrap_or_default()));
+                ui.memory_mut(|memory| memory.request_focus(Self::rename_id(id)));
+            } else if label.clicked() {
+                if ui.input(|i| i.modifiers.command) {
+                    selection.toggle(id);
+                } else {
+                    selection.set(id);
+                }
+            }
+        })
+        .response
+    }
+
+    fn rename_id(model: ModelId) -> Id {
+        Id::new(("Outliner rename", model))
+    }
+
+    // Moves the dropped model under `parent`. If it's selected, the rest of the selection goes along.
+    fn reparent(world: &World, dropped: ModelId, parent: Option<ModelId>, selection: &Selection, cmd_sender: &mpsc::Sender<Command>) {
+        let models = if selection.contains(dropped) {
+            selection.roots(world)
+        } else {
+            vec![dropped]
+        };
+        let mut index = parent.map_or(0, |parent| world.models[&parent].children.len());
+        for model in models {
+            // a model can't be moved into its own subtree
+            if parent.is_some_and(|parent| world.is_ancestor(model, parent)) || world.parent_of(model) == parent {
+                continue;
+            }
+            cmd_sender
+                .send(Command::Edit(Edit::Reparent {
+                    model,
+                    before: world.placement(model),
+                    after: parent.map(|parent| (parent, index)),
+                }))
+                .unwrap();
+            index += 1;
+        }
+    }
+}
@@ -89,6 +89,35 @@ pub mod device_discovery {
         let (graphics, present) = find_queue_families(instance, surface, surface_khr, device);
         graphics.is_some() && present.is_some()
     }
+
+    /// Whether images of the given format can be used as blit source and destination with linear filtering,
+    /// which is what mip generation on upload relies on.
+    pub(crate) fn supports_linear_blit(instance: &Instance, device: vk::PhysicalDevice, format: vk::Format) -> bool {
+        let props = unsafe { instance.get_physical_device_format_properties(device, format) };
+        props.optimal_tiling_features.contains(
+            vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    /// The sample counts the draw and depth images can be rendered with, see `AppSettings::msaa`.
+    pub(crate) fn supported_sample_counts(instance: &Instance, device: vk::PhysicalDevice) -> vk::SampleCountFlags {
+        let limits = unsafe { instance.get_physical_device_properties(device) }.limits;
+        limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts & limits.framebuffer_stencil_sample_counts
+    }
+
+    /// A depth format with a stencil aspect that the device can render to. Not every device supports both of them.
+    pub(crate) fn pick_depth_format(instance: &Instance, device: vk::PhysicalDevice) -> vk::Format {
+        [vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT]
+            .into_iter()
+            .find(|format| {
+                let props = unsafe { instance.get_physical_device_format_properties(device, *format) };
+                props
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            })
+            .expect("No supported depth/stencil format")
+    }
+
     pub(crate) fn find_queue_families(
         instance: &Instance,
         surface: &khr::surface::Instance,
@@ -136,10 +165,10 @@ pub(crate) fn transition_image(
         .new_layout(new_layout)
         .subresource_range(
             vk::ImageSubresourceRange::default()
-                .aspect_mask(if new_layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL {
-                    vk::ImageAspectFlags::DEPTH
-                } else {
-                    vk::ImageAspectFlags::COLOR
+                .aspect_mask(match new_layout {
+                    vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL => vk::ImageAspectFlags::DEPTH,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+                    _ => vk::ImageAspectFlags::COLOR,
                 })
                 .level_count(vk::REMAINING_MIP_LEVELS)
                 .layer_count(vk::REMAINING_ARRAY_LAYERS),
@@ -152,6 +181,57 @@ pub(crate) fn transition_image(
     unsafe { device.cmd_pipeline_barrier2(cmd, &dependency_info) }
 }
 
+/// Like `transition_image`, but only transitions a single mip level of a color image.
+pub(crate) fn transition_image_mip(
+    device: &Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    mip_level: u32,
+    current_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let image_barrier = vk::ImageMemoryBarrier2::default()
+        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+        .old_layout(current_layout)
+        .new_layout(new_layout)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(mip_level)
+                .level_count(1)
+                .layer_count(vk::REMAINING_ARRAY_LAYERS),
+        )
+        .image(image);
+
+    let binding = [image_barrier];
+    let dependency_info = vk::DependencyInfoKHR::default().image_memory_barriers(&binding);
+
+    unsafe { device.cmd_pipeline_barrier2(cmd, &dependency_info) }
+}
+
+/// Makes writes in `src_stage` visible to reads or writes in `dst_stage`, for buffers that aren't tied to an image layout.
+pub(crate) fn memory_barrier(
+    device: &Device,
+    cmd: vk::CommandBuffer,
+    src_stage: vk::PipelineStageFlags2,
+    src_access: vk::AccessFlags2,
+    dst_stage: vk::PipelineStageFlags2,
+    dst_access: vk::AccessFlags2,
+) {
+    let barrier = vk::MemoryBarrier2::default()
+        .src_stage_mask(src_stage)
+        .src_access_mask(src_access)
+        .dst_stage_mask(dst_stage)
+        .dst_access_mask(dst_access);
+    let binding = [barrier];
+    let dependency_info = vk::DependencyInfoKHR::default().memory_barriers(&binding);
+
+    unsafe { device.cmd_pipeline_barrier2(cmd, &dependency_info) }
+}
+
 pub(crate) fn copy_image_to_image(
     device: &Device,
     cmd: vk::CommandBuffer,
@@ -204,6 +284,38 @@ pub(crate) fn copy_image_to_image(
     unsafe { device.cmd_blit_image2(cmd, &blit_info) };
 }
 
+/// Averages the samples of a multisampled color attachment into a single sampled one. Both have to be in
+/// `COLOR_ATTACHMENT_OPTIMAL`, the contents of the source are undefined afterwards.
+pub(crate) fn resolve_image(
+    device: &Device,
+    cmd: vk::CommandBuffer,
+    source: vk::ImageView,
+    destination: vk::ImageView,
+    extent: vk::Extent2D,
+) {
+    // resolving is part of ending a rendering, so this is one without any draws
+    let color_attachment = vk::RenderingAttachmentInfo::default()
+        .image_view(source)
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::LOAD)
+        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+        .resolve_image_view(destination)
+        .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    let color_attachments = [color_attachment];
+    let render_info = vk::RenderingInfo::default()
+        .color_attachments(&color_attachments)
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        })
+        .layer_count(1);
+    unsafe {
+        device.cmd_begin_rendering(cmd, &render_info);
+        device.cmd_end_rendering(cmd);
+    }
+}
+
 pub fn load_shader_module(device: &Device, code: &[u8]) -> Result<vk::ShaderModule, Box<dyn Error>> {
     // copy vec<u8> into vec<u32> where each u32 is a 4 byte chunk of u8s
     let code: Vec<u32> = code